@@ -0,0 +1,122 @@
+//! Importing the sync group of another gsb repository into this one, the
+//! reverse of [`crate::split`].
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use config_file::*;
+use log::{info, warn};
+
+use crate::{
+    cli::json_output,
+    commit::commit,
+    config::{save_config, Config, Getable, PlaceholderPolicy, CONFIG, CONFIG_NAME},
+    git_command::{git, REPO_PATH, SYNC_BRANCH},
+    manifest::{Devices, DEVICES_NAME},
+    utils::copy_item,
+};
+
+/// Import the items of the sync group of the repository at `other`, with their
+/// history if `history`. Fails without changing anything if an item of
+/// `other` collides with one of this repository, by path in the repository or
+/// by path on this device.
+pub async fn absorb(other: &Path, history: bool) -> Result<()> {
+    let content = std::fs::read_to_string(other.join(CONFIG_NAME))
+        .with_context(|| format!("`{:?}` is not a gsb repository", other))?;
+    let theirs: Config = toml::from_str(&content)?;
+    let ours = CONFIG.read().unwrap().clone();
+    check_collisions(&ours, &theirs)?;
+    let items = theirs.sync_group.0.keys().cloned().collect::<Vec<_>>();
+
+    if history {
+        let other = other.to_str().context("the repository path is not valid UTF-8")?;
+        git(["fetch", other, SYNC_BRANCH])?;
+        // The only paths in both histories are the files of gsb itself, which
+        // are ours and merged below.
+        git([
+            "merge",
+            "--allow-unrelated-histories",
+            "--no-commit",
+            "--strategy-option",
+            "ours",
+            "FETCH_HEAD",
+        ])?;
+    } else {
+        for item in items.iter() {
+            copy_item(&other.join(item), &REPO_PATH.join(item), PlaceholderPolicy::Hydrate)
+                .await?;
+        }
+    }
+
+    {
+        let mut config = CONFIG.write().unwrap();
+        config.sync_group.0.extend(theirs.sync_group.0);
+        for (item, service) in theirs.services {
+            config.services.entry(item).or_insert(service);
+        }
+        for (name, hook) in theirs.hooks {
+            if config.hooks.contains_key(&name) {
+                warn!("hook `{}` exists in both repositories, keeping ours", name);
+                continue;
+            }
+            config.hooks.insert(name, hook);
+        }
+    }
+    save_config()?;
+    merge_devices(other)?;
+
+    git(["add", "--all"])?;
+    commit("absorb", items.iter().map(PathBuf::as_path))?;
+    info!("absorbed {} items from `{:?}`", items.len(), other);
+    if json_output() {
+        println!("{}", serde_json::json!({ "absorbed": items, "from": other }));
+    }
+    Ok(())
+}
+
+fn check_collisions(ours: &Config, theirs: &Config) -> Result<()> {
+    let mut collisions = Vec::new();
+    for (item, info) in theirs.sync_group.0.iter() {
+        if ours.sync_group.0.contains_key(item) || REPO_PATH.join(item).exists() {
+            collisions.push(format!("`{}` in the repository", item.display()));
+        }
+        let Some(on_device) = info.get_on_device() else {
+            continue;
+        };
+        if ours
+            .sync_group
+            .0
+            .values()
+            .filter_map(|info| info.get_on_device())
+            .any(|path| path.starts_with(on_device) || on_device.starts_with(path))
+        {
+            collisions.push(format!("`{}` on this device", on_device.display()));
+        }
+    }
+    if !collisions.is_empty() {
+        bail!("items collide: {}", collisions.join(", "));
+    }
+    Ok(())
+}
+
+/// Add the public keys of the devices only known to `other`.
+fn merge_devices(other: &Path) -> Result<()> {
+    let Ok(theirs) = Devices::from_config_file(other.join(DEVICES_NAME)) else {
+        return Ok(());
+    };
+    let path = REPO_PATH.join(DEVICES_NAME);
+    let mut ours = Devices::from_config_file(&path).unwrap_or_default();
+    for (device, info) in theirs.devices {
+        match ours.devices.get(&device) {
+            Some(known) if known.public_key != info.public_key => {
+                warn!("device `{}` has another key in `{:?}`, keeping ours", device, other)
+            }
+            Some(_) => {}
+            None => {
+                ours.devices.insert(device, info);
+            }
+        }
+    }
+    ours.to_config_file(path)?;
+    Ok(())
+}
@@ -0,0 +1,319 @@
+//! The cold storage of the repository: items of the sync group untouched for
+//! `archive_after` are compressed into `.archive/<item>.tar.gz` and skipped by
+//! collect, which keeps routine runs fast as the repository ages. They are
+//! rehydrated when a restore needs them or when they are touched again.
+//!
+//! Items with `archive` set are packed instead at every collect, into
+//! `<item>.<format>` next to where they would be.
+
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{ensure, Context, Result};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use log::info;
+
+use crate::{
+    config::{ArchiveFormat, Config},
+    git_command::REPO_PATH,
+    state::state_dir,
+    utils::CopyStats,
+};
+
+pub const ARCHIVE_DIR: &str = ".archive";
+const ARCHIVE_EXT: &str = ".tar.gz";
+
+/// The archive of `item`, relative to the repository.
+pub fn archive_path(item: &Path) -> PathBuf {
+    let mut name = item.as_os_str().to_owned();
+    name.push(ARCHIVE_EXT);
+    Path::new(ARCHIVE_DIR).join(name)
+}
+
+pub fn is_archived(item: &Path) -> bool {
+    REPO_PATH.join(archive_path(item)).exists()
+}
+
+/// The item archived at `path`, relative to the repository, if it is an
+/// archive.
+pub fn item_of_archive(path: &Path) -> Option<PathBuf> {
+    let item = path.strip_prefix(ARCHIVE_DIR).ok()?;
+    let item = item.to_str()?.strip_suffix(ARCHIVE_EXT)?;
+    Some(PathBuf::from(item))
+}
+
+/// The name of the entry of `item` in its archive.
+fn entry_name(item: &Path) -> Result<&std::ffi::OsStr> {
+    item.file_name()
+        .with_context(|| format!("`{}` has no name to archive", item.display()))
+}
+
+/// Compress `item` in the repository into its archive and remove it.
+pub fn archive_item(item: &Path) -> Result<()> {
+    let source = REPO_PATH.join(item);
+    let archive = REPO_PATH.join(archive_path(item));
+    std::fs::create_dir_all(archive.parent().unwrap())?;
+    let mut builder = tar::Builder::new(GzEncoder::new(
+        File::create(&archive)?,
+        Compression::default(),
+    ));
+    if source.is_dir() {
+        builder.append_dir_all(entry_name(item)?, &source)?;
+    } else {
+        builder.append_path_with_name(&source, entry_name(item)?)?;
+    }
+    builder.into_inner()?.finish()?;
+    if source.is_dir() {
+        std::fs::remove_dir_all(&source)?;
+    } else {
+        std::fs::remove_file(&source)?;
+    }
+    info!("archived `{}`", item.display());
+    Ok(())
+}
+
+/// Extract the archive of `item` back into the repository and remove it.
+pub fn unarchive_item(item: &Path) -> Result<()> {
+    let archive = REPO_PATH.join(archive_path(item));
+    let parent = REPO_PATH.join(item.parent().unwrap_or(Path::new("")));
+    std::fs::create_dir_all(&parent)?;
+    tar::Archive::new(GzDecoder::new(File::open(&archive)?)).unpack(&parent)?;
+    std::fs::remove_file(&archive)?;
+    info!("rehydrated `{}`", item.display());
+    Ok(())
+}
+
+/// Extract the archive of `item` into a temporary directory of `.gsb/`,
+/// returning the extracted item. The directory is removed with the returned
+/// guard.
+pub fn extract(item: &Path) -> Result<(PathBuf, Extracted)> {
+    let archive = REPO_PATH.join(archive_path(item));
+    let dir = state_dir()?.join("rehydrate").join(item);
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir)?;
+    }
+    std::fs::create_dir_all(&dir)?;
+    tar::Archive::new(GzDecoder::new(File::open(&archive)?)).unpack(&dir)?;
+    Ok((dir.join(entry_name(item)?), Extracted(dir)))
+}
+
+/// Where the packed `item` is, relative to the repository.
+pub fn packed_path(item: &Path, format: ArchiveFormat) -> PathBuf {
+    let mut name = item.as_os_str().to_owned();
+    name.push(".");
+    name.push(format.extension());
+    PathBuf::from(name)
+}
+
+/// The packed item at `path`, relative to the repository, with its format.
+pub fn packed_item(config: &Config, path: &Path) -> Option<(PathBuf, ArchiveFormat)> {
+    config.sync_group.0.iter().find_map(|(item, info)| {
+        let format = info.archive?;
+        (packed_path(item, format) == path).then(|| (item.clone(), format))
+    })
+}
+
+/// Pack the directory `from` on the device into the packed `item`. The
+/// entries are sorted and have no times or owners, so the pack only changes
+/// with the content.
+pub fn pack(from: &Path, item: &Path, format: ArchiveFormat) -> Result<CopyStats> {
+    pack_into(&REPO_PATH, from, item, format)
+}
+
+/// [`pack`] into the repository at `repo`.
+fn pack_into(repo: &Path, from: &Path, item: &Path, format: ArchiveFormat) -> Result<CopyStats> {
+    ensure!(from.is_dir(), "only directories can be packed, {:?} is not one", from);
+    let mut builder = tar::Builder::new(Vec::new());
+    builder.mode(tar::HeaderMode::Deterministic);
+    let mut stats = CopyStats::default();
+    for entry in walkdir::WalkDir::new(from).sort_by_file_name() {
+        let entry = entry?;
+        let relative = entry.path().strip_prefix(from)?;
+        if relative.as_os_str().is_empty() {
+            continue;
+        }
+        if entry.file_type().is_dir() {
+            builder.append_dir(relative, entry.path())?;
+        } else if entry.file_type().is_file() {
+            builder.append_path_with_name(entry.path(), relative)?;
+            stats.files_copied += 1;
+            stats.bytes_copied += entry.metadata()?.len();
+        }
+    }
+    let tar = builder.into_inner()?;
+    let packed = match format {
+        ArchiveFormat::TarZst => zstd::encode_all(tar.as_slice(), 0)?,
+        ArchiveFormat::TarGz => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&tar)?;
+            encoder.finish()?
+        }
+    };
+
+    let target = repo.join(packed_path(item, format));
+    if std::fs::read(&target).is_ok_and(|current| current == packed) {
+        return Ok(CopyStats {
+            files_skipped: stats.files_copied,
+            ..Default::default()
+        });
+    }
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&target, packed)?;
+    // The files collected before the item was packed.
+    let unpacked = repo.join(item);
+    if unpacked.is_dir() {
+        std::fs::remove_dir_all(&unpacked)?;
+    }
+    Ok(stats)
+}
+
+/// Unpack the packed `item` into a temporary directory of `.gsb/`, like
+/// [`extract`].
+pub fn unpack(item: &Path, format: ArchiveFormat) -> Result<(PathBuf, Extracted)> {
+    unpack_from(&REPO_PATH, &state_dir()?.join("unpack"), item, format)
+}
+
+/// [`unpack`] from the repository at `repo` into a directory of `scratch`.
+fn unpack_from(
+    repo: &Path,
+    scratch: &Path,
+    item: &Path,
+    format: ArchiveFormat,
+) -> Result<(PathBuf, Extracted)> {
+    let file = File::open(repo.join(packed_path(item, format)))?;
+    let dir = scratch.join(item);
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir)?;
+    }
+    std::fs::create_dir_all(&dir)?;
+    match format {
+        ArchiveFormat::TarZst => tar::Archive::new(zstd::Decoder::new(file)?).unpack(&dir)?,
+        ArchiveFormat::TarGz => tar::Archive::new(GzDecoder::new(file)).unpack(&dir)?,
+    }
+    Ok((dir.clone(), Extracted(dir)))
+}
+
+/// A directory from [`extract`], removed when dropped.
+pub struct Extracted(pub PathBuf);
+
+impl Drop for Extracted {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Whether nothing in `path` was modified within `age`. Only the metadata is
+/// read, so this is much cheaper than collecting `path`.
+pub fn untouched_for(path: &Path, age: Duration) -> bool {
+    let Some(since) = SystemTime::now().checked_sub(age) else {
+        return false;
+    };
+    walkdir::WalkDir::new(path).into_iter().all(|entry| {
+        entry
+            .ok()
+            .and_then(|entry| entry.metadata().ok())
+            .and_then(|meta| meta.modified().ok())
+            .is_some_and(|modified| modified < since)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_dir;
+
+    /// The files of `dir` with their content, and the directories as `None`.
+    fn tree(dir: &Path) -> Vec<(PathBuf, Option<Vec<u8>>)> {
+        walkdir::WalkDir::new(dir)
+            .sort_by_file_name()
+            .into_iter()
+            .map(|entry| entry.unwrap())
+            .map(|entry| {
+                let relative = entry.path().strip_prefix(dir).unwrap().to_path_buf();
+                let is_file = entry.file_type().is_file();
+                (relative, is_file.then(|| std::fs::read(entry.path()).unwrap()))
+            })
+            .collect()
+    }
+
+    /// A browser profile like directory at `dir`.
+    fn profile(dir: &Path) {
+        std::fs::create_dir_all(dir.join("cache/empty")).unwrap();
+        std::fs::write(dir.join("prefs.js"), "user_pref(\"a\", 1);").unwrap();
+        std::fs::write(dir.join("cache/blob"), [0, 159, 146, 150, 255]).unwrap();
+        std::fs::write(dir.join("cache/large"), "data ".repeat(100_000)).unwrap();
+    }
+
+    #[test]
+    fn test_pack_round_trip() {
+        for format in [ArchiveFormat::TarZst, ArchiveFormat::TarGz] {
+            let dir = test_dir(&format!("pack_{}", format.extension()));
+            let (repo, scratch) = (dir.join("repo"), dir.join("scratch"));
+            let from = dir.join("profile");
+            let item = Path::new("browser/profile");
+            profile(&from);
+
+            let stats = pack_into(&repo, &from, item, format).unwrap();
+            assert_eq!((stats.files_copied, stats.files_skipped), (3, 0));
+            let packed = std::fs::read(repo.join(packed_path(item, format))).unwrap();
+            let (unpacked, _guard) = unpack_from(&repo, &scratch, item, format).unwrap();
+            assert_eq!(tree(&unpacked), tree(&from));
+
+            // The same content packs to the same bytes.
+            let stats = pack_into(&repo, &from, item, format).unwrap();
+            assert_eq!((stats.files_copied, stats.files_skipped), (0, 3));
+            assert_eq!(std::fs::read(repo.join(packed_path(item, format))).unwrap(), packed);
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_pack_collected_before() {
+        let dir = test_dir("pack_collected_before");
+        let (repo, scratch, from) = (dir.join("repo"), dir.join("scratch"), dir.join("profile"));
+        let item = Path::new("profile");
+        profile(&from);
+        // Collected file by file before `archive` was set.
+        profile(&repo.join(item));
+
+        pack_into(&repo, &from, item, ArchiveFormat::TarZst).unwrap();
+        assert!(!repo.join(item).exists());
+        let (unpacked, _guard) = unpack_from(&repo, &scratch, item, ArchiveFormat::TarZst).unwrap();
+        assert_eq!(tree(&unpacked), tree(&from));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pack_executable() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = test_dir("pack_executable");
+        let (repo, scratch, from) = (dir.join("repo"), dir.join("scratch"), dir.join("bin"));
+        let item = Path::new("bin");
+        std::fs::create_dir_all(&from).unwrap();
+        std::fs::write(from.join("run.sh"), "#!/bin/sh").unwrap();
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o111;
+
+        pack_into(&repo, &from, item, ArchiveFormat::TarZst).unwrap();
+        let (unpacked, _guard) = unpack_from(&repo, &scratch, item, ArchiveFormat::TarZst).unwrap();
+        assert_eq!(mode(&unpacked.join("run.sh")), 0);
+
+        // Only the mode changed, which still makes a new pack.
+        let packed = std::fs::read(repo.join(packed_path(item, ArchiveFormat::TarZst))).unwrap();
+        std::fs::set_permissions(from.join("run.sh"), std::fs::Permissions::from_mode(0o755))
+            .unwrap();
+        let stats = pack_into(&repo, &from, item, ArchiveFormat::TarZst).unwrap();
+        assert_eq!(stats.files_copied, 1);
+        let repacked = std::fs::read(repo.join(packed_path(item, ArchiveFormat::TarZst))).unwrap();
+        assert_ne!(repacked, packed);
+        let (unpacked, _guard) = unpack_from(&repo, &scratch, item, ArchiveFormat::TarZst).unwrap();
+        assert_ne!(mode(&unpacked.join("run.sh")), 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+//! The attic: files removed from the repository by gsb are kept in
+//! `.gsb/attic/<date>/` for `attic_retention`, so removing the wrong item does
+//! not lose the only copy at hand.
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+
+use crate::{
+    cli::json_output,
+    commit::commit,
+    config::CONFIG,
+    git_command::{git, REPO_PATH},
+    state::{state_dir, STATE_DIR},
+    utils::item_files,
+};
+
+const ATTIC_DIR: &str = "attic";
+const DEFAULT_RETENTION: &str = "30d";
+
+fn today() -> String {
+    humantime::format_rfc3339_seconds(SystemTime::now()).to_string()[..10].to_string()
+}
+
+/// Copy `item` of the repository into the attic of today, before it is
+/// removed, then drop the days older than `attic_retention`.
+pub fn stash(item: &Path) -> Result<()> {
+    let dir = state_dir()?.join(ATTIC_DIR);
+    let stashed = dir.join(today()).join(item);
+    copy_tree(&REPO_PATH.join(item), &stashed)?;
+    info!("kept `{}` in {:?}", item.display(), stashed);
+    if let Err(e) = purge(&dir) {
+        warn!("failed to clean up the attic: {:#}", e);
+    }
+    Ok(())
+}
+
+fn copy_tree(from: &Path, to: &Path) -> Result<()> {
+    for (source, target) in item_files(from, to)? {
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(&source, &target)?;
+    }
+    Ok(())
+}
+
+fn purge(dir: &Path) -> Result<()> {
+    let retention = CONFIG.read().unwrap().attic_retention.clone();
+    let retention = retention.as_deref().unwrap_or(DEFAULT_RETENTION);
+    let retention = humantime::parse_duration(retention).context("invalid `attic_retention`")?;
+    let oldest = SystemTime::now()
+        .checked_sub(retention)
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+    for day in days(dir)? {
+        let expired = humantime::parse_rfc3339(&format!("{}T00:00:00Z", day))
+            .is_ok_and(|time| time + Duration::from_secs(24 * 3600) < oldest);
+        if expired {
+            std::fs::remove_dir_all(dir.join(&day))?;
+            info!("dropped the attic of {}", day);
+        }
+    }
+    Ok(())
+}
+
+/// The days in the attic, oldest first.
+fn days(dir: &Path) -> Result<Vec<String>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut days = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    days.sort();
+    Ok(days)
+}
+
+/// List the files in the attic by day.
+pub fn list() -> Result<()> {
+    let dir = REPO_PATH.join(STATE_DIR).join(ATTIC_DIR);
+    let mut files = Vec::new();
+    for day in days(&dir)? {
+        for (source, _) in item_files(&dir.join(&day), Path::new(""))? {
+            let path = source.strip_prefix(dir.join(&day))?.to_path_buf();
+            files.push((day.clone(), path));
+        }
+    }
+    if json_output() {
+        let files = files
+            .iter()
+            .map(|(day, path)| serde_json::json!({ "date": day, "path": path }))
+            .collect::<Vec<_>>();
+        println!("{}", serde_json::Value::Array(files));
+        return Ok(());
+    }
+    for (day, path) in files {
+        println!("{}  {}", day, path.display());
+    }
+    Ok(())
+}
+
+/// Put `path` back from the attic of `date`, or of the last day having it, to
+/// `to`, or to where it was in the repository and commit it.
+pub fn restore(path: &Path, date: Option<&str>, to: Option<&Path>) -> Result<()> {
+    let dir = REPO_PATH.join(STATE_DIR).join(ATTIC_DIR);
+    let day = match date {
+        Some(date) => date.to_string(),
+        None => days(&dir)?
+            .into_iter()
+            .rev()
+            .find(|day| dir.join(day).join(path).exists())
+            .with_context(|| format!("`{}` is not in the attic", path.display()))?,
+    };
+    let from = dir.join(&day).join(path);
+    anyhow::ensure!(from.exists(), "`{}` is not in the attic of {}", path.display(), day);
+    let target = to.map_or_else(|| REPO_PATH.join(path), PathBuf::from);
+    copy_tree(&from, &target)?;
+    info!("restored `{}` of {} to {:?}", path.display(), day, target);
+    if to.is_none() {
+        git(["add", "--", &path.to_string_lossy()])?;
+        commit("attic", [path])?;
+    }
+    Ok(())
+}
@@ -1,28 +1,123 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Instant};
 
-use anyhow::Result;
-use die_exit::Die;
+use anyhow::{ensure, Context, Result};
+use log::{info, warn};
 
 use crate::{
-    config::CONFIG,
-    git_command::{git, BACKUP_BRANCH, REMOTE_NAME, SYNC_BRANCH},
+    commit::commit,
+    config::{HookPhase, CONFIG},
+    git_command::{git, git_checked, push_branch, BACKUP_BRANCH, REMOTE_NAME, REPO_PATH},
+    hooks::run_hooks,
+    lfs,
+    limits,
+    mounts::hardlink_usable,
+    profile::phase,
+    progress::overall_bar,
+    report::Report,
+    state::save_state,
+    utils::{copy_item, run_items, CopyStats},
+    workspace::resolve,
 };
 
+/// Collect the backup group onto this device's backup branch and push it,
+/// then switch back to the branch checked out before, whatever happened.
+#[cfg_attr(feature = "profiling", tracing::instrument(skip_all))]
 pub async fn backup() -> Result<()> {
-    git(["switch", &BACKUP_BRANCH])?;
-    let backup_list = &CONFIG.read().unwrap().backup_group.0;
-    let result = async_scoped::TokioScope::scope_and_block(move |scope| {
-        for path in backup_list.keys() {
-            scope.spawn(backup_file(path));
+    let original = git_checked(["branch", "--show-current"])?;
+    let original = original.trim();
+    ensure!(!original.is_empty(), "`HEAD` is detached, check out a branch to back up from");
+    let branch = format!("refs/heads/{}", BACKUP_BRANCH.as_str());
+    if git_checked(["rev-parse", "--verify", "--quiet", &branch]).is_ok() {
+        git_checked(["switch", &BACKUP_BRANCH])?;
+    } else {
+        info!("creating the backup branch `{}`", BACKUP_BRANCH.as_str());
+        git_checked(["switch", "-c", &BACKUP_BRANCH])?;
+    }
+    let result = backup_on_branch().await;
+    let switched = git_checked(["switch", original])
+        .with_context(|| format!("failed to switch back to `{}`", original));
+    match (result, switched) {
+        (Err(e), Err(switch_error)) => {
+            warn!("{:#}", switch_error);
+            Err(e)
+        }
+        (result, switched) => result.and(switched.map(|_| ())),
+    }
+}
+
+async fn backup_on_branch() -> Result<()> {
+    run_hooks(HookPhase::PreCollect, &[])?;
+    let items = {
+        let config = CONFIG.read().unwrap();
+        let selected = config.backup_group.0.iter();
+        let selected = selected.filter(|(_, info)| config.selects(&info.profiles, &info.tags));
+        selected.map(|(item, _)| item.clone()).collect::<Vec<_>>()
+    };
+    let collect = phase("collect");
+    let bar = overall_bar("backup", items.len() as u64);
+    let jobs = items.iter().map(|path| {
+        let bar = &bar;
+        async move {
+            let started = Instant::now();
+            let result = backup_file(path).await;
+            bar.inc(1);
+            (path.clone(), result, started.elapsed())
         }
     });
+    let result = run_items(jobs.collect());
+    bar.finish_and_clear();
 
-    result.1.into_iter().flatten().collect::<Result<()>>()?;
+    let mut report = Report::new("backup");
+    report.add_all(result);
+    save_state()?;
+    drop(collect);
+
+    let commit_phase = phase("commit");
+    let head = git(["rev-parse", "HEAD"])?;
+    lfs::ensure_attributes()?;
     git(["add", "."])?;
-    git(["push", REMOTE_NAME, SYNC_BRANCH])?;
-    Ok(())
+    commit("backup", items.iter().map(PathBuf::as_path))?;
+    drop(commit_phase);
+    let _push = phase("push");
+    push_branch(REMOTE_NAME, &BACKUP_BRANCH).await?;
+    let committed = git(["diff", "--name-only", head.trim(), "HEAD"])?;
+    run_hooks(HookPhase::PostCollect, &committed.lines().collect::<Vec<_>>())?;
+    report.finish()
 }
 
-async fn backup_file(path: &PathBuf) -> Result<()> {
-    todo!()
+#[cfg_attr(feature = "profiling", tracing::instrument(skip_all))]
+async fn backup_file(path: &PathBuf) -> Result<CopyStats> {
+    let info = CONFIG
+        .read()
+        .unwrap()
+        .backup_group
+        .0
+        .get(path)
+        .with_context(|| format!("`{:?}` not found in config", path))?
+        .clone();
+    if let Some(workspace) = info.workspace.as_deref() {
+        let mut stats = CopyStats::default();
+        for (from, relative) in resolve(workspace, &info)? {
+            limits::check(&from, path, info.limits)?;
+            let to = REPO_PATH.join(path).join(relative);
+            stats += copy_item(&from, &to, info.placeholder).await?;
+        }
+        return Ok(stats);
+    }
+    if info.is_hardlink && hardlink_usable(&info.path_on_device, &REPO_PATH.join(path)) {
+        return Ok(CopyStats::default());
+    }
+    ensure!(
+        info.path_on_device.exists(),
+        "`{:?}` does not exist on {}",
+        info.path_on_device,
+        CONFIG.read().unwrap().this_device()
+    );
+    limits::check(&info.path_on_device, path, info.limits)?;
+    copy_item(
+        &info.path_on_device,
+        &REPO_PATH.join(path),
+        info.placeholder,
+    )
+    .await
 }
@@ -0,0 +1,112 @@
+//! `gsb batch`: many commands in one process, sharing the repository, the
+//! config and the state.
+
+use std::io::Read;
+
+use anyhow::{anyhow, ensure, Context, Result};
+use clap::Parser;
+use log::{error, info};
+use serde::Deserialize;
+
+use crate::cli::{Cli, SubCommand};
+
+/// A command of a JSON batch.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Command {
+    /// A command line like `track ~/.bashrc --yes`.
+    Line(String),
+    /// The arguments, without `gsb`.
+    Args(Vec<String>),
+}
+
+/// The commands of `input`: a JSON array, or one command line per line.
+/// Empty lines and lines starting with `#` are skipped, and the commands
+/// taking the repository lock themselves are refused.
+fn parse(input: &str) -> Result<Vec<SubCommand>> {
+    let commands = if input.trim_start().starts_with('[') {
+        serde_json::from_str::<Vec<Command>>(input)
+            .context("invalid JSON batch")?
+            .into_iter()
+            .map(|command| match command {
+                Command::Line(line) => split(&line),
+                Command::Args(args) => Ok(args),
+            })
+            .collect::<Result<Vec<_>>>()?
+    } else {
+        input
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(split)
+            .collect::<Result<Vec<_>>>()?
+    };
+    commands
+        .into_iter()
+        .map(|args| {
+            let line = args.join(" ");
+            let command = Cli::try_parse_from(std::iter::once("gsb".to_string()).chain(args))
+                .map(|cli| cli.command)
+                .with_context(|| format!("invalid command `{}`", line))?;
+            ensure!(!locks_itself(&command), "`{}` cannot run in a batch", line);
+            Ok(command)
+        })
+        .collect()
+}
+
+/// Whether `command` takes the repository lock itself. The batch holds it
+/// already, so it would wait for itself.
+fn locks_itself(command: &SubCommand) -> bool {
+    matches!(
+        command,
+        SubCommand::Init { .. }
+            | SubCommand::Clone { .. }
+            | SubCommand::Daemon { .. }
+            | SubCommand::Watch { .. }
+            | SubCommand::Batch { .. }
+    )
+}
+
+fn split(line: &str) -> Result<Vec<String>> {
+    shlex::split(line).ok_or_else(|| anyhow!("unbalanced quotes in `{}`", line))
+}
+
+/// Run the commands read from stdin in order, stopping at the first failure
+/// unless `keep_going`. Nothing runs if any command is invalid.
+pub async fn batch(keep_going: bool) -> Result<()> {
+    let mut input = String::new();
+    std::io::stdin().read_to_string(&mut input)?;
+    let commands = parse(&input)?;
+    let mut failed = 0;
+    for (i, command) in commands.iter().enumerate() {
+        info!("batch {}/{}: {:?}", i + 1, commands.len(), command);
+        if let Err(e) = crate::run(command).await {
+            if !keep_going {
+                return Err(e.context(format!("command {} of the batch failed", i + 1)));
+            }
+            error!("command {} of the batch failed: {:#}", i + 1, e);
+            failed += 1;
+        }
+    }
+    anyhow::ensure!(failed == 0, "{} of {} commands failed", failed, commands.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let commands = parse("# nightly\ncollect\n\npush\n").unwrap();
+        assert!(matches!(commands[..], [SubCommand::Collect, SubCommand::Push]));
+        let commands = parse(r#"["collect", ["push"]]"#).unwrap();
+        assert_eq!(commands.len(), 2);
+        assert!(parse("collect\nnot-a-command").is_err());
+        let locking = ["init", "clone https://example.com/a.git", "daemon", "watch", "batch"];
+        for locking in locking {
+            let e = parse(&format!("collect\n{}", locking)).unwrap_err();
+            assert!(e.to_string().contains("cannot run in a batch"), "{:#}", e);
+        }
+    }
+}
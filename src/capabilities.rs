@@ -0,0 +1,136 @@
+//! What the filesystems can do, probed once per mount and cached in the
+//! [`crate::state::State`], so the copy logic adjusts to them up front instead
+//! of failing in the middle of an item.
+
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{mounts::mount_of, state::STATE};
+
+/// Serializes the probes, so the parallel items probe each mount only once.
+static PROBING: Mutex<()> = Mutex::new(());
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    pub hardlink: bool,
+    pub reflink: bool,
+    /// The resolution of the mtimes in nanoseconds, e.g. 2 seconds on FAT.
+    pub mtime_resolution_ns: u64,
+    /// Whether names differing only in case are different files. Otherwise
+    /// such files of an item overwrite each other, see
+    /// [`crate::utils::copy_item`].
+    pub case_sensitive: bool,
+}
+
+impl Default for Capabilities {
+    /// What is assumed of a filesystem that could not be probed: everything,
+    /// falling back at runtime as before.
+    fn default() -> Self {
+        Self {
+            hardlink: true,
+            reflink: true,
+            mtime_resolution_ns: 1,
+            case_sensitive: true,
+        }
+    }
+}
+
+/// The mount point of `path`, or its root if the mounts are unknown.
+fn mount_point(path: &Path) -> PathBuf {
+    if let Some(mount) = mount_of(path) {
+        return mount.point.clone();
+    }
+    let path = path
+        .ancestors()
+        .find_map(|ancestor| ancestor.canonicalize().ok())
+        .unwrap_or_else(|| path.to_path_buf());
+    path.ancestors().last().unwrap_or(&path).to_path_buf()
+}
+
+/// The capabilities of the filesystem `path` lives on. Probed in the nearest
+/// existing directory of `path` the first time a mount is seen.
+pub fn capabilities(path: &Path) -> Capabilities {
+    let point = mount_point(path);
+    if let Some(capabilities) = STATE.lock().unwrap().mounts.get(&point) {
+        return *capabilities;
+    }
+    let _probing = PROBING.lock().unwrap();
+    if let Some(capabilities) = STATE.lock().unwrap().mounts.get(&point) {
+        return *capabilities;
+    }
+    let Some(dir) = path.ancestors().find(|ancestor| ancestor.is_dir()) else {
+        return Capabilities::default();
+    };
+    match probe(dir) {
+        Ok(capabilities) => {
+            debug!("capabilities of `{:?}`: {:?}", point, capabilities);
+            STATE.lock().unwrap().mounts.insert(point, capabilities);
+            capabilities
+        }
+        Err(e) => {
+            warn!("failed to probe the filesystem of `{:?}`: {}", dir, e);
+            Capabilities::default()
+        }
+    }
+}
+
+/// Probe the filesystem by creating some files in `dir`.
+fn probe(dir: &Path) -> Result<Capabilities> {
+    let name = format!(".gsb-probe-{}", std::process::id());
+    let file = dir.join(&name);
+    let others = ["hardlink", "reflink"].map(|kind| dir.join(format!("{}-{}", name, kind)));
+    let result = probe_with(&file, &others, &dir.join(name.to_uppercase()));
+    for path in others.iter().chain([&file]) {
+        let _ = std::fs::remove_file(path);
+    }
+    result
+}
+
+fn probe_with(
+    file: &Path,
+    [hardlink, reflink]: &[PathBuf; 2],
+    upper: &Path,
+) -> Result<Capabilities> {
+    std::fs::write(file, "gsb")?;
+    Ok(Capabilities {
+        hardlink: std::fs::hard_link(file, hardlink).is_ok(),
+        reflink: reflink_copy::reflink(file, reflink).is_ok(),
+        mtime_resolution_ns: mtime_resolution(file)?,
+        case_sensitive: !upper.exists(),
+    })
+}
+
+/// Set an mtime with full nanoseconds and see how much of it is kept.
+fn mtime_resolution(file: &Path) -> Result<u64> {
+    let set = UNIX_EPOCH + Duration::new(1_000_000_001, 123_456_789);
+    File::options().write(true).open(file)?.set_modified(set)?;
+    let got = file.metadata()?.modified()?;
+    let diff = set
+        .duration_since(got)
+        .unwrap_or_else(|e| e.duration())
+        .as_nanos() as u64;
+    Ok([1, 1_000, 1_000_000, 1_000_000_000, 2_000_000_000]
+        .into_iter()
+        .find(|resolution| diff < *resolution)
+        .unwrap_or(2_000_000_000))
+}
+
+/// The capabilities of both ends of a copy: a capability only counts if both
+/// filesystems have it.
+pub fn common_capabilities(a: &Path, b: &Path) -> Capabilities {
+    let (a, b) = (capabilities(a), capabilities(b));
+    Capabilities {
+        hardlink: a.hardlink && b.hardlink,
+        reflink: a.reflink && b.reflink,
+        mtime_resolution_ns: a.mtime_resolution_ns.max(b.mtime_resolution_ns),
+        case_sensitive: a.case_sensitive && b.case_sensitive,
+    }
+}
@@ -0,0 +1,221 @@
+//! Items with `chunk = true` are stored file by file as `<file>.chunks/`:
+//! content-defined chunks named by their hash and the list of them, so an
+//! append-mostly file like a mail archive or a database only adds its new
+//! chunks to the history. Restore reassembles them.
+
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use config_file::*;
+use fastcdc::v2020::StreamCDC;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    archive::Extracted,
+    config::Config,
+    git_command::REPO_PATH,
+    state::state_dir,
+    utils::{item_files, CopyStats},
+};
+
+const EXTENSION: &str = ".chunks";
+const LIST_NAME: &str = "chunks.toml";
+const MIN_SIZE: u32 = 16 * 1024;
+const AVG_SIZE: u32 = 64 * 1024;
+const MAX_SIZE: u32 = 256 * 1024;
+
+/// The chunks of a file, in order.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+struct ChunkList {
+    size: u64,
+    /// The names of the chunk files, the BLAKE3 hashes of their content.
+    chunks: Vec<String>,
+}
+
+/// The directory of the chunks of `path`.
+pub fn chunk_dir(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(EXTENSION);
+    PathBuf::from(name)
+}
+
+/// The path the chunk or chunk list at `path` in the repository stands for,
+/// if it belongs to an item with `chunk` set.
+pub fn chunked_file(config: &Config, path: &Path) -> Option<PathBuf> {
+    let original = path
+        .ancestors()
+        .find_map(|dir| dir.to_str()?.strip_suffix(EXTENSION))
+        .map(PathBuf::from)?;
+    let mut items = config.sync_group.0.iter();
+    items
+        .any(|(item, info)| info.chunk && original.starts_with(item))
+        .then_some(original)
+}
+
+/// Whether `path` is a chunk list rather than a chunk.
+pub fn is_chunk_list(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name == LIST_NAME)
+}
+
+/// Chunk the files of the item at `from` into the repository. Only the new
+/// chunks are written and the ones no longer listed are removed.
+pub fn chunk_item(from: &Path, item: &Path) -> Result<CopyStats> {
+    chunk_into(&REPO_PATH, from, item)
+}
+
+/// [`chunk_item`] into the repository at `repo`.
+fn chunk_into(repo: &Path, from: &Path, item: &Path) -> Result<CopyStats> {
+    let mut stats = CopyStats::default();
+    for (source, target) in item_files(from, &repo.join(item))? {
+        // Collected before the item was chunked.
+        if target.is_file() {
+            std::fs::remove_file(&target)?;
+        }
+        let dir = chunk_dir(&target);
+        std::fs::create_dir_all(&dir)?;
+        let mut list = ChunkList::default();
+        for chunk in StreamCDC::new(File::open(&source)?, MIN_SIZE, AVG_SIZE, MAX_SIZE) {
+            let chunk = chunk.with_context(|| format!("failed to chunk {:?}", source))?;
+            let name = blake3::hash(&chunk.data).to_hex().to_string();
+            let path = dir.join(&name);
+            if !path.exists() {
+                std::fs::write(&path, &chunk.data)?;
+            }
+            list.size += chunk.length as u64;
+            list.chunks.push(name);
+        }
+        let list_path = dir.join(LIST_NAME);
+        if ChunkList::from_config_file(&list_path).is_ok_and(|current| current == list) {
+            stats.files_skipped += 1;
+            continue;
+        }
+        list.to_config_file(&list_path)?;
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name != LIST_NAME && !list.chunks.contains(&name) {
+                std::fs::remove_file(entry.path())?;
+            }
+        }
+        stats.files_copied += 1;
+        stats.bytes_copied += list.size;
+    }
+    Ok(stats)
+}
+
+/// Reassemble the chunked `original` (relative to the repository) into a
+/// temporary directory of `.gsb/`, returning the reassembled file. The
+/// directory is removed with the returned guard.
+pub fn reassemble(original: &Path) -> Result<(PathBuf, Extracted)> {
+    reassemble_from(&REPO_PATH, &state_dir()?.join("reassemble"), original)
+}
+
+/// [`reassemble`] from the repository at `repo` into a directory of `scratch`.
+/// Every chunk is checked against the hash it is named by.
+fn reassemble_from(repo: &Path, scratch: &Path, original: &Path) -> Result<(PathBuf, Extracted)> {
+    let chunks = repo.join(chunk_dir(original));
+    let list = ChunkList::from_config_file(chunks.join(LIST_NAME))
+        .with_context(|| format!("no chunk list of `{}`", original.display()))?;
+    let dir = scratch.join(original);
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir)?;
+    }
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(original.file_name().unwrap_or_default());
+    let mut file = File::create(&path)?;
+    let mut size = 0;
+    for name in list.chunks.iter() {
+        let chunk = std::fs::read(chunks.join(name))
+            .with_context(|| format!("missing chunk `{}` of `{}`", name, original.display()))?;
+        if blake3::hash(&chunk).to_hex().as_str() != name {
+            bail!("corrupt chunk `{}` of `{}`", name, original.display());
+        }
+        size += chunk.len() as u64;
+        file.write_all(&chunk)?;
+    }
+    ensure!(size == list.size, "the chunks of `{}` are incomplete", original.display());
+    Ok((path, Extracted(dir)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_dir;
+
+    /// Content that does not repeat, so it is cut into several chunks.
+    fn content(len: usize) -> Vec<u8> {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(b"gsb");
+        let mut content = vec![0; len];
+        hasher.finalize_xof().fill(&mut content);
+        content
+    }
+
+    fn chunks_of(repo: &Path, original: &Path) -> Vec<String> {
+        let mut chunks = std::fs::read_dir(repo.join(chunk_dir(original)))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|name| name != LIST_NAME)
+            .collect::<Vec<_>>();
+        chunks.sort();
+        chunks
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = test_dir("chunk_round_trip");
+        let (repo, scratch) = (dir.join("repo"), dir.join("scratch"));
+        let source = dir.join("mail.mbox");
+        let original = Path::new("mail/mail.mbox");
+        let data = content(1024 * 1024);
+        std::fs::write(&source, &data).unwrap();
+
+        let stats = chunk_into(&repo, &source, original).unwrap();
+        assert_eq!((stats.files_copied, stats.bytes_copied), (1, data.len() as u64));
+        assert!(chunks_of(&repo, original).len() > 1);
+        let (file, _guard) = reassemble_from(&repo, &scratch, original).unwrap();
+        assert_eq!(std::fs::read(file).unwrap(), data);
+
+        let stats = chunk_into(&repo, &source, original).unwrap();
+        assert_eq!((stats.files_copied, stats.files_skipped), (0, 1));
+
+        // Shrinking drops the chunks of the cut end.
+        let before = chunks_of(&repo, original);
+        std::fs::write(&source, &data[..data.len() / 4]).unwrap();
+        chunk_into(&repo, &source, original).unwrap();
+        let after = chunks_of(&repo, original);
+        assert!(after.len() < before.len());
+        let (file, _guard) = reassemble_from(&repo, &scratch, original).unwrap();
+        assert_eq!(std::fs::read(file).unwrap(), &data[..data.len() / 4]);
+
+        std::fs::write(&source, "").unwrap();
+        chunk_into(&repo, &source, original).unwrap();
+        assert!(chunks_of(&repo, original).is_empty());
+        let (file, _guard) = reassemble_from(&repo, &scratch, original).unwrap();
+        assert!(std::fs::read(file).unwrap().is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_chunk() {
+        let dir = test_dir("chunk_corrupt");
+        let (repo, scratch) = (dir.join("repo"), dir.join("scratch"));
+        let source = dir.join("mail.mbox");
+        let original = Path::new("mail.mbox");
+        std::fs::write(&source, content(256 * 1024)).unwrap();
+        chunk_into(&repo, &source, original).unwrap();
+
+        let name = chunks_of(&repo, original).remove(0);
+        let chunk = repo.join(chunk_dir(original)).join(&name);
+        let mut data = std::fs::read(&chunk).unwrap();
+        data[0] ^= 1;
+        std::fs::write(&chunk, data).unwrap();
+        let e = reassemble_from(&repo, &scratch, original).unwrap_err();
+        assert!(e.to_string().contains("corrupt chunk"), "{:#}", e);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,6 +1,12 @@
 use std::{path::PathBuf, sync::OnceLock};
 
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{ArgAction, ArgGroup, Parser, Subcommand, ValueEnum};
+use log::LevelFilter;
+
+use crate::{
+    config::{ConflictPolicy, HookPhase},
+    prune_history::Keep,
+};
 
 pub static CLI: OnceLock<Cli> = OnceLock::new();
 
@@ -15,14 +21,117 @@ pub struct Cli {
     /// Repository path
     #[arg(short, long, global = true)]
     pub repo: Option<PathBuf>,
+    /// Output format of the results on stdout
+    #[arg(long, global = true, value_enum, default_value_t)]
+    pub output: OutputFormat,
+    /// More logs, `-v` for debug and `-vv` for trace
+    #[arg(short, long, global = true, action = ArgAction::Count, conflicts_with = "quiet")]
+    pub verbose: u8,
+    /// Only log errors
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+    /// Print the time spent in each phase at the end
+    #[arg(long, global = true)]
+    pub timings: bool,
+    /// Only run the items of this profile, instead of the default profile of
+    /// this device
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+    /// Only run the items with this tag, may be given more than once
+    #[arg(long, global = true)]
+    pub tag: Vec<String>,
+    /// Push even more than the `push_size_limit` of the config
+    #[arg(long, global = true)]
+    pub force_push_large: bool,
+    /// Process the items one after the other in a stable order, see
+    /// `deterministic` in the config
+    #[arg(long, global = true)]
+    pub ordered: bool,
+    /// Wait for another running gsb to finish instead of failing
+    #[arg(long, global = true)]
+    pub wait: bool,
 }
 
-#[derive(Subcommand, Debug, Clone, Default)]
+impl Cli {
+    /// The log level selected by `-q` and `-v`, or `None` to fall back to
+    /// `RUST_LOG`.
+    pub fn log_level(&self) -> Option<LevelFilter> {
+        match (self.quiet, self.verbose) {
+            (true, _) => Some(LevelFilter::Error),
+            (false, 0) => None,
+            (false, 1) => Some(LevelFilter::Debug),
+            (false, _) => Some(LevelFilter::Trace),
+        }
+    }
+}
+
+#[derive(Subcommand, Debug, Clone)]
 pub enum SubCommand {
     /// Sync all files in sync group.
-    #[default]
     #[clap(alias("s"))]
-    Sync,
+    Sync {
+        /// Do not restart the services of the restored files.
+        #[arg(long)]
+        no_restart: bool,
+        /// Only record what would have been restored in `.gsb/shadow`, without
+        /// writing anything to the device.
+        #[arg(long)]
+        shadow: bool,
+        /// Adopt the config file if the remote changed it.
+        #[arg(long)]
+        accept_config: bool,
+        /// Overwrite the files changed here since the last collect.
+        #[arg(long)]
+        force: bool,
+        /// Never ask about a conflict, apply this policy instead of `ask`.
+        #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "stop")]
+        non_interactive: Option<ConflictPolicy>,
+    },
+    /// Fetch the remote and move the repository to it, without restoring
+    /// anything to the device. Collected commits not pushed yet are merged
+    /// with the pulled ones, applying the conflict policies.
+    Pull {
+        /// Adopt the config file if the remote changed it.
+        #[arg(long)]
+        accept_config: bool,
+        /// Take the pulled version of the files changed on both sides.
+        #[arg(long)]
+        force: bool,
+        /// Never ask about a conflict, apply this policy instead of `ask`.
+        #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "stop")]
+        non_interactive: Option<ConflictPolicy>,
+    },
+    /// Restore the files changed by the pulls since the last restore.
+    Restore {
+        /// Do not restart the services of the restored files.
+        #[arg(long)]
+        no_restart: bool,
+        /// Only record what would have been restored in `.gsb/shadow`.
+        #[arg(long)]
+        shadow: bool,
+        /// Overwrite the files changed here since the last collect.
+        #[arg(long)]
+        force: bool,
+        /// Never ask about a conflict, apply this policy instead of `ask`.
+        #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "stop")]
+        non_interactive: Option<ConflictPolicy>,
+        /// Restore all items as they were at this commit or tag instead, e.g.
+        /// `HEAD@{1.week.ago}`, without touching the branches.
+        #[arg(long)]
+        at: Option<String>,
+    },
+    /// Collect the files of the sync group and commit them, without pushing.
+    Collect,
+    /// Push the collected commits.
+    Push,
+    /// Run the commands read from stdin in one process, one per line or as a
+    /// JSON array of command lines or argument arrays. The global options are
+    /// the ones given to `batch`.
+    Batch {
+        /// Run the remaining commands after one fails.
+        #[arg(long)]
+        keep_going: bool,
+    },
     /// Add files to a group.
     Add {
         #[clap(required = true)]
@@ -32,10 +141,371 @@ pub enum SubCommand {
     },
     /// Init the backup repository in specified path.
     Init { path: Option<PathBuf> },
+    /// Set up this device from the remote: clone it, show the name of this
+    /// device in the config and optionally restore the items.
+    Clone {
+        url: String,
+        /// Where to clone to, instead of the repository path.
+        path: Option<PathBuf>,
+        /// Only clone this many commits of history.
+        #[arg(long)]
+        depth: Option<u32>,
+        /// Add this alias of the device to the config.
+        #[arg(long)]
+        alias: Option<String>,
+        /// Restore the items of this device after cloning.
+        #[arg(long)]
+        restore: bool,
+    },
+    /// Restore the quarantined files with the given ids, or list them if no id
+    /// is given.
+    Approve {
+        ids: Vec<String>,
+        /// Discard the quarantined files instead of restoring them.
+        #[arg(long)]
+        reject: bool,
+        /// Restore as an administrator, for the restores that need elevation.
+        /// Windows only.
+        #[arg(long, conflicts_with = "reject")]
+        elevate: bool,
+    },
+    /// Review and resolve the files changed both here and on the remote.
+    /// Without a subcommand, list the files that the next restore would find
+    /// changed on both sides, without changing anything.
+    Conflicts {
+        #[command(subcommand)]
+        command: Option<ConflictsCommand>,
+    },
+    /// Sync periodically, see the `[daemon]` section of the config.
+    Daemon {
+        /// Serve a local web page with the status and buttons to sync now on
+        /// this address, e.g. `127.0.0.1:7070`.
+        #[arg(long)]
+        web: Option<String>,
+        /// Only record what would have been restored in `.gsb/shadow`, without
+        /// collecting or pushing anything, like `daemon.shadow`.
+        #[arg(long)]
+        shadow: bool,
+    },
+    /// Watch the files of the sync group and collect them when they change.
+    Watch {
+        /// Only commit the collected files, without pushing them.
+        #[arg(long)]
+        no_push: bool,
+    },
+    /// Show where the states of two devices, by name or alias, have diverged.
+    DiffDevices { a: String, b: String },
+    /// Check the config, the paths of the items and the remote, reporting all
+    /// problems at once.
+    Doctor,
+    /// Show what gsb recorded in a commit: the device, the items changed, the
+    /// manifest hash and the gsb version. With `<item>@<rev>`, print the
+    /// content of a file of an item at a revision or a date instead, e.g.
+    /// `gsb show .zshrc@2026-03-01`.
+    Show {
+        rev: String,
+        /// Write the content of the file to this path instead of printing it.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Show the commits that changed an item, with their device and files.
+    History {
+        /// The path of the item in the repository.
+        item: PathBuf,
+        /// Only show this many commits.
+        #[arg(short = 'n', long)]
+        limit: Option<usize>,
+    },
+    /// Show the files, size and history size of every item, to find what
+    /// makes the repository grow.
+    Stats,
+    /// Show when the last run was and whether it succeeded.
+    Status {
+        /// Print a single line like `✓ synced 12m ago`, for status bars and
+        /// login messages.
+        #[arg(long)]
+        short: bool,
+    },
+    /// Run a hook by name, or all hooks of a phase.
+    Run {
+        #[arg(required_unless_present = "all")]
+        hook: Option<String>,
+        /// Run all hooks of this phase instead.
+        #[arg(long, value_enum, conflicts_with = "hook")]
+        all: Option<HookPhase>,
+    },
+    /// Move items of the sync group into a new repository.
+    Split {
+        /// The items to move, separated by commas.
+        #[arg(long, value_delimiter = ',', required = true)]
+        items: Vec<PathBuf>,
+        /// The remote of the new repository.
+        #[arg(long)]
+        to: String,
+        /// Keep the history of the items.
+        #[arg(long)]
+        history: bool,
+    },
+    /// Track a path in a new item, unless an item covers it already, and collect
+    /// it at once.
+    Track {
+        path: PathBuf,
+        /// The group of the new item.
+        #[arg(short, long, value_enum, default_value_t)]
+        group: Group,
+        /// Do not ask, use the defaults.
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Find the files ignored by the projects in the workspaces that are worth
+    /// a backup, like `.env`, and propose items for them.
+    ScanProjects {
+        /// Do not ask, add all proposed items.
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Stop tracking an item. Asks what to clean up unless told.
+    Untrack {
+        /// The path of the item in the repository.
+        item: PathBuf,
+        /// Remove the files of the item from the repository.
+        #[arg(long, conflicts_with = "keep")]
+        remove_files: bool,
+        /// Turn the hardlinks on this device into independent files.
+        #[arg(long, conflicts_with = "keep")]
+        unlink: bool,
+        /// Only remove the item from the config.
+        #[arg(long)]
+        keep: bool,
+    },
+    /// Put back the files written by the last restore as they were before it.
+    Undo,
+    /// The files removed from the repository, kept for `attic_retention`.
+    Attic {
+        #[command(subcommand)]
+        command: AtticCommand,
+    },
+    /// Move an item to another path in the repository, keeping its history.
+    Mv { old: PathBuf, new: PathBuf },
+    /// Squash the old history of a branch into one commit, keeping the recent
+    /// commits, so the repository stops growing.
+    PruneHistory {
+        /// The newest commits to keep, a number like `500` or a duration like
+        /// `90d`.
+        #[arg(long)]
+        keep: Keep,
+        /// The branch to prune.
+        #[arg(long, default_value = "sync")]
+        branch: String,
+        /// Do not ask before rewriting the history.
+        #[arg(short, long)]
+        yes: bool,
+        /// Force-push the pruned branch, rewriting it on the remote too.
+        #[arg(long)]
+        push: bool,
+    },
+    /// Publish the items of `[publish]` to a branch of their own.
+    Publish,
+    /// Import the items of another gsb repository into this one.
+    Absorb {
+        /// The path of the other repository.
+        path: PathBuf,
+        /// Keep the history of the items.
+        #[arg(long)]
+        history: bool,
+    },
+    /// Run gsb in the background, as a systemd user service, a launchd agent or
+    /// a scheduled task.
+    Service {
+        #[command(subcommand)]
+        command: ServiceCommand,
+    },
+    /// Manage the config file.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Track well-known locations in one go.
+    Preset {
+        #[command(subcommand)]
+        command: PresetCommand,
+    },
+    /// List the devices referenced by the config.
+    Devices,
+    /// Manage the names of the devices.
+    Device {
+        #[command(subcommand)]
+        command: DeviceCommand,
+    },
+}
+
+impl SubCommand {
+    /// Whether the command writes to the repository, and so has to lock it for
+    /// the whole run. `watch` and `daemon` only lock it while working.
+    pub fn is_mutating(&self) -> bool {
+        !matches!(
+            self,
+            SubCommand::DiffDevices { .. }
+                | SubCommand::Clone { .. }
+                | SubCommand::Init { .. }
+                | SubCommand::Status { .. }
+                | SubCommand::Show { .. }
+                | SubCommand::Stats
+                | SubCommand::History { .. }
+                | SubCommand::Conflicts { command: None }
+                | SubCommand::Attic {
+                    command: AtticCommand::List
+                }
+                | SubCommand::Doctor
+                | SubCommand::Devices
+                | SubCommand::Watch { .. }
+                | SubCommand::Daemon { .. }
+                | SubCommand::Service { .. }
+        )
+    }
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ConfigCommand {
+    /// Adopt the config changed by the remote. Only additive changes are
+    /// adopted without `--review`.
+    Pull {
+        /// Show the diff of the incoming config and ask for approval.
+        #[arg(long)]
+        review: bool,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ConflictsCommand {
+    /// List the conflicts of past restores waiting to be resolved.
+    List,
+    /// Show a conflict and the diff from the local version to the repository
+    /// one.
+    Show { id: String },
+    /// Write the chosen version to the device, to be collected by the next
+    /// sync.
+    #[command(group(ArgGroup::new("resolution").required(true)))]
+    Resolve {
+        id: String,
+        /// Keep the version of this device.
+        #[arg(long, group = "resolution")]
+        take_local: bool,
+        /// Take the version of the repository.
+        #[arg(long, group = "resolution")]
+        take_repo: bool,
+        /// Merge both versions, leaving conflict markers where they clash.
+        #[arg(long, group = "resolution")]
+        merge: bool,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum AtticCommand {
+    /// List the files in the attic by the day they were removed.
+    List,
+    /// Put a file or directory back into the repository and commit it.
+    Restore {
+        /// The path in the repository.
+        path: PathBuf,
+        /// The day it was removed, e.g. `2024-06-30`. The last day if not given.
+        #[arg(long)]
+        date: Option<String>,
+        /// Copy it here instead of into the repository.
+        #[arg(long)]
+        to: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum PresetCommand {
+    /// Sync the game saves found in the usual places of this OS. The newest
+    /// save wins a conflict, the other one is kept as a conflict copy.
+    Games {
+        /// Do not ask, add all saves not synced by the publisher's cloud.
+        #[arg(short, long)]
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum DeviceCommand {
+    /// Manage the aliases usable instead of the device names in the config.
+    Alias {
+        #[command(subcommand)]
+        command: AliasCommand,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum AliasCommand {
+    /// Add an alias of this device, or of `--device`.
+    Add {
+        name: String,
+        /// The device name the alias stands for.
+        #[arg(long)]
+        device: Option<String>,
+    },
+    /// List the aliases.
+    List,
+    /// Remove an alias.
+    Rm { name: String },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ServiceCommand {
+    /// Install and enable a timer or agent running `gsb sync` on this
+    /// repository.
+    Install {
+        /// The name of the units.
+        #[arg(long, default_value = "gsb")]
+        name: String,
+        /// How often the timer runs, e.g. `15m`.
+        #[arg(long, default_value = "15m")]
+        interval: String,
+        /// Install a service running `gsb daemon` instead of a timer.
+        #[arg(long)]
+        daemon: bool,
+        /// Only print the units.
+        #[arg(long)]
+        print: bool,
+    },
+}
+
+/// The format of the results printed on stdout.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human readable text.
+    #[default]
+    Text,
+    /// Machine readable JSON, one document per command.
+    Json,
+    /// A line of JSON per event while running, see [`crate::events::Event`].
+    Events,
+}
+
+/// Whether the results should be printed as JSON.
+pub fn json_output() -> bool {
+    CLI.get().is_some_and(|cli| cli.output == OutputFormat::Json)
+}
+
+/// Print `text` for the user on stdout, or on stderr with `--output json`,
+/// where stdout only holds the JSON documents.
+pub fn print_text(text: &str) {
+    if json_output() {
+        eprintln!("{}", text);
+    } else {
+        println!("{}", text);
+    }
+}
+
+/// Whether the events should be printed as they happen.
+pub fn events_output() -> bool {
+    CLI.get().is_some_and(|cli| cli.output == OutputFormat::Events)
 }
 
 /// What group the file should be add to, Backup or Sync.
-#[derive(ValueEnum, Debug, Clone, Default)]
+#[derive(ValueEnum, Debug, Clone, Copy, Default)]
 pub enum Group {
     #[default]
     #[clap(alias("b"))]
@@ -43,3 +513,19 @@ pub enum Group {
     #[clap(alias("s"))]
     Sync,
 }
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn test_cli() {
+        // Fails on clashing flags, e.g. `profile` as an alias of `--timings`.
+        Cli::command().debug_assert();
+        let cli = Cli::try_parse_from(["gsb", "--timings", "--profile", "work", "sync"]).unwrap();
+        assert!(cli.timings);
+        assert_eq!(cli.profile.as_deref(), Some("work"));
+    }
+}
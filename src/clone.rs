@@ -0,0 +1,78 @@
+//! `gsb clone`: set up a new device from the remote in one go.
+
+use anyhow::{bail, ensure, Result};
+use log::info;
+
+use crate::{
+    cli::{json_output, print_text, AliasCommand, CLI},
+    config::{check_identity, reload_config, report_config_errors, CONFIG, CONFIG_NAME},
+    device::{alias, devicename},
+    git_command::{git_remote, REPO_PATH, SYNC_BRANCH},
+    lock::lock_repo,
+    sync::{restore_at, SyncOptions},
+};
+
+/// Clone the sync branch of `url` into the repository path, check that it is a
+/// gsb repository and show the name of this device in it. Then add `alias`
+/// for this device, and restore the items if `restore`.
+pub async fn clone(
+    url: &str,
+    depth: Option<u32>,
+    alias_name: Option<&str>,
+    restore: bool,
+) -> Result<()> {
+    let repo = REPO_PATH.as_path();
+    if repo.read_dir().is_ok_and(|mut entries| entries.next().is_some()) {
+        bail!("{:?} is not empty, clone into another path with `gsb clone <url> <path>`", repo);
+    }
+    std::fs::create_dir_all(repo)?;
+    let depth = depth.map(|depth| format!("--depth={}", depth));
+    let mut args = vec!["clone", "--branch", SYNC_BRANCH];
+    args.extend(depth.as_deref());
+    args.extend(["--", url, "."]);
+    git_remote(args).await?;
+    ensure!(
+        repo.join(CONFIG_NAME).exists(),
+        "{} has no {}, it is not a gsb repository",
+        url,
+        CONFIG_NAME
+    );
+    // The config was read before it was cloned.
+    reload_config();
+    report_config_errors();
+    check_identity()?;
+    let _lock = lock_repo(CLI.get().is_some_and(|cli| cli.wait))?;
+
+    let config = CONFIG.read().unwrap().clone();
+    let name = devicename();
+    let items = config
+        .sync_group
+        .0
+        .values()
+        .filter(|info| info.path_on_devices.contains_key(&name))
+        .count();
+    info!("cloned into {:?}", repo);
+    print_text(&format!(
+        "this device is `{}`, with {} items of the sync group",
+        name, items
+    ));
+    if items == 0 {
+        print_text(&format!(
+            "add its paths to the items in {} to sync them here",
+            CONFIG_NAME
+        ));
+    }
+    if let Some(name) = alias_name {
+        alias(&AliasCommand::Add {
+            name: name.to_string(),
+            device: None,
+        })?;
+    }
+    if restore {
+        // The report of the restore is the JSON output then.
+        restore_at("HEAD", SyncOptions::default()).await?;
+    } else if json_output() {
+        println!("{}", serde_json::json!({ "device": name, "items": items }));
+    }
+    Ok(())
+}
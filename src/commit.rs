@@ -0,0 +1,212 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use indicatif::HumanBytes;
+use log::debug;
+
+use crate::{
+    config::{CONFIG, CONFIG_NAME},
+    device::devicename,
+    git_command::{git, git_checked, REPO_PATH},
+};
+
+pub const ACTION_TRAILER: &str = "Gsb-Action";
+pub const DEVICE_TRAILER: &str = "Gsb-Device";
+pub const VERSION_TRAILER: &str = "Gsb-Version";
+
+/// The changes of one item in a commit.
+#[derive(Debug, Clone, Copy, Default)]
+struct Delta {
+    added: u64,
+    modified: u64,
+    deleted: u64,
+    bytes: u64,
+}
+
+/// Commit the staged changes. The body summarizes the changes of every item,
+/// so the history is readable without opening diffs of binary files.
+///
+/// A change of the config file gets a commit of its own, prefixed with
+/// `config:`, so it stands out in the history.
+#[cfg_attr(feature = "profiling", tracing::instrument(skip_all))]
+pub fn commit(action: &str, items: impl IntoIterator<Item = &Path>) -> Result<()> {
+    commit_config()?;
+    let deltas = deltas(items)?;
+    if deltas.is_empty() {
+        debug!("nothing changed, no commit");
+        return Ok(());
+    }
+    let subject = subject(action, &deltas)?;
+    let body = summary(&deltas)?;
+    git_commit(&["-m", &subject, "-m", &body, "-m", &trailers(action)])
+}
+
+/// Run `git commit` with `args`. A commit signed for `git.sign` that fails is
+/// an error, instead of leaving the changes uncommitted.
+fn git_commit(args: &[&str]) -> Result<()> {
+    let args = [&["commit"][..], args].concat();
+    if !CONFIG.read().unwrap().git.sign {
+        git(args)?;
+        return Ok(());
+    }
+    git_checked(args)
+        .context("failed to sign the commit, check `user.signingkey` and `gpg.format`")?;
+    Ok(())
+}
+
+/// The subject of a commit, from `git.commit_template` if set, e.g.
+/// `gsb: {device_alias} {changed_items} ({timestamp})`. The placeholders are
+/// `{action}`, `{device}`, `{device_alias}`, `{changed_items}`, `{files}`,
+/// `{timestamp}` and `{epoch}`.
+fn subject(action: &str, deltas: &BTreeMap<PathBuf, Delta>) -> Result<String> {
+    let config = CONFIG.read().unwrap();
+    let now = SystemTime::now();
+    let epoch = now.duration_since(UNIX_EPOCH)?.as_secs().to_string();
+    let Some(template) = config.git.commit_template.as_deref() else {
+        return Ok(format!(
+            "{} on {} at {}",
+            action,
+            config.this_device(),
+            epoch
+        ));
+    };
+    let items = deltas.keys().map(|item| item.display().to_string());
+    let files = deltas
+        .values()
+        .map(|delta| delta.added + delta.modified + delta.deleted)
+        .sum::<u64>();
+    let timestamp = humantime::format_rfc3339_seconds(now).to_string();
+    let values = [
+        ("action", action.to_string()),
+        ("device_alias", config.this_device()),
+        ("device", devicename()),
+        ("changed_items", items.collect::<Vec<_>>().join(", ")),
+        ("files", files.to_string()),
+        ("timestamp", timestamp),
+        ("epoch", epoch),
+    ];
+    Ok(fill(template, &values))
+}
+
+/// `template` with every `{name}` of `values` replaced, in a single pass so a
+/// value like an item named `{files}` is not replaced again. Unknown
+/// placeholders are left as they are.
+fn fill(template: &str, values: &[(&str, String)]) -> String {
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let value = after.find('}').and_then(|end| {
+            let name = &after[..end];
+            let value = values.iter().find(|(key, _)| *key == name)?;
+            Some((&value.1, end))
+        });
+        match value {
+            Some((value, end)) => {
+                filled.push_str(value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                filled.push('{');
+                rest = after;
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+/// The trailers of a gsb commit, read back by `gsb show`.
+fn trailers(action: &str) -> String {
+    format!(
+        "{}: {}\n{}: {}\n{}: {}",
+        ACTION_TRAILER,
+        action,
+        DEVICE_TRAILER,
+        devicename(),
+        VERSION_TRAILER,
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+fn commit_config() -> Result<()> {
+    if git(["diff", "--cached", "--name-only", "--", CONFIG_NAME])?
+        .trim()
+        .is_empty()
+    {
+        return Ok(());
+    }
+    let subject = format!("config: update on {}", CONFIG.read().unwrap().this_device());
+    git_commit(&["-m", &subject, "-m", &trailers("config"), "--", CONFIG_NAME])
+}
+
+/// The staged changes by item, files outside of `items` under `other`.
+fn deltas(items: impl IntoIterator<Item = &Path>) -> Result<BTreeMap<PathBuf, Delta>> {
+    let items = items.into_iter().collect::<Vec<_>>();
+    let mut deltas: BTreeMap<PathBuf, Delta> = BTreeMap::new();
+    // Lines look like `M\tpath`, or `R100\told\tnew` for renames.
+    for line in git(["diff", "--cached", "--name-status"])?.lines() {
+        let mut fields = line.split('\t');
+        let (Some(status), Some(path)) = (fields.next(), fields.last()) else {
+            continue;
+        };
+        let path = Path::new(path);
+        let item = items
+            .iter()
+            .find(|item| path.starts_with(item))
+            .map_or_else(|| PathBuf::from("other"), |item| item.to_path_buf());
+        let delta = deltas.entry(item).or_default();
+        match status.chars().next() {
+            Some('A') => delta.added += 1,
+            Some('D') => delta.deleted += 1,
+            _ => delta.modified += 1,
+        }
+        if let Ok(meta) = REPO_PATH.join(path).metadata() {
+            delta.bytes += meta.len();
+        }
+    }
+    Ok(deltas)
+}
+
+/// The body of a commit: the changes of every item, so the history is readable
+/// without opening diffs of binary files.
+fn summary(deltas: &BTreeMap<PathBuf, Delta>) -> Result<String> {
+    let mut summary = String::new();
+    for (item, delta) in deltas {
+        writeln!(
+            summary,
+            "{}: {} added, {} modified, {} deleted, {}",
+            item.display(),
+            delta.added,
+            delta.modified,
+            delta.deleted,
+            HumanBytes(delta.bytes)
+        )?;
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fill() {
+        let values = [
+            ("action", "collect".to_string()),
+            ("files", "{action}".to_string()),
+        ];
+        // A value is not substituted again.
+        assert_eq!(fill("{action}: {files}", &values), "collect: {action}");
+        // Unknown placeholders and lone braces are kept.
+        assert_eq!(fill("{x} {action} { }", &values), "{x} collect { }");
+        assert_eq!(fill("{{action}}", &values), "{collect}");
+        assert_eq!(fill("{action", &values), "{action");
+    }
+}
@@ -0,0 +1,142 @@
+//! Items with `compress` set are stored file by file as `<file>.zst` in the
+//! repository, keeping a history per file, and decompressed on restore.
+
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+use crate::{
+    archive::Extracted,
+    config::Config,
+    git_command::REPO_PATH,
+    state::state_dir,
+    utils::{item_files, CopyStats},
+};
+
+const EXTENSION: &str = ".zst";
+
+/// The name of the compressed `path` in the repository.
+pub fn compressed_name(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(EXTENSION);
+    PathBuf::from(name)
+}
+
+/// The path the compressed file at `path` in the repository stands for, if it
+/// belongs to an item with `compress` set.
+pub fn compressed_file(config: &Config, path: &Path) -> Option<PathBuf> {
+    let original = Path::new(path.to_str()?.strip_suffix(EXTENSION)?);
+    let mut items = config.sync_group.0.iter();
+    items
+        .any(|(item, info)| info.compress.is_some() && original.starts_with(item))
+        .then(|| original.to_path_buf())
+}
+
+/// Compress the files of the item at `from` into the repository. A file is
+/// skipped if its copy has the same modification time, which is set to the
+/// one of the original.
+pub fn compress_item(from: &Path, item: &Path) -> Result<CopyStats> {
+    compress_into(&REPO_PATH, from, item)
+}
+
+/// [`compress_item`] into the repository at `repo`.
+fn compress_into(repo: &Path, from: &Path, item: &Path) -> Result<CopyStats> {
+    let mut stats = CopyStats::default();
+    for (source, target) in item_files(from, &repo.join(item))? {
+        // Collected before the item was compressed.
+        if target.is_file() {
+            std::fs::remove_file(&target)?;
+        }
+        let target = compressed_name(&target);
+        let meta = source.metadata()?;
+        let modified = meta.modified()?;
+        let current = target.metadata().and_then(|meta| meta.modified());
+        if current.is_ok_and(|current| current == modified) {
+            stats.files_skipped += 1;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let output = File::create(&target)?;
+        zstd::stream::copy_encode(File::open(&source)?, &output, 0)?;
+        output.set_modified(modified)?;
+        stats.files_copied += 1;
+        stats.bytes_copied += meta.len();
+    }
+    Ok(stats)
+}
+
+/// Decompress the compressed `original` (relative to the repository) into a
+/// temporary directory of `.gsb/`, returning the decompressed file. The
+/// directory is removed with the returned guard.
+pub fn decompress(original: &Path) -> Result<(PathBuf, Extracted)> {
+    decompress_from(&REPO_PATH, &state_dir()?.join("decompress"), original)
+}
+
+/// [`decompress`] from the repository at `repo` into a directory of `scratch`.
+fn decompress_from(repo: &Path, scratch: &Path, original: &Path) -> Result<(PathBuf, Extracted)> {
+    let dir = scratch.join(original);
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir)?;
+    }
+    std::fs::create_dir_all(&dir)?;
+    let file = dir.join(original.file_name().unwrap_or_default());
+    let compressed = File::open(repo.join(compressed_name(original)))?;
+    zstd::stream::copy_decode(compressed, File::create(&file)?)?;
+    Ok((file, Extracted(dir)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_dir;
+
+    #[test]
+    fn test_round_trip() {
+        let dir = test_dir("compress_round_trip");
+        let (repo, scratch, from) = (dir.join("repo"), dir.join("scratch"), dir.join("logs"));
+        let item = Path::new("logs");
+        std::fs::create_dir_all(from.join("old")).unwrap();
+        let files = [
+            ("app.log", "started\n".repeat(10_000).into_bytes()),
+            ("old/app.log", vec![0, 159, 146, 150, 255]),
+            ("empty.log", Vec::new()),
+        ];
+        for (name, content) in files.iter() {
+            std::fs::write(from.join(name), content).unwrap();
+        }
+
+        let stats = compress_into(&repo, &from, item).unwrap();
+        assert_eq!((stats.files_copied, stats.files_skipped), (3, 0));
+        for (name, content) in files.iter() {
+            let original = item.join(name);
+            assert!(repo.join(compressed_name(&original)).is_file());
+            let (file, _guard) = decompress_from(&repo, &scratch, &original).unwrap();
+            assert_eq!(&std::fs::read(file).unwrap(), content);
+        }
+        let stats = compress_into(&repo, &from, item).unwrap();
+        assert_eq!((stats.files_copied, stats.files_skipped), (0, 3));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_collected_before() {
+        let dir = test_dir("compress_collected_before");
+        let (repo, scratch, from) = (dir.join("repo"), dir.join("scratch"), dir.join("app.db"));
+        let item = Path::new("app.db");
+        std::fs::write(&from, "rows ".repeat(1000)).unwrap();
+        // Collected as is before `compress` was set.
+        std::fs::create_dir_all(&repo).unwrap();
+        std::fs::write(repo.join(item), "old rows").unwrap();
+
+        compress_into(&repo, &from, item).unwrap();
+        assert!(!repo.join(item).exists());
+        let (file, _guard) = decompress_from(&repo, &scratch, item).unwrap();
+        assert_eq!(std::fs::read(file).unwrap(), std::fs::read(&from).unwrap());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
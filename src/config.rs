@@ -4,13 +4,20 @@ use std::{
     sync::{Arc, Mutex, RwLock},
 };
 
-use config_file::*;
-use serde::{Deserialize, Serialize};
-use whoami::devicename;
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use clap::ValueEnum;
+use log::{error, warn};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use toml::Value;
+use toml_edit::{ImDocument, Item};
 
-use crate::git_command::REPO_PATH;
+use crate::{
+    cli::CLI,
+    device::{devicename, set_identity},
+    git_command::REPO_PATH,
+};
 
-const CONFIG_NAME: &str = ".gsb.config.toml";
+pub const CONFIG_NAME: &str = ".gsb.config.toml";
 
 use std::sync::LazyLock;
 
@@ -25,14 +32,154 @@ pub struct SyncFile {
     pub path_on_devices: BTreeMap<String, PathBuf>,
     /// Whether the file is a hardlink. If not, it needs a copy sync.
     pub is_hardlink: bool,
+    #[serde(default)]
+    pub placeholder: PlaceholderPolicy,
+    /// What a pull does with a conflict in this item, instead of the
+    /// `conflict` of the config.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conflict: Option<ConflictPolicy>,
+    /// Keep the losing local file of a conflict next to the restored one, as
+    /// `<name>.conflict-<device>-<time>`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub conflict_copies: bool,
+    /// The profiles this item belongs to. In all profiles if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub profiles: Vec<String>,
+    /// Ad-hoc labels like `shell` or `editor`, to run only some items with
+    /// `--tag`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Pack this directory item into a single `<item>.<format>` in the
+    /// repository, e.g. `"tar.zst"` for a browser profile of thousands of
+    /// small files. Restore unpacks it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive: Option<ArchiveFormat>,
+    /// Store every file of this item compressed, as `<file>.zst` in the
+    /// repository, keeping a history per file unlike `archive`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compress: Option<Compression>,
+    /// Store every file of this item as content-defined chunks, so large
+    /// append-mostly files like mail archives or databases delta well.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub chunk: bool,
+    /// Store the files of this item encrypted with `age` to the recipients of
+    /// `[encryption]`, see [`crate::encrypt`]. Takes the place of `compress`
+    /// and `chunk`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypt: Option<Encryption>,
+    /// Overrides the `[limits]` of the config for this item.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<Limits>,
+    /// Store the files of this item with Git LFS, for large binaries like
+    /// game saves or databases. Needs `git lfs` installed.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub lfs: bool,
+}
+
+/// What [`SyncFile::encrypt`] hides from the remote.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, PartialOrd, Eq, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Encryption {
+    /// The content of the files, stored as `<file>.age` under their names.
+    Contents,
+    /// The content, the names and the directory structure: the files are
+    /// stored flat under random-looking names, with an encrypted index.
+    Names,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, PartialOrd, Eq, Ord)]
 pub struct BackupFile {
-    /// The absolute path of file in this device.
+    /// The absolute path of file in this device, or a glob relative to
+    /// `workspace` if set.
     pub path_on_device: PathBuf,
     /// Whether the file is a hardlink. If not, it needs a copy backup.
     pub is_hardlink: bool,
+    #[serde(default)]
+    pub placeholder: PlaceholderPolicy,
+    /// The profiles this item belongs to. In all profiles if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub profiles: Vec<String>,
+    /// Ad-hoc labels like `shell` or `editor`, to run only some items with
+    /// `--tag`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// The workspace of [`Config::workspaces`] that `path_on_device` is
+    /// relative to. Every match of the glob is kept in the item under its path
+    /// relative to the workspace root.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace: Option<String>,
+    /// Overrides the `[limits]` of the config for this item.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<Limits>,
+    /// Store the files of this item with Git LFS, for large binaries like
+    /// game saves or databases. Needs `git lfs` installed.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub lfs: bool,
+}
+
+/// The `[limits]` section, or the `limits` of an item overriding it.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, PartialOrd, Eq, Ord,
+)]
+#[serde(default)]
+pub struct Limits {
+    /// Warn when collecting a file larger than this many bytes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warn_file_size: Option<u64>,
+    /// Refuse to collect an item with a file larger than this many bytes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_file_size: Option<u64>,
+}
+
+impl Limits {
+    /// These limits, with the ones not set taken from `other`.
+    pub fn or(self, other: Limits) -> Limits {
+        Limits {
+            warn_file_size: self.warn_file_size.or(other.warn_file_size),
+            max_file_size: self.max_file_size.or(other.max_file_size),
+        }
+    }
+}
+
+/// How [`SyncFile::archive`] packs an item.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, PartialOrd, Eq, Ord)]
+pub enum ArchiveFormat {
+    #[serde(rename = "tar.zst")]
+    TarZst,
+    #[serde(rename = "tar.gz")]
+    TarGz,
+}
+
+impl ArchiveFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::TarZst => "tar.zst",
+            Self::TarGz => "tar.gz",
+        }
+    }
+}
+
+/// How [`SyncFile::compress`] compresses the files of an item.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, PartialOrd, Eq, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Zstd,
+}
+
+/// What to do with a cloud-only placeholder of OneDrive, Dropbox or iCloud in
+/// an item, whose content is only downloaded when read.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, PartialOrd, Eq, Ord,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaceholderPolicy {
+    /// Skip the file with a warning.
+    #[default]
+    Skip,
+    /// Read the file anyway, downloading its content.
+    Hydrate,
+    /// Fail the item.
+    Error,
 }
 
 pub trait Getable<'a> {
@@ -40,10 +187,26 @@ pub trait Getable<'a> {
     fn get_on_device(&'a self) -> Self::Output;
 }
 
+/// The aliases of this device, see [`Config::aliases`].
+static THIS_DEVICE_ALIASES: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Remember which of `aliases` stand for this device.
+pub fn set_aliases(aliases: &BTreeMap<String, String>) {
+    let this = devicename();
+    *THIS_DEVICE_ALIASES.write().unwrap() = aliases
+        .iter()
+        .filter(|(_, device)| **device == this)
+        .map(|(alias, _)| alias.clone())
+        .collect();
+}
+
 impl<'a> Getable<'a> for SyncFile {
     type Output = Option<&'a PathBuf>;
     fn get_on_device(&'a self) -> Self::Output {
-        self.path_on_devices.get(&devicename())
+        self.path_on_devices.get(&devicename()).or_else(|| {
+            let aliases = THIS_DEVICE_ALIASES.read().unwrap();
+            aliases.iter().find_map(|alias| self.path_on_devices.get(alias))
+        })
     }
 }
 
@@ -66,36 +229,1164 @@ pub struct SyncGroup(pub BTreeMap<PathBuf, SyncFile>);
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct BackupGroup(pub BTreeMap<PathBuf, BackupFile>);
 
+/// How to decide whether a file needs to be copied.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CompareMode {
+    /// Compare size and modification time.
+    #[default]
+    Metadata,
+    /// Compare the BLAKE3 hash of the content against the hash index.
+    Hash,
+}
+
+/// The `[compare]` section. A bare `compare = "hash"` is accepted as well and
+/// only sets the method.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(from = "CompareConfigRepr")]
+pub struct CompareConfig {
+    pub method: CompareMode,
+    /// How far apart two mtimes may be to still count as equal, for
+    /// filesystems with a coarse resolution like FAT (2 seconds). Full
+    /// precision by default.
+    pub mtime_tolerance_ms: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CompareConfigRepr {
+    Method(CompareMode),
+    Table {
+        #[serde(default)]
+        method: CompareMode,
+        #[serde(default)]
+        mtime_tolerance_ms: u64,
+    },
+}
+
+impl From<CompareConfigRepr> for CompareConfig {
+    fn from(repr: CompareConfigRepr) -> Self {
+        match repr {
+            CompareConfigRepr::Method(method) => Self {
+                method,
+                ..Default::default()
+            },
+            CompareConfigRepr::Table {
+                method,
+                mtime_tolerance_ms,
+            } => Self {
+                method,
+                mtime_tolerance_ms,
+            },
+        }
+    }
+}
+
+/// What to do when a pulled manifest is unsigned or its signature is invalid.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SignaturePolicy {
+    /// Do not verify the manifest.
+    #[default]
+    Off,
+    /// Verify the manifest, but only warn on failure.
+    Warn,
+    /// Refuse to restore the pulled changes on failure.
+    Require,
+}
+
+/// The `[signing]` section, see [`crate::manifest`].
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SigningConfig {
+    /// The private SSH key signing the manifest on collect. Nothing is signed
+    /// if not set.
+    pub key: Option<PathBuf>,
+    #[serde(default)]
+    pub policy: SignaturePolicy,
+}
+
+/// The `[encryption]` section, for the items with `encrypt` set.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct EncryptionConfig {
+    /// The `age` recipients file the items are encrypted to, with the public
+    /// keys of all devices.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recipients: Option<PathBuf>,
+    /// The `age` identity file of this device, decrypting the items on
+    /// restore. Also keys the stored names of `encrypt = "names"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identity: Option<PathBuf>,
+}
+
+/// Where the logs go.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogTarget {
+    /// stderr, and the log file if configured.
+    #[default]
+    Stderr,
+    /// The systemd journal, with structured fields.
+    Journald,
+    /// The local syslog daemon.
+    Syslog,
+}
+
+/// The `[logging]` section.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub target: LogTarget,
+    /// Write the logs to this file as well. Only stderr if not set.
+    pub file: Option<PathBuf>,
+    /// Keep logging to stderr when logging to a file.
+    pub stderr: bool,
+    /// Rotate the log file once it grows beyond this many bytes.
+    pub max_size: u64,
+    /// Rotate the log file once it is older than this, e.g. `1d` or `12h`.
+    pub rotate_every: Option<String>,
+    /// How many rotated log files to keep.
+    pub keep: u32,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            target: LogTarget::default(),
+            file: None,
+            stderr: true,
+            max_size: 10 * 1024 * 1024,
+            rotate_every: None,
+            keep: 5,
+        }
+    }
+}
+
+/// The `[watch]` section, see `gsb watch`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct WatchConfig {
+    /// How long the files have to be quiet before a collect, so a burst of
+    /// saves is collected once.
+    pub debounce_ms: u64,
+    /// The minimum time between two collects, e.g. `5m`. The changes in
+    /// between are collected together. No minimum if not set.
+    pub min_commit_interval: Option<String>,
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        Self {
+            debounce_ms: 500,
+            min_commit_interval: None,
+        }
+    }
+}
+
+/// What a pull does with a file changed both by the remote and on this device
+/// since the last collect.
+#[derive(Serialize, Deserialize, ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Ask what to do with each file on the terminal. Without a terminal, or
+    /// with `--non-interactive`, act as `stop`.
+    #[default]
+    Ask,
+    /// Restore the remote file, discarding the local change.
+    Remote,
+    /// Keep the local file, it is collected and pushed over the remote one.
+    Local,
+    /// Keep whichever is newer: the local file by its mtime, or the remote one
+    /// by the time it was committed. Meant for game saves, where the newest
+    /// save has the most progress.
+    Newest,
+    /// Refuse to restore until the conflict is resolved with `gsb conflicts`,
+    /// or the restore is forced with `--force`.
+    Stop,
+}
+
+/// The `[daemon]` section, see `gsb daemon`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DaemonConfig {
+    /// The time between two syncs, e.g. `5m` or `1h`.
+    pub interval: String,
+    /// How far each wait may randomly differ from `interval`, either a
+    /// percentage of it like `10%`, at most `100%`, or a time like `30s`, so
+    /// devices sharing a remote do not all fetch at once. No jitter if not set.
+    pub jitter: Option<String>,
+    /// Serve Prometheus metrics on this address, e.g. `127.0.0.1:9898`.
+    pub metrics_addr: Option<String>,
+    /// How many files to hash on both sides after each sync, looking for bit
+    /// rot and drift. Off if 0.
+    pub verify_per_cycle: usize,
+    /// Run every cycle in shadow mode, like `gsb daemon --shadow`: pull and
+    /// record what would have been restored, but collect and push nothing.
+    pub shadow: bool,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            interval: "5m".to_string(),
+            jitter: None,
+            metrics_addr: None,
+            verify_per_cycle: 0,
+            shadow: false,
+        }
+    }
+}
+
+/// The `[retry]` section: how fetches and pushes are retried on network
+/// errors, waiting twice as long after each attempt.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// How many times to retry, 0 to fail at once.
+    pub max_retries: u32,
+    /// The wait before the first retry.
+    pub initial_delay_ms: u64,
+    /// The longest wait between two retries.
+    pub max_delay_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_delay_ms: 1000,
+            max_delay_ms: 60_000,
+        }
+    }
+}
+
+/// The `[publish]` section, see `gsb publish`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PublishConfig {
+    /// The items to publish, by relative path in the repository. Only put
+    /// non-sensitive items here.
+    pub items: Vec<PathBuf>,
+    /// The branch they are published to.
+    pub branch: String,
+    /// The remote the branch is pushed to, e.g. a public repository. The remote
+    /// of the repository if not set.
+    pub remote: Option<String>,
+}
+
+impl Default for PublishConfig {
+    fn default() -> Self {
+        Self {
+            items: Vec::new(),
+            branch: "public".to_string(),
+            remote: None,
+        }
+    }
+}
+
+/// The `[notifications]` section: which categories are shown, see
+/// [`crate::notification`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct NotificationConfig {
+    /// Files were restored.
+    pub success: bool,
+    /// Files changed both locally and on the remote.
+    pub conflict: bool,
+    /// A sync failed.
+    pub error: bool,
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        Self {
+            success: true,
+            conflict: true,
+            error: true,
+        }
+    }
+}
+
+/// The format of the events posted to [`NotifyConfig::url`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookKind {
+    /// The event as JSON, for a generic endpoint.
+    #[default]
+    Json,
+    /// A plain text message for an ntfy topic URL.
+    Ntfy,
+    /// A message for a Slack incoming webhook.
+    Slack,
+}
+
+/// The `[notify]` section, reporting the results to a webhook, see
+/// [`crate::webhook`].
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct NotifyConfig {
+    /// Where the events are posted. Nothing is posted if not set.
+    pub url: Option<String>,
+    #[serde(default)]
+    pub kind: WebhookKind,
+    /// Pinged after a successful sync or collect, and with `/fail` appended
+    /// after a failed one, like healthchecks.io expects.
+    pub healthcheck_url: Option<String>,
+}
+
+/// The `[scan]` section, see `gsb scan-projects`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ScanConfig {
+    /// The file names worth a backup when a project ignores them.
+    pub patterns: Vec<String>,
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        Self {
+            patterns: [".env", ".env.*", "local.settings.json", "*.local.json", ".envrc"]
+                .map(String::from)
+                .to_vec(),
+        }
+    }
+}
+
+/// The `[device]` section.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DeviceConfig {
+    /// Where the name of this device comes from: `devicename`, the name the
+    /// OS shows, `hostname`, `machine-uid`, `env:<VAR>` like `env:GSB_DEVICE`,
+    /// or `file:<path>`. The machine uid changes when the OS is reinstalled
+    /// and is missing in some containers.
+    pub identity: String,
+}
+
+impl Default for DeviceConfig {
+    fn default() -> Self {
+        Self {
+            identity: "devicename".to_string(),
+        }
+    }
+}
+
+/// The `[git]` section.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct GitConfig {
+    /// The git binary to run, e.g. a portable git not on the `PATH`. `git` if
+    /// not set.
+    pub binary: Option<PathBuf>,
+    /// Only fetch this many commits of history, for slow connections that
+    /// only need the current state. The whole history if not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub depth: Option<u32>,
+    /// The name the commits of gsb are made by, instead of `user.name` of the
+    /// git config.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The email the commits of gsb are made by, instead of `user.email` of
+    /// the git config.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    /// Sign the commits of gsb with the signing key of the git config, GPG or
+    /// SSH as set by `gpg.format`, for remotes that require verified commits.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub sign: bool,
+    /// The subject of the commits of gsb, e.g.
+    /// `"gsb: {device_alias} {changed_items} ({timestamp})"`, with `{action}`,
+    /// `{device}`, `{device_alias}`, `{changed_items}`, `{files}`,
+    /// `{timestamp}` and `{epoch}`. `<action> on <device> at <epoch>` if not
+    /// set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit_template: Option<String>,
+}
+
+/// The `[restore]` section.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct RestoreConfig {
+    pub backup: RestoreBackup,
+    /// Also commit the receipt of every restore in `.gsb-receipts/<device>/`,
+    /// see [`crate::receipt`].
+    pub commit_receipts: bool,
+}
+
+/// Where a restore keeps the local file it overwrites, if it differs.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RestoreBackup {
+    /// Overwrite it.
+    #[default]
+    None,
+    /// Move it to `<data dir>/gsb/trash/<time of the run>/<its absolute path>`,
+    /// e.g. `~/.local/share/gsb/trash/...`.
+    Trash,
+    /// Move it to `<file>.gsb.bak` next to it, replacing an older one.
+    Bak,
+}
+
+/// An item of the config file that could not be loaded. The item is skipped
+/// so the others still work, but it is kept as it is when the config is
+/// saved.
+#[derive(Debug, Clone)]
+pub struct BrokenItem {
+    /// `sync_group` or `backup_group`.
+    pub group: &'static str,
+    pub key: String,
+    pub value: Value,
+    pub error: String,
+}
+
+/// A value of the config merged in from an included file, see
+/// [`Config::include`].
+#[derive(Debug, Clone)]
+pub struct Included {
+    pub section: String,
+    /// `None` for a top-level value that is not a table.
+    pub key: Option<String>,
+    /// The included file, relative to the repository.
+    pub file: PathBuf,
+    /// The value as it is saved, to tell whether it was changed since. `None`
+    /// if it is left out when saving, like a default.
+    pub value: Option<Value>,
+}
+
+impl Included {
+    /// The value this stands for in the saved `table`.
+    fn get<'a>(&self, table: &'a toml::Table) -> Option<&'a Value> {
+        let value = table.get(&self.section)?;
+        match &self.key {
+            Some(key) => value.get(key),
+            None => Some(value),
+        }
+    }
+
+    /// The name of the value, like `sync_group."notes"`.
+    fn name(&self) -> String {
+        match &self.key {
+            Some(key) => format!("{}.{:?}", self.section, key),
+            None => self.section.clone(),
+        }
+    }
+}
+
+/// Validation of an item beyond its types.
+trait CheckItem {
+    fn check(&self, key: &Path) -> Result<()>;
+}
+
+impl CheckItem for SyncFile {
+    fn check(&self, key: &Path) -> Result<()> {
+        ensure!(key.is_relative(), "the path in the repository must be relative");
+        for (device, path) in self.path_on_devices.iter() {
+            ensure!(path.is_absolute(), "the path on `{}` must be absolute", device);
+        }
+        Ok(())
+    }
+}
+
+impl CheckItem for BackupFile {
+    fn check(&self, key: &Path) -> Result<()> {
+        ensure!(key.is_relative(), "the path in the repository must be relative");
+        if self.workspace.is_some() {
+            ensure!(
+                self.path_on_device.is_relative(),
+                "the path in a workspace must be relative to its root"
+            );
+            ensure!(!self.is_hardlink, "the files of a workspace cannot be hardlinks");
+        } else {
+            ensure!(self.path_on_device.is_absolute(), "the path on the device must be absolute");
+        }
+        Ok(())
+    }
+}
+
+fn check_item<T: DeserializeOwned + CheckItem>(
+    doc: &ImDocument<&str>,
+    group: &str,
+    key: &str,
+    value: &Value,
+) -> Result<()> {
+    let at = [Step::Key(group.to_string()), Step::Key(key.to_string())];
+    let item: T = deserialize_at(doc, &at, value.clone(), |_| {})?;
+    item.check(Path::new(key))
+}
+
+/// A step of the path to a value in the config file.
+#[derive(Debug, Clone)]
+enum Step {
+    Key(String),
+    Index(usize),
+}
+
+/// Deserialize `value`, which is at `at` in the config file `doc`. An error
+/// tells the line, the key and what was expected there, e.g. `line 12,
+/// daemon.interval: invalid type: integer `5`, expected a string`. The keys
+/// unknown to `T` are passed to `unknown`.
+fn deserialize_at<T: DeserializeOwned>(
+    doc: &ImDocument<&str>,
+    at: &[Step],
+    value: Value,
+    mut unknown: impl FnMut(Vec<Step>),
+) -> Result<T> {
+    let de = serde_ignored::Deserializer::new(value, |path: serde_ignored::Path| {
+        let mut steps = at.to_vec();
+        ignored_steps(&path, &mut steps);
+        unknown(steps);
+    });
+    serde_path_to_error::deserialize(de).map_err(|e| {
+        let mut steps = at.to_vec();
+        for segment in e.path().iter() {
+            match segment {
+                serde_path_to_error::Segment::Map { key } => steps.push(Step::Key(key.clone())),
+                serde_path_to_error::Segment::Seq { index } => steps.push(Step::Index(*index)),
+                _ => {}
+            }
+        }
+        anyhow!("{}: {}", locate(doc, &steps), e.inner())
+    })
+}
+
+fn ignored_steps(path: &serde_ignored::Path, steps: &mut Vec<Step>) {
+    use serde_ignored::Path as Ignored;
+    match path {
+        Ignored::Root => {}
+        Ignored::Seq { parent, index } => {
+            ignored_steps(parent, steps);
+            steps.push(Step::Index(*index));
+        }
+        Ignored::Map { parent, key } => {
+            ignored_steps(parent, steps);
+            steps.push(Step::Key(key.clone()));
+        }
+        Ignored::Some { parent }
+        | Ignored::NewtypeStruct { parent }
+        | Ignored::NewtypeVariant { parent } => ignored_steps(parent, steps),
+    }
+}
+
+/// `steps` as `line 12, daemon.interval`, with the line of the deepest value
+/// found in `doc`.
+fn locate(doc: &ImDocument<&str>, steps: &[Step]) -> String {
+    let mut item = doc.as_item();
+    let mut span = None;
+    for step in steps {
+        let next = match step {
+            Step::Key(key) => item.get(key.as_str()),
+            Step::Index(index) => item.get(*index),
+        };
+        let Some(next) = next else {
+            break;
+        };
+        item = next;
+        span = item_span(item).or(span);
+    }
+    let path = steps
+        .iter()
+        .map(|step| match step {
+            Step::Key(key) if key.contains(['.', ' ', '/', '\\']) => format!("{:?}", key),
+            Step::Key(key) => key.clone(),
+            Step::Index(index) => index.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(".");
+    match span {
+        Some(start) => {
+            let line = doc.raw()[..start].matches('\n').count() + 1;
+            format!("line {}, {}", line, path)
+        }
+        None => path,
+    }
+}
+
+/// Where `item` starts in the config file.
+fn item_span(item: &Item) -> Option<usize> {
+    match item {
+        Item::Value(value) => value.span(),
+        Item::Table(table) => table.span(),
+        Item::ArrayOfTables(array) => array.span(),
+        Item::None => None,
+    }
+    .map(|span| span.start)
+}
+
+/// When a hook runs.
+#[derive(Serialize, Deserialize, ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum HookPhase {
+    PreCollect,
+    PostCollect,
+    PreRestore,
+    PostRestore,
+}
+
+/// A shell command run at a [`HookPhase`], or only by `gsb run`. See
+/// [`crate::hooks`] for its environment.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Hook {
+    pub command: String,
+    /// When the hook runs. Only run by `gsb run` if not set.
+    pub on: Option<HookPhase>,
+    /// Only run the hook if a file of this item changed.
+    pub item: Option<PathBuf>,
+    /// The working directory, relative to the repository. The repository if
+    /// not set.
+    pub cwd: Option<PathBuf>,
+    /// Kill the hook after this long, e.g. `30s`.
+    pub timeout: Option<String>,
+}
+
 /// The config file contains the information of current device, as well as the
 /// sync group and the backup group on current device.
 ///
 /// There is only one SyncGroup in a repository.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
+    /// Globs of more config files relative to the repository, e.g.
+    /// `items/*.toml`, merged into this one. This file wins where both set a
+    /// value.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
     pub device_name: String,
     pub remote: Option<String>,
     pub sync_group: SyncGroup,
     pub backup_group: BackupGroup,
+    #[serde(default)]
+    pub compare: CompareConfig,
+    /// Compress the items of the sync group untouched for this long, e.g.
+    /// `90d`, into `.archive/` and skip them when collecting. Never if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive_after: Option<String>,
+    /// How long the files removed from the repository stay in `.gsb/attic/`,
+    /// `30d` if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attic_retention: Option<String>,
+    /// Cancel an item after this long, e.g. `10m`, so one item stuck on a dead
+    /// network mount does not freeze the whole run. Items on a network
+    /// filesystem get four times as long. No limit if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub item_timeout: Option<String>,
+    /// The memory the parallel jobs may use for their buffers together, in
+    /// bytes. Fewer items run at the same time if needed. Unlimited if not
+    /// set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_budget: Option<u64>,
+    /// Refuse to push more than this many bytes of new objects, unless
+    /// `--force-push-large` is given. No limit if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub push_size_limit: Option<u64>,
+    /// Process the items one after the other in the order of the config, and
+    /// the files of an item sorted by name, so the output and the commits of
+    /// two runs can be compared. Slower, as nothing runs in parallel.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deterministic: bool,
+    /// Only check out the items used on this device, leaving the others out
+    /// of the working tree.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sparse_checkout: bool,
+    /// Collect onto a branch of this device, `sync-<device>`, and merge the
+    /// branches of the other devices when pulling, so a noisy device stays
+    /// apart and the history tells which device changed what.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub device_branches: bool,
+    /// The services to reload or restart after their items are restored: a
+    /// systemd user unit on Linux, a launchd label on macOS, or a Windows
+    /// service.
+    ///
+    /// Key: relative path of the item in the repository.
+    /// Value: the service name.
+    #[serde(default)]
+    pub services: BTreeMap<PathBuf, String>,
+    /// Other names of the devices, usable as the keys of `path_on_devices`
+    /// instead of the device names, e.g. `laptop = "DESKTOP-4F2K9QX"`.
+    ///
+    /// Key: the alias.
+    /// Value: the device name.
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+    /// The default profile of each device, selecting the items with it in
+    /// their `profiles`.
+    ///
+    /// Key: the device name.
+    /// Value: the profile.
+    #[serde(default)]
+    pub profiles: BTreeMap<String, String>,
+    /// Named roots for the items of the backup group with a `workspace`, e.g.
+    /// `projects = "~/code"`.
+    #[serde(default)]
+    pub workspaces: BTreeMap<String, PathBuf>,
+    #[serde(default)]
+    pub signing: SigningConfig,
+    #[serde(default)]
+    pub encryption: EncryptionConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub watch: WatchConfig,
+    #[serde(default)]
+    pub conflict: ConflictPolicy,
+    #[serde(default)]
+    pub daemon: DaemonConfig,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    #[serde(default)]
+    pub publish: PublishConfig,
+    #[serde(default)]
+    pub notifications: NotificationConfig,
+    #[serde(default)]
+    pub notify: NotifyConfig,
+    #[serde(default)]
+    pub device: DeviceConfig,
+    #[serde(default)]
+    pub git: GitConfig,
+    #[serde(default)]
+    pub restore: RestoreConfig,
+    #[serde(default)]
+    pub limits: Limits,
+    #[serde(default)]
+    pub scan: ScanConfig,
+    /// Key: the name of the hook.
+    #[serde(default)]
+    pub hooks: BTreeMap<String, Hook>,
+    /// The items skipped when loading, see [`BrokenItem`].
+    #[serde(skip)]
+    pub broken_items: Vec<BrokenItem>,
+    /// Why the config file could not be loaded at all, if so.
+    #[serde(skip)]
+    pub load_error: Option<String>,
+    /// Why `device.identity` could not be resolved, if so. The config is
+    /// loaded, but nothing runs without knowing which device this is.
+    #[serde(skip)]
+    pub identity_error: Option<String>,
+    /// The values merged in from the included files. They are left out when
+    /// saving unless changed since, the keys added to their sections since are
+    /// kept.
+    #[serde(skip)]
+    pub included: Vec<Included>,
+    /// The keys of the config file that mean nothing, likely misspelled, as
+    /// `line 3, daemon.intervall`.
+    #[serde(skip)]
+    pub unknown_keys: Vec<String>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            include: Vec::new(),
             device_name: devicename(),
             remote: None,
             sync_group: SyncGroup::default(),
             backup_group: Default::default(),
+            compare: CompareConfig::default(),
+            memory_budget: None,
+            push_size_limit: None,
+            deterministic: false,
+            sparse_checkout: false,
+            device_branches: false,
+            item_timeout: None,
+            archive_after: None,
+            attic_retention: None,
+            services: BTreeMap::new(),
+            aliases: BTreeMap::new(),
+            profiles: BTreeMap::new(),
+            workspaces: BTreeMap::new(),
+            signing: SigningConfig::default(),
+            encryption: EncryptionConfig::default(),
+            logging: LoggingConfig::default(),
+            watch: WatchConfig::default(),
+            conflict: ConflictPolicy::default(),
+            daemon: DaemonConfig::default(),
+            retry: RetryConfig::default(),
+            publish: PublishConfig::default(),
+            notifications: NotificationConfig::default(),
+            notify: NotifyConfig::default(),
+            device: DeviceConfig::default(),
+            git: GitConfig::default(),
+            restore: RestoreConfig::default(),
+            limits: Limits::default(),
+            scan: ScanConfig::default(),
+            hooks: BTreeMap::new(),
+            broken_items: Vec::new(),
+            load_error: None,
+            identity_error: None,
+            included: Vec::new(),
+            unknown_keys: Vec::new(),
         }
     }
 }
 
-fn save_config_inner(config: &Config) -> Result<(), ConfigFileError> {
-    config.to_config_file(REPO_PATH.clone().join(CONFIG_NAME))
+impl Config {
+    /// The profile selecting the items of this run: `--profile`, or the default
+    /// of this device. All items are selected if there is none.
+    pub fn active_profile(&self) -> Option<&str> {
+        CLI.get()
+            .and_then(|cli| cli.profile.as_deref())
+            .or_else(|| self.profiles.get(&devicename()).map(String::as_str))
+    }
+
+    /// How `device` is shown in logs, commits and errors: its first alias, or
+    /// its name if it has none.
+    pub fn device_label(&self, device: &str) -> String {
+        self.aliases
+            .iter()
+            .find(|(_, name)| *name == device)
+            .map_or_else(|| device.to_string(), |(alias, _)| alias.clone())
+    }
+
+    /// The device `name` stands for: the device of an alias, or `name` itself.
+    pub fn device_name(&self, name: &str) -> String {
+        self.aliases.get(name).map_or_else(|| name.to_string(), Clone::clone)
+    }
+
+    /// The path of `file` on `device`, keyed by its name or by one of its
+    /// aliases.
+    pub fn path_on<'a>(&self, file: &'a SyncFile, device: &str) -> Option<&'a PathBuf> {
+        file.path_on_devices.get(device).or_else(|| {
+            self.aliases
+                .iter()
+                .filter(|(_, name)| *name == device)
+                .find_map(|(alias, _)| file.path_on_devices.get(alias))
+        })
+    }
+
+    /// The included file that `item` or its service comes from, where it has
+    /// to be changed, see [`Config::include`].
+    pub fn included_item(&self, item: &Path) -> Option<&Path> {
+        let sections = ["sync_group", "backup_group", "services"];
+        let mut included = self.included.iter();
+        included
+            .find(|included| {
+                sections.contains(&included.section.as_str())
+                    && included.key.as_deref().is_some_and(|key| Path::new(key) == item)
+            })
+            .map(|included| included.file.as_path())
+    }
+
+    /// How this device is shown, see [`Config::device_label`].
+    pub fn this_device(&self) -> String {
+        self.device_label(&devicename())
+    }
+
+    /// Whether an item with `profiles` and `tags` is selected by the active
+    /// profile and the `--tag`s of this run.
+    pub fn selects(&self, profiles: &[String], tags: &[String]) -> bool {
+        let in_profile = match self.active_profile() {
+            Some(active) => profiles.is_empty() || profiles.iter().any(|p| p == active),
+            None => true,
+        };
+        let wanted = CLI.get().map_or(&[][..], |cli| cli.tag.as_slice());
+        in_profile && (wanted.is_empty() || tags.iter().any(|tag| wanted.contains(tag)))
+    }
+}
+
+fn save_config_inner(config: &Config) -> Result<()> {
+    // The default config stands in for it, saving would overwrite the file.
+    if let Some(e) = config.load_error.as_ref() {
+        bail!("refused to save {} since it failed to load, fix it first: {}", CONFIG_NAME, e);
+    }
+    let mut table = saved_table(config)?;
+    strip_included(&mut table, &config.included)?;
+    std::fs::write(REPO_PATH.join(CONFIG_NAME), toml::to_string_pretty(&table)?)?;
+    Ok(())
+}
+
+/// The config as it is saved, with the broken items as they were.
+fn saved_table(config: &Config) -> Result<toml::Table> {
+    let Value::Table(mut table) = Value::try_from(config)? else {
+        bail!("the config is not a table");
+    };
+    for item in config.broken_items.iter() {
+        if let Some(Value::Table(group)) = table.get_mut(item.group) {
+            group.insert(item.key.clone(), item.value.clone());
+        }
+    }
+    Ok(table)
 }
-pub fn save_config() -> Result<(), ConfigFileError> {
+pub fn save_config() -> Result<()> {
     save_config_inner(&CONFIG.read().unwrap())
 }
+/// Load the config file again, e.g. after a pull changed it.
+pub fn reload_config() {
+    *CONFIG.write().unwrap() = load_config_or_default();
+}
 pub fn load_config_or_default() -> Config {
-    let config_file = Config::from_config_file(REPO_PATH.clone().join(CONFIG_NAME));
-    config_file.unwrap_or_default()
+    let Ok(content) = std::fs::read_to_string(REPO_PATH.join(CONFIG_NAME)) else {
+        return Config::default();
+    };
+    parse_config(&content).unwrap_or_else(|e| Config {
+        load_error: Some(format!("{:#}", e)),
+        ..Default::default()
+    })
+}
+
+/// Merge the files of the `include` globs into `table`, returning what was
+/// merged in. Sections like `[sync_group]` are merged key by key.
+fn include(table: &mut toml::Table) -> Result<Vec<Included>> {
+    let Some(globs) = table.get("include") else {
+        return Ok(Vec::new());
+    };
+    let globs: Vec<String> = globs.clone().try_into()?;
+    let mut included = Vec::new();
+    for pattern in globs {
+        let pattern = Path::new(&glob::Pattern::escape(&REPO_PATH.to_string_lossy()))
+            .join(&pattern);
+        let mut paths = glob::glob(&pattern.to_string_lossy())?.collect::<Result<Vec<_>, _>>()?;
+        paths.sort();
+        for path in paths {
+            let fragment: toml::Table = toml::from_str(&std::fs::read_to_string(&path)?)
+                .with_context(|| format!("failed to include `{}`", path.display()))?;
+            let file = path.strip_prefix(REPO_PATH.as_path()).unwrap_or(&path);
+            merge_fragment(table, fragment, file, &mut included);
+        }
+    }
+    Ok(included)
+}
+
+/// Merge the `fragment` included from `file` into `table`, noting what was
+/// merged in `included`. The values of `table` win.
+fn merge_fragment(
+    table: &mut toml::Table,
+    fragment: toml::Table,
+    file: &Path,
+    included: &mut Vec<Included>,
+) {
+    let note = |section: &str, key: Option<&str>, value: &Value| Included {
+        section: section.to_string(),
+        key: key.map(str::to_string),
+        file: file.to_path_buf(),
+        value: Some(value.clone()),
+    };
+    for (section, value) in fragment {
+        match (table.get_mut(&section), value) {
+            (Some(Value::Table(existing)), Value::Table(values)) => {
+                for (key, value) in values {
+                    if !existing.contains_key(&key) {
+                        included.push(note(&section, Some(&key), &value));
+                        existing.insert(key, value);
+                    }
+                }
+            }
+            (None, Value::Table(values)) => {
+                let keys = values.iter().map(|(key, value)| note(&section, Some(key), value));
+                included.extend(keys);
+                table.insert(section, Value::Table(values));
+            }
+            (None, value) => {
+                included.push(note(&section, None, &value));
+                table.insert(section, value);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Remove the `included` values from the saved `table`, leaving the ones
+/// added since. A value changed since is kept, and wins over the included
+/// file. A value removed since cannot be saved, as the included file would
+/// bring it back.
+fn strip_included(table: &mut toml::Table, included: &[Included]) -> Result<()> {
+    for included in included.iter() {
+        let current = included.get(table);
+        if current.is_none() && included.value.is_some() {
+            bail!(
+                "`{}` comes from the included `{}`, remove it there",
+                included.name(),
+                included.file.display()
+            );
+        }
+        if current != included.value.as_ref() {
+            continue;
+        }
+        match (&included.key, table.get_mut(&included.section)) {
+            (Some(key), Some(Value::Table(section))) => {
+                section.remove(key);
+            }
+            (None, _) => {
+                table.remove(&included.section);
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Parse the config, skipping the broken items instead of failing.
+fn parse_config(content: &str) -> Result<Config> {
+    let mut table: toml::Table = toml::from_str(content)?;
+    let doc = ImDocument::parse(content)?;
+    let included = include(&mut table)?;
+    let mut broken_items = Vec::new();
+    for group in ["sync_group", "backup_group"] {
+        let Some(Value::Table(items)) = table.get_mut(group) else {
+            continue;
+        };
+        let keys = items.keys().cloned().collect::<Vec<_>>();
+        for key in keys {
+            let checked = match group {
+                "sync_group" => check_item::<SyncFile>(&doc, group, &key, &items[&key]),
+                _ => check_item::<BackupFile>(&doc, group, &key, &items[&key]),
+            };
+            if let Err(e) = checked {
+                let value = items.remove(&key).unwrap();
+                broken_items.push(BrokenItem {
+                    group,
+                    key,
+                    value,
+                    error: format!("{:#}", e),
+                });
+            }
+        }
+    }
+    let mut unknown_keys = Vec::new();
+    let mut config: Config = deserialize_at(&doc, &[], Value::Table(table), |steps| {
+        unknown_keys.push(locate(&doc, &steps));
+    })?;
+    if let Err(e) = set_identity(&config.device.identity) {
+        config.identity_error = Some(format!("{:#}", e));
+    }
+    set_aliases(&config.aliases);
+    config.broken_items = broken_items;
+    // Compared as saved, with the defaults filled in.
+    let saved = saved_table(&config)?;
+    config.included = included
+        .into_iter()
+        .map(|included| Included {
+            value: included.get(&saved).cloned(),
+            ..included
+        })
+        .collect();
+    config.unknown_keys = unknown_keys;
+    Ok(config)
+}
+
+/// Fail if the name of this device could not be resolved from
+/// `device.identity`.
+pub fn check_identity() -> Result<()> {
+    match CONFIG.read().unwrap().identity_error.as_ref() {
+        Some(e) => bail!("invalid `device.identity`: {}", e),
+        None => Ok(()),
+    }
+}
+
+/// Log the problems found when loading the config. Called once the logger is
+/// ready, since the logger itself is configured by the config.
+pub fn report_config_errors() {
+    let config = CONFIG.read().unwrap();
+    if let Some(e) = config.load_error.as_ref() {
+        error!("failed to load {}, using the default config: {}", CONFIG_NAME, e);
+    }
+    for item in config.broken_items.iter() {
+        error!(
+            "skipped the broken item `{}` in {}: {}",
+            item.key, item.group, item.error
+        );
+    }
+    for key in config.unknown_keys.iter() {
+        warn!("unknown key in {}, ignored: {}", CONFIG_NAME, key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locate() {
+        let content = "device_name = \"a\"\n\n[daemon]\ninterval = 5\n";
+        let doc = ImDocument::parse(content).unwrap();
+        let steps = [Step::Key("daemon".into()), Step::Key("interval".into())];
+        assert_eq!(locate(&doc, &steps), "line 4, daemon.interval");
+        let steps = [Step::Key("sync_group".into()), Step::Key("a.b".into())];
+        assert_eq!(locate(&doc, &steps), "sync_group.\"a.b\"");
+    }
+
+    #[test]
+    fn test_path_on() {
+        let content = "device_name = \"a\"\n[aliases]\nlaptop = \"DESKTOP-1\"\n\
+                       [sync_group.notes]\nis_hardlink = false\n\
+                       [sync_group.notes.path_on_devices]\nlaptop = \"/notes\"\n\
+                       [backup_group]\n";
+        let config = parse_config(content).unwrap();
+        assert_eq!(config.device_name("laptop"), "DESKTOP-1");
+        assert_eq!(config.device_name("DESKTOP-1"), "DESKTOP-1");
+        assert_eq!(config.device_label("DESKTOP-1"), "laptop");
+        let notes = &config.sync_group.0[Path::new("notes")];
+        assert_eq!(config.path_on(notes, "DESKTOP-1"), Some(&PathBuf::from("/notes")));
+        assert_eq!(config.path_on(notes, "DESKTOP-2"), None);
+    }
+
+    #[test]
+    fn test_parse_error_location() {
+        let content = "device_name = \"a\"\n[sync_group]\n[backup_group]\n[daemon]\ninterval = 5\n";
+        let e = format!("{:#}", parse_config(content).unwrap_err());
+        assert!(e.starts_with("line 5, daemon.interval: invalid type"), "{}", e);
+    }
+
+    #[test]
+    fn test_include() {
+        let mut table: toml::Table =
+            toml::from_str("remote = \"a\"\n[sync_group.mine]\nis_hardlink = true\n").unwrap();
+        let fragment = toml::from_str(
+            "remote = \"b\"\nmemory_budget = 1\n[sync_group.theirs]\nis_hardlink = false\n\
+             [services]\nx = \"x.service\"\n",
+        )
+        .unwrap();
+        let mut included = Vec::new();
+        merge_fragment(&mut table, fragment, Path::new("items/a.toml"), &mut included);
+        // This file wins.
+        assert_eq!(table["remote"].as_str(), Some("a"));
+        assert_eq!(table["memory_budget"].as_integer(), Some(1));
+        assert!(table["sync_group"].get("theirs").is_some());
+
+        // An entry added to a section that came from the include is kept.
+        table["services"]
+            .as_table_mut()
+            .unwrap()
+            .insert("y".into(), Value::String("y.service".into()));
+        strip_included(&mut table, &included).unwrap();
+        assert!(table.get("memory_budget").is_none());
+        assert!(table["sync_group"].get("theirs").is_none());
+        assert!(table["sync_group"].get("mine").is_some());
+        assert!(table["services"].get("x").is_none());
+        assert_eq!(table["services"]["y"].as_str(), Some("y.service"));
+    }
+
+    #[test]
+    fn test_change_included() {
+        let mut table: toml::Table = toml::from_str("remote = \"a\"\n[sync_group]\n").unwrap();
+        let fragment = toml::from_str(
+            "[sync_group.notes]\nis_hardlink = false\n[sync_group.dots]\nis_hardlink = false\n",
+        )
+        .unwrap();
+        let mut included = Vec::new();
+        merge_fragment(&mut table, fragment, Path::new("items/a.toml"), &mut included);
+        let config = Config {
+            included: included.clone(),
+            ..Default::default()
+        };
+        assert_eq!(config.included_item(Path::new("notes")), Some(Path::new("items/a.toml")));
+        assert_eq!(config.included_item(Path::new("other")), None);
+
+        // An edited item is kept, and wins over the included file.
+        let mut saved = table.clone();
+        saved["sync_group"]["notes"]["is_hardlink"] = Value::Boolean(true);
+        strip_included(&mut saved, &included).unwrap();
+        assert_eq!(saved["sync_group"]["notes"]["is_hardlink"].as_bool(), Some(true));
+        assert!(saved["sync_group"].get("dots").is_none());
+
+        // An untracked item would come back, so is refused.
+        let mut untracked = table.clone();
+        untracked["sync_group"].as_table_mut().unwrap().remove("notes");
+        let e = strip_included(&mut untracked, &included).unwrap_err();
+        assert!(e.to_string().contains("sync_group.\"notes\""), "{}", e);
+        assert!(e.to_string().contains("items/a.toml"), "{}", e);
+
+        // So is a moved one, which would be there twice.
+        let mut moved = table.clone();
+        let items = moved["sync_group"].as_table_mut().unwrap();
+        let notes = items.remove("notes").unwrap();
+        items.insert("archive/notes".into(), notes);
+        assert!(strip_included(&mut moved, &included).is_err());
+    }
 }
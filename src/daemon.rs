@@ -0,0 +1,146 @@
+use std::{
+    hash::{BuildHasher, RandomState},
+    time::Duration,
+};
+
+use anyhow::{ensure, Context, Result};
+use log::{debug, error, info};
+
+use crate::{
+    config::{ConflictPolicy, CONFIG},
+    lock::lock_repo,
+    metrics,
+    notification::{self, Category},
+    shutdown::Shutdown,
+    sync::{sync, sync_pull, sync_push, SyncOptions},
+    verify,
+    web::{self, Action},
+    webhook,
+};
+
+/// Sync in both directions every `daemon.interval`: pull and restore the
+/// remote changes, then collect, commit and push the local ones. A failed sync
+/// is logged and retried in the next cycle. Runs until a shutdown is
+/// requested, finishing the current cycle first. With `web_addr`, a local web
+/// page shows the status and can start a cycle at once. With `shadow` or
+/// `daemon.shadow`, the restores are only recorded and nothing is collected.
+pub async fn daemon(web_addr: Option<&str>, shadow: bool) -> Result<()> {
+    let mut shutdown = Shutdown::new()?;
+    notification::enable();
+    let config = CONFIG.read().unwrap().daemon.clone();
+    let interval =
+        humantime::parse_duration(&config.interval).context("invalid `daemon.interval`")?;
+    let jitter = config
+        .jitter
+        .as_deref()
+        .map(|jitter| parse_jitter(jitter, interval))
+        .transpose()
+        .context("invalid `daemon.jitter`")?
+        .unwrap_or_default();
+    if let Some(addr) = config.metrics_addr.as_deref() {
+        metrics::serve(addr)?;
+    }
+    if let Some(addr) = web_addr {
+        web::serve(addr)?;
+    }
+    let shadow = shadow || config.shadow;
+    info!("syncing every {}", humantime::format_duration(interval));
+    if shadow {
+        info!("shadow mode, nothing is restored, collected or pushed");
+    }
+    let mut action = Action::Sync;
+    loop {
+        let lock = lock_repo(true)?;
+        let options = SyncOptions {
+            restart: true,
+            shadow,
+            non_interactive: Some(ConflictPolicy::Stop),
+            ..Default::default()
+        };
+        let result = match action {
+            Action::Sync => sync(options).await,
+            Action::Collect if shadow => Ok(()),
+            Action::Collect => sync_push(true).await,
+            Action::Restore => sync_pull(options).await,
+        };
+        web::record_cycle(action, &result);
+        webhook::ping(&result);
+        metrics::record_sync(result.is_ok());
+        if let Err(e) = result {
+            error!("{:?}", e);
+            notification::notify(Category::Error, "gsb sync failed", &format!("{:#}", e));
+        }
+        if config.verify_per_cycle > 0 {
+            if let Err(e) = verify::verify_some(config.verify_per_cycle).await {
+                error!("failed to verify: {:?}", e);
+            }
+        }
+        drop(lock);
+        let wait = jittered(interval, jitter);
+        debug!("next sync in {}", humantime::format_duration(wait));
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => action = Action::Sync,
+            requested = web::requested() => action = requested,
+            _ = shutdown.requested() => break,
+        }
+    }
+    info!("shutting down");
+    Ok(())
+}
+
+/// Parse a jitter of `10%` of `interval`, up to `100%`, or a time like `30s`.
+fn parse_jitter(jitter: &str, interval: Duration) -> Result<Duration> {
+    match jitter.trim().strip_suffix('%') {
+        Some(percent) => {
+            let percent = percent.trim().parse::<f64>()?;
+            ensure!((0.0..=100.0).contains(&percent), "`{}` is not within 0% to 100%", jitter);
+            Ok(interval.mul_f64(percent / 100.0))
+        }
+        None => Ok(humantime::parse_duration(jitter)?),
+    }
+}
+
+/// `interval` moved randomly by up to `jitter` either way.
+fn jittered(interval: Duration, jitter: Duration) -> Duration {
+    if jitter.is_zero() {
+        return interval;
+    }
+    // A random number in [0, 1), without pulling in a crate for it.
+    let random = (RandomState::new().hash_one(0u8) >> 11) as f64 / (1u64 << 53) as f64;
+    (interval + jitter.mul_f64(2.0 * random)).saturating_sub(jitter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_jitter() {
+        let interval = Duration::from_secs(600);
+        let parse = |jitter| parse_jitter(jitter, interval).unwrap();
+        assert_eq!(parse("10%"), Duration::from_secs(60));
+        assert_eq!(parse(" 5 % "), Duration::from_secs(30));
+        assert_eq!(parse("0%"), Duration::ZERO);
+        assert_eq!(parse("100%"), interval);
+        assert_eq!(parse("30s"), Duration::from_secs(30));
+        assert_eq!(parse("1m 30s"), Duration::from_secs(90));
+        for invalid in ["", "%", "ten%", "-10%", "101%", "NaN%", "inf%", "30", "30 parsecs"] {
+            assert!(parse_jitter(invalid, interval).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_jittered() {
+        let interval = Duration::from_secs(600);
+        assert_eq!(jittered(interval, Duration::ZERO), interval);
+        let jitter = Duration::from_secs(60);
+        for _ in 0..1000 {
+            let next = jittered(interval, jitter);
+            assert!(next >= interval - jitter && next < interval + jitter, "{:?}", next);
+        }
+        // A jitter as large as the interval never goes below zero.
+        for _ in 0..1000 {
+            assert!(jittered(interval, interval) < interval * 2);
+        }
+    }
+}
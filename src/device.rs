@@ -0,0 +1,163 @@
+//! `gsb device`: the names this device is known by in the config.
+
+use std::{collections::BTreeMap, path::Path, sync::RwLock};
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::{
+    cli::{json_output, AliasCommand},
+    commit::commit,
+    config::{save_config, set_aliases, CONFIG, CONFIG_NAME},
+    git_command::git,
+};
+
+/// The identity of this device resolved from `device.identity`, set when the
+/// config is loaded.
+static IDENTITY: RwLock<Option<String>> = RwLock::new(None);
+
+/// The name of this device in the config, from the source chosen by
+/// `device.identity`. The device name of the OS until the config is loaded.
+pub fn devicename() -> String {
+    IDENTITY
+        .read()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(whoami::devicename)
+}
+
+/// Resolve `identity` and make it the name of this device: `devicename`,
+/// `hostname`, `machine-uid`, `env:<VAR>` or `file:<path>`.
+pub fn set_identity(identity: &str) -> Result<()> {
+    let name = match (identity, identity.split_once(':')) {
+        ("devicename", _) => whoami::devicename(),
+        ("hostname", _) => whoami::fallible::hostname().context("failed to get the hostname")?,
+        ("machine-uid", _) => {
+            machine_uid::get().map_err(|e| anyhow!("failed to get the machine uid: {}", e))?
+        }
+        (_, Some(("env", var))) => {
+            std::env::var(var).with_context(|| format!("`{}` is not set", var))?
+        }
+        (_, Some(("file", path))) => std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read the device identity from `{}`", path))?
+            .trim()
+            .to_string(),
+        _ => bail!("unknown device identity `{}`", identity),
+    };
+    if name.is_empty() {
+        bail!("the device identity `{}` is empty", identity);
+    }
+    *IDENTITY.write().unwrap() = Some(name);
+    Ok(())
+}
+
+/// Add, list or remove the aliases of the `aliases` table, then commit the
+/// config.
+pub fn alias(command: &AliasCommand) -> Result<()> {
+    match command {
+        AliasCommand::Add { name, device } => {
+            let device = device.clone().unwrap_or_else(devicename);
+            let mut config = CONFIG.write().unwrap();
+            if let Some(existing) = config.aliases.get(name) {
+                bail!("`{}` is already an alias of `{}`", name, existing);
+            }
+            config.aliases.insert(name.clone(), device.clone());
+            info!("`{}` is now an alias of `{}`", name, device);
+        }
+        AliasCommand::List => {
+            let config = CONFIG.read().unwrap();
+            if json_output() {
+                println!("{}", serde_json::to_string(&config.aliases)?);
+                return Ok(());
+            }
+            for (name, device) in config.aliases.iter() {
+                let this = if *device == devicename() { " (this device)" } else { "" };
+                println!("{} = {}{}", name, device, this);
+            }
+            return Ok(());
+        }
+        AliasCommand::Rm { name } => {
+            let mut config = CONFIG.write().unwrap();
+            if config.aliases.remove(name).is_none() {
+                bail!("no alias `{}`", name);
+            }
+            info!("removed the alias `{}`", name);
+        }
+    }
+    set_aliases(&CONFIG.read().unwrap().aliases);
+    save_config()?;
+    git(["add", CONFIG_NAME])?;
+    commit("alias", std::iter::empty::<&Path>())
+}
+
+/// A device referenced by the config.
+#[derive(Serialize, Debug, Default)]
+struct KnownDevice {
+    aliases: Vec<String>,
+    /// The items of the sync group with a path on the device.
+    items: usize,
+    profile: Option<String>,
+    this: bool,
+}
+
+/// List the devices referenced by the paths of the sync group, the `profiles`
+/// and the `aliases`, and warn about the aliases of devices referenced nowhere
+/// else.
+pub fn devices() -> Result<()> {
+    let config = CONFIG.read().unwrap();
+    let resolve = |name: &String| config.device_name(name);
+    let mut devices = BTreeMap::<String, KnownDevice>::new();
+    for info in config.sync_group.0.values() {
+        for name in info.path_on_devices.keys() {
+            devices.entry(resolve(name)).or_default().items += 1;
+        }
+    }
+    for (name, profile) in config.profiles.iter() {
+        devices.entry(resolve(name)).or_default().profile = Some(profile.clone());
+    }
+    let mut unreferenced = Vec::new();
+    for (alias, device) in config.aliases.iter() {
+        match devices.get_mut(device) {
+            Some(known) => known.aliases.push(alias.clone()),
+            None => unreferenced.push((alias, device)),
+        }
+    }
+    let this = devicename();
+    if let Some(known) = devices.get_mut(&this) {
+        known.this = true;
+    }
+    if json_output() {
+        let unreferenced = unreferenced.iter().map(|(alias, _)| alias).collect::<Vec<_>>();
+        let output = serde_json::json!({
+            "devices": devices,
+            "unreferenced_aliases": unreferenced,
+        });
+        println!("{}", output);
+        return Ok(());
+    }
+    for (name, known) in devices.iter() {
+        let aliases = if known.aliases.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", known.aliases.join(", "))
+        };
+        let profile = known
+            .profile
+            .as_ref()
+            .map(|profile| format!(", profile `{}`", profile))
+            .unwrap_or_default();
+        let this = if known.this { ", this device" } else { "" };
+        println!("{}{}: {} items{}{}", name, aliases, known.items, profile, this);
+    }
+    if !devices.contains_key(&this) {
+        info!("this device `{}` is not referenced by the config", this);
+    }
+    for (alias, device) in unreferenced {
+        warn!(
+            "the alias `{}` stands for `{}`, which no item or profile references",
+            alias, device
+        );
+    }
+    Ok(())
+}
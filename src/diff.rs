@@ -0,0 +1,128 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use anyhow::Result;
+use log::warn;
+use serde::Serialize;
+
+use crate::{
+    cli::json_output,
+    config::CONFIG,
+    git_command::{backup_branch, git, REMOTE_NAME},
+    manifest::{item_hashes, last_manifest_of},
+};
+
+/// A path that differs between two devices.
+#[derive(Serialize, Debug, Clone)]
+struct Divergence {
+    path: PathBuf,
+    difference: String,
+}
+
+/// Show where the states of two devices have diverged: the items of the sync
+/// group tracked on only one of them, at other paths, or with other content in
+/// the last manifests they wrote, and the backed up files whose content
+/// differs. `a` and `b` are device names or aliases.
+pub fn diff_devices(a: &str, b: &str) -> Result<()> {
+    let config = CONFIG.read().unwrap().clone();
+    let (a, b) = (config.device_name(a), config.device_name(b));
+    let (a_label, b_label) = (config.device_label(&a), config.device_label(&b));
+
+    // Without a signing key no manifest is written, see `write_manifest`.
+    let manifest_a = last_manifest_of(&a)?;
+    let manifest_b = last_manifest_of(&b)?;
+    for (manifest, label) in [(&manifest_a, &a_label), (&manifest_b, &b_label)] {
+        if manifest.is_none() {
+            warn!("{} wrote no manifest, the content of the sync group is not compared", label);
+        }
+    }
+    let mut sync_group = Vec::new();
+    for (item, file) in config.sync_group.0.iter() {
+        let difference = match (config.path_on(file, &a), config.path_on(file, &b)) {
+            (Some(_), None) => format!("only on {}", a_label),
+            (None, Some(_)) => format!("only on {}", b_label),
+            (Some(path_a), Some(path_b)) if path_a != path_b => format!(
+                "at `{}` on {}, `{}` on {}",
+                path_a.display(),
+                a_label,
+                path_b.display(),
+                b_label
+            ),
+            (Some(_), Some(_)) => match (&manifest_a, &manifest_b) {
+                (Some(manifest_a), Some(manifest_b))
+                    if item_hashes(manifest_a, item, file) != item_hashes(manifest_b, item, file) =>
+                {
+                    "differs".to_string()
+                }
+                _ => continue,
+            },
+            (None, None) => continue,
+        };
+        sync_group.push(Divergence {
+            path: item.clone(),
+            difference,
+        });
+    }
+
+    let mut backup_group = Vec::new();
+    let files_a = backup_files(&a)?;
+    let files_b = backup_files(&b)?;
+    for (path, hash) in files_a.iter() {
+        let difference = match files_b.get(path) {
+            None => format!("only on {}", a_label),
+            Some(other) if other != hash => "differs".to_string(),
+            _ => continue,
+        };
+        backup_group.push(Divergence {
+            path: path.clone(),
+            difference,
+        });
+    }
+    for path in files_b.keys().filter(|path| !files_a.contains_key(*path)) {
+        backup_group.push(Divergence {
+            path: path.clone(),
+            difference: format!("only on {}", b_label),
+        });
+    }
+
+    if json_output() {
+        println!(
+            "{}",
+            serde_json::json!({ "sync_group": sync_group, "backup_group": backup_group })
+        );
+        return Ok(());
+    }
+    for (title, divergences) in [("Sync group", sync_group), ("Backup group", backup_group)] {
+        println!("{}:", title);
+        for divergence in divergences {
+            println!("  `{}` {}", divergence.path.display(), divergence.difference);
+        }
+    }
+    Ok(())
+}
+
+/// The blob hashes of all files in the backup branch of `device`, falling back
+/// to the remote branch if it is not checked out locally.
+fn backup_files(device: &str) -> Result<BTreeMap<PathBuf, String>> {
+    let branch = backup_branch(device);
+    let remote_branch = format!("{}/{}", REMOTE_NAME, branch);
+    let Some(rev) = [branch, remote_branch].into_iter().find(|rev| {
+        !git(["rev-parse", "--verify", "--quiet", rev])
+            .unwrap_or_default()
+            .trim()
+            .is_empty()
+    }) else {
+        warn!("no backup branch found for device `{}`", device);
+        return Ok(BTreeMap::new());
+    };
+
+    // Lines look like `<mode> blob <hash>\t<path>`.
+    let files = git(["ls-tree", "-r", &rev])?
+        .lines()
+        .filter_map(|line| {
+            let (info, path) = line.split_once('\t')?;
+            let hash = info.split_whitespace().nth(2)?;
+            Some((PathBuf::from(path), hash.to_string()))
+        })
+        .collect();
+    Ok(files)
+}
@@ -0,0 +1,143 @@
+//! `gsb doctor`: check the whole setup in one pass and report every problem,
+//! instead of stopping at the first one in the middle of a sync.
+
+use std::path::Path;
+
+use anyhow::{bail, ensure, Context, Result};
+use serde::Serialize;
+
+use crate::{
+    cli::json_output,
+    config::{Config, Getable, CONFIG, CONFIG_NAME},
+    git_command::{git_checked, REMOTE_NAME, REPO_PATH},
+    mounts::{hardlink_usable, mount_of},
+    workspace::workspace_root,
+};
+
+#[derive(Serialize, Debug)]
+struct Check {
+    name: String,
+    ok: bool,
+    /// Why the check failed, if it did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Run all checks, printing one line per check, or a list of them with
+/// `--output json`. Fails if any check failed.
+pub fn doctor() -> Result<()> {
+    let config = CONFIG.read().unwrap().clone();
+    let mut checks = Vec::new();
+    let mut check = |name: &str, result: Result<()>| {
+        checks.push(Check {
+            name: name.to_string(),
+            ok: result.is_ok(),
+            error: result.err().map(|e| format!("{:#}", e)),
+        })
+    };
+    check(CONFIG_NAME, check_config(&config));
+    for (item, info) in config.sync_group.0.iter() {
+        let name = format!("sync item `{}`", item.display());
+        match info.get_on_device() {
+            Some(path) => check(&name, check_item(item, path, info.is_hardlink)),
+            None => check(&name, Ok(())),
+        }
+    }
+    for (item, info) in config.backup_group.0.iter() {
+        let name = format!("backup item `{}`", item.display());
+        match info.workspace.as_deref() {
+            Some(workspace) => check(&name, check_workspace(workspace)),
+            None => check(&name, check_item(item, &info.path_on_device, info.is_hardlink)),
+        }
+    }
+    check("remote", check_remote());
+
+    if json_output() {
+        println!("{}", serde_json::to_string(&checks)?);
+    } else {
+        for check in checks.iter() {
+            match check.error.as_ref() {
+                None => println!("ok    {}", check.name),
+                Some(e) => println!("FAIL  {}: {}", check.name, e),
+            }
+        }
+    }
+    let failed = checks.iter().filter(|check| !check.ok).count();
+    ensure!(failed == 0, "{} checks failed", failed);
+    Ok(())
+}
+
+/// The problems found when loading the config, and the times it holds.
+fn check_config(config: &Config) -> Result<()> {
+    if let Some(e) = config.load_error.as_ref() {
+        bail!("failed to load: {}", e);
+    }
+    if let Some(item) = config.broken_items.first() {
+        bail!("broken item `{}` in {}: {}", item.key, item.group, item.error);
+    }
+    if let Some(key) = config.unknown_keys.first() {
+        bail!("unknown key, likely misspelled: {}", key);
+    }
+    let durations = [
+        ("daemon.interval", Some(&config.daemon.interval)),
+        ("item_timeout", config.item_timeout.as_ref()),
+        ("archive_after", config.archive_after.as_ref()),
+        ("attic_retention", config.attic_retention.as_ref()),
+        ("watch.min_commit_interval", config.watch.min_commit_interval.as_ref()),
+    ];
+    let hooks = config
+        .hooks
+        .iter()
+        .map(|(name, hook)| (name.as_str(), hook.timeout.as_ref()));
+    for (key, duration) in durations.into_iter().chain(hooks) {
+        if let Some(duration) = duration {
+            humantime::parse_duration(duration)
+                .with_context(|| format!("invalid time `{}` of `{}`", duration, key))?;
+        }
+    }
+    Ok(())
+}
+
+/// The path of `item` on this device exists, and a hardlink item can actually
+/// be hardlinked to the repository.
+fn check_item(item: &Path, path: &Path, is_hardlink: bool) -> Result<()> {
+    ensure!(path.exists(), "`{}` does not exist", path.display());
+    if !is_hardlink {
+        return Ok(());
+    }
+    let in_repo = REPO_PATH.join(item);
+    let (Some(mount), Some(repo_mount)) = (mount_of(path), mount_of(&in_repo)) else {
+        return Ok(());
+    };
+    ensure!(
+        mount.point == repo_mount.point,
+        "hardlink item on `{}`, but the repository is on `{}`",
+        mount.point.display(),
+        repo_mount.point.display()
+    );
+    ensure!(
+        hardlink_usable(path, &in_repo),
+        "the filesystem of `{}` does not support reliable hardlinks, it is copied instead",
+        path.display()
+    );
+    Ok(())
+}
+
+/// The root of `workspace` exists.
+fn check_workspace(workspace: &str) -> Result<()> {
+    let root = workspace_root(workspace)?;
+    ensure!(root.is_dir(), "workspace root `{}` does not exist", root.display());
+    Ok(())
+}
+
+/// The remote can be reached with the configured credentials.
+fn check_remote() -> Result<()> {
+    let remotes = git_checked(["remote"])?;
+    ensure!(
+        remotes.lines().any(|remote| remote.trim() == REMOTE_NAME),
+        "no remote `{}` configured",
+        REMOTE_NAME
+    );
+    git_checked(["ls-remote", "--heads", REMOTE_NAME])?;
+    Ok(())
+}
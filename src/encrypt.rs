@@ -0,0 +1,486 @@
+//! Items with `encrypt` set are stored encrypted with `age`, so the remote only
+//! sees ciphertext. With `encrypt = "contents"` every file is stored as
+//! `<file>.age`. With `encrypt = "names"` the names and the directory structure
+//! are hidden too: the files are stored flat as `<item>.enc/<id>.age`, and
+//! their paths are kept in the encrypted `<item>.enc/index.age`. Restore
+//! decrypts them with the identity of `[encryption]`.
+
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::UNIX_EPOCH,
+};
+
+use anyhow::{ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    archive::Extracted,
+    config::{Config, Encryption, EncryptionConfig, CONFIG},
+    git_command::REPO_PATH,
+    state::state_dir,
+    utils::{item_files, CopyStats},
+};
+
+const EXTENSION: &str = ".age";
+const DIR_EXTENSION: &str = ".enc";
+const INDEX_NAME: &str = "index.age";
+/// The context of the key of the stored names, derived from the identity.
+const NAMES_CONTEXT: &str = "gsb encrypted file names v1";
+
+/// The files of an item with encrypted names, by the id they are stored as.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+struct Index {
+    files: BTreeMap<String, IndexEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct IndexEntry {
+    /// Relative to the item, empty for an item that is a file.
+    path: PathBuf,
+    /// The modification time of the original in seconds, so a change of any
+    /// file changes the index too.
+    modified: u64,
+}
+
+/// The name of the encrypted `path` in the repository.
+pub fn encrypted_name(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(EXTENSION);
+    PathBuf::from(name)
+}
+
+/// The directory of the item with encrypted names.
+pub fn encrypted_dir(item: &Path) -> PathBuf {
+    let mut name = item.as_os_str().to_owned();
+    name.push(DIR_EXTENSION);
+    PathBuf::from(name)
+}
+
+/// The path the encrypted file at `path` in the repository stands for, if it
+/// belongs to an item with `encrypt = "contents"`.
+pub fn encrypted_file(config: &Config, path: &Path) -> Option<PathBuf> {
+    let original = Path::new(path.to_str()?.strip_suffix(EXTENSION)?);
+    let mut items = config.sync_group.0.iter();
+    items
+        .any(|(item, info)| {
+            info.encrypt == Some(Encryption::Contents) && original.starts_with(item)
+        })
+        .then(|| original.to_path_buf())
+}
+
+/// The item with `encrypt = "names"` that `path` in the repository belongs to.
+pub fn encrypted_item(config: &Config, path: &Path) -> Option<PathBuf> {
+    let mut items = config.sync_group.0.iter();
+    items
+        .find(|(item, info)| {
+            info.encrypt == Some(Encryption::Names) && path.starts_with(encrypted_dir(item))
+        })
+        .map(|(item, _)| item.clone())
+}
+
+/// Whether `path` is the index of an item with encrypted names.
+pub fn is_index(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name == INDEX_NAME)
+}
+
+/// Encrypt the files of the item at `from` into the repository. A file is
+/// skipped if it did not change since it was encrypted, as `age` encrypts the
+/// same content differently every time.
+pub fn encrypt_item(from: &Path, item: &Path, encryption: Encryption) -> Result<CopyStats> {
+    let keys = CONFIG.read().unwrap().encryption.clone();
+    encrypt_into(&REPO_PATH, &keys, from, item, encryption)
+}
+
+/// [`encrypt_item`] into the repository at `repo`.
+fn encrypt_into(
+    repo: &Path,
+    keys: &EncryptionConfig,
+    from: &Path,
+    item: &Path,
+    encryption: Encryption,
+) -> Result<CopyStats> {
+    match encryption {
+        Encryption::Contents => encrypt_contents(repo, keys, from, item),
+        Encryption::Names => encrypt_names(repo, keys, from, item),
+    }
+}
+
+fn encrypt_contents(
+    repo: &Path,
+    keys: &EncryptionConfig,
+    from: &Path,
+    item: &Path,
+) -> Result<CopyStats> {
+    let recipients = recipients(keys)?;
+    let mut stats = CopyStats::default();
+    for (source, target) in item_files(from, &repo.join(item))? {
+        // Collected before the item was encrypted.
+        if target.is_file() {
+            std::fs::remove_file(&target)?;
+        }
+        let target = encrypted_name(&target);
+        let meta = source.metadata()?;
+        let modified = meta.modified()?;
+        let current = target.metadata().and_then(|meta| meta.modified());
+        if current.is_ok_and(|current| current == modified) {
+            stats.files_skipped += 1;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        age_file(["--encrypt", "-R"], recipients, &source, &target)?;
+        File::options()
+            .write(true)
+            .open(&target)?
+            .set_modified(modified)?;
+        stats.files_copied += 1;
+        stats.bytes_copied += meta.len();
+    }
+    Ok(stats)
+}
+
+fn encrypt_names(
+    repo: &Path,
+    keys: &EncryptionConfig,
+    from: &Path,
+    item: &Path,
+) -> Result<CopyStats> {
+    let recipients = recipients(keys)?;
+    let key = names_key(keys)?;
+    // Collected before the item was encrypted, its names are in the clear.
+    let plain = repo.join(item);
+    if plain.is_dir() {
+        std::fs::remove_dir_all(&plain)?;
+    } else if plain.is_file() {
+        std::fs::remove_file(&plain)?;
+    }
+    let dir = repo.join(encrypted_dir(item));
+    std::fs::create_dir_all(&dir)?;
+    let current = read_index(keys, &dir)?;
+    let mut index = Index::default();
+    let mut stats = CopyStats::default();
+    for (source, path) in item_files(from, Path::new(""))? {
+        let meta = source.metadata()?;
+        let modified = meta.modified()?.duration_since(UNIX_EPOCH)?.as_secs();
+        let hash = blake3::keyed_hash(&key, path.to_string_lossy().as_bytes());
+        let id = hash.to_hex().as_str()[..32].to_string();
+        let entry = IndexEntry { path, modified };
+        let target = dir.join(format!("{}{}", id, EXTENSION));
+        if current.files.get(&id) == Some(&entry) && target.exists() {
+            stats.files_skipped += 1;
+        } else {
+            age_file(["--encrypt", "-R"], recipients, &source, &target)?;
+            stats.files_copied += 1;
+            stats.bytes_copied += meta.len();
+        }
+        index.files.insert(id, entry);
+    }
+    // The files no longer in the item.
+    for entry in std::fs::read_dir(&dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let id = name.strip_suffix(EXTENSION).unwrap_or(&name);
+        if name != INDEX_NAME && !index.files.contains_key(id) {
+            std::fs::remove_file(entry.path())?;
+        }
+    }
+    if index != current {
+        let content = toml::to_string(&index)?;
+        let mut args = vec![
+            OsStr::new("--encrypt"),
+            OsStr::new("-R"),
+            recipients.as_os_str(),
+        ];
+        let index_path = dir.join(INDEX_NAME);
+        args.extend([OsStr::new("-o"), index_path.as_os_str()]);
+        age(args, Some(content.into_bytes()))?;
+    }
+    Ok(stats)
+}
+
+/// Decrypt the encrypted `original` (relative to the repository) into a
+/// temporary directory of `.gsb/`, returning the decrypted file. The
+/// directory is removed with the returned guard.
+pub fn decrypt(original: &Path) -> Result<(PathBuf, Extracted)> {
+    let keys = CONFIG.read().unwrap().encryption.clone();
+    decrypt_from(&REPO_PATH, &keys, &state_dir()?.join("decrypt"), original)
+}
+
+/// [`decrypt`] from the repository at `repo` into a directory of `scratch`.
+fn decrypt_from(
+    repo: &Path,
+    keys: &EncryptionConfig,
+    scratch: &Path,
+    original: &Path,
+) -> Result<(PathBuf, Extracted)> {
+    let identity = identity(keys)?;
+    let dir = temporary_dir(scratch, original)?;
+    let file = dir.join(original.file_name().unwrap_or_default());
+    let encrypted = repo.join(encrypted_name(original));
+    age_file(["--decrypt", "-i"], identity, &encrypted, &file)?;
+    Ok((file, Extracted(dir)))
+}
+
+/// Decrypt the item with encrypted names into a temporary directory of
+/// `.gsb/`, returning the decrypted item. The directory is removed with the
+/// returned guard.
+pub fn decrypt_item(item: &Path) -> Result<(PathBuf, Extracted)> {
+    let keys = CONFIG.read().unwrap().encryption.clone();
+    decrypt_item_from(&REPO_PATH, &keys, &state_dir()?.join("decrypt"), item)
+}
+
+/// [`decrypt_item`] from the repository at `repo` into a directory of
+/// `scratch`.
+fn decrypt_item_from(
+    repo: &Path,
+    keys: &EncryptionConfig,
+    scratch: &Path,
+    item: &Path,
+) -> Result<(PathBuf, Extracted)> {
+    let identity = identity(keys)?;
+    let encrypted = repo.join(encrypted_dir(item));
+    let index = read_index(keys, &encrypted)?;
+    let dir = temporary_dir(scratch, item)?;
+    let root = dir.join(item.file_name().unwrap_or_default());
+    for (id, entry) in index.files.iter() {
+        let target = if entry.path.as_os_str().is_empty() {
+            root.clone()
+        } else {
+            root.join(&entry.path)
+        };
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let source = encrypted.join(format!("{}{}", id, EXTENSION));
+        age_file(["--decrypt", "-i"], identity, &source, &target)
+            .with_context(|| format!("failed to decrypt `{}`", entry.path.display()))?;
+    }
+    Ok((root, Extracted(dir)))
+}
+
+/// A new empty directory of `scratch` to decrypt `path` into.
+fn temporary_dir(scratch: &Path, path: &Path) -> Result<PathBuf> {
+    let dir = scratch.join(path);
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir)?;
+    }
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// The index in the encrypted `dir`, empty if there is none yet.
+fn read_index(keys: &EncryptionConfig, dir: &Path) -> Result<Index> {
+    let path = dir.join(INDEX_NAME);
+    if !path.exists() {
+        return Ok(Index::default());
+    }
+    let identity = identity(keys)?;
+    let args = [
+        OsStr::new("--decrypt"),
+        OsStr::new("-i"),
+        identity.as_os_str(),
+        path.as_os_str(),
+    ];
+    let content = age(args, None)?;
+    toml::from_str(std::str::from_utf8(&content)?)
+        .with_context(|| format!("invalid index of the encrypted {:?}", dir))
+}
+
+fn recipients(keys: &EncryptionConfig) -> Result<&Path> {
+    let recipients = keys.recipients.as_deref();
+    recipients.context("the items with `encrypt` need `encryption.recipients`")
+}
+
+fn identity(keys: &EncryptionConfig) -> Result<&Path> {
+    let identity = keys.identity.as_deref();
+    identity.context("the items with `encrypt` need `encryption.identity` to be read")
+}
+
+/// The key of the stored names, so they reveal nothing to whoever lacks the
+/// identity, yet stay the same from one collect to the next.
+fn names_key(keys: &EncryptionConfig) -> Result<[u8; 32]> {
+    let identity = std::fs::read(identity(keys)?);
+    let identity = identity.context("failed to read `encryption.identity`")?;
+    Ok(blake3::derive_key(NAMES_CONTEXT, &identity))
+}
+
+/// Run `age` with `args` and their `key` file from `source` to `target`.
+fn age_file(args: [&str; 2], key: &Path, source: &Path, target: &Path) -> Result<()> {
+    let mut args = args.map(OsStr::new).to_vec();
+    args.extend([key.as_os_str(), OsStr::new("-o"), target.as_os_str()]);
+    args.push(source.as_os_str());
+    age(args, None)?;
+    Ok(())
+}
+
+/// Run `age` with `args`, writing `input` to its stdin, returning its stdout.
+fn age<'a>(args: impl IntoIterator<Item = &'a OsStr>, input: Option<Vec<u8>>) -> Result<Vec<u8>> {
+    let mut child = Command::new("age")
+        .args(args)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("the items with `encrypt` need `age`, install it")?;
+    let writer = input.map(|input| {
+        let mut stdin = child.stdin.take().unwrap();
+        std::thread::spawn(move || stdin.write_all(&input))
+    });
+    let output = child.wait_with_output()?;
+    ensure!(
+        output.status.success(),
+        "age failed: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    if let Some(writer) = writer {
+        writer.join().unwrap()?;
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A new empty directory for the test `name`.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gsb-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// The keys of a new `age` identity in `dir`, `None` without `age`.
+    fn new_keys(dir: &Path) -> Option<EncryptionConfig> {
+        let identity = dir.join("identity.txt");
+        let keygen = Command::new("age-keygen")
+            .arg("-o")
+            .arg(&identity)
+            .stderr(Stdio::null())
+            .status();
+        if !keygen.ok()?.success() || Command::new("age").arg("--version").output().is_err() {
+            return None;
+        }
+        let recipient = Command::new("age-keygen").arg("-y").arg(&identity).output().unwrap();
+        let recipients = dir.join("recipients.txt");
+        std::fs::write(&recipients, recipient.stdout).unwrap();
+        Some(EncryptionConfig {
+            recipients: Some(recipients),
+            identity: Some(identity),
+        })
+    }
+
+    /// An item `work/clients` with two files, at `dir/device`.
+    fn new_item(dir: &Path) -> PathBuf {
+        let from = dir.join("device");
+        std::fs::create_dir_all(from.join("acme")).unwrap();
+        std::fs::write(from.join("acme/contract.pdf"), b"signed").unwrap();
+        std::fs::write(from.join("notes.md"), b"# notes").unwrap();
+        from
+    }
+
+    /// The paths of the files under `dir`, relative to it.
+    fn stored_files(dir: &Path) -> Vec<String> {
+        let files = walkdir::WalkDir::new(dir).into_iter().map(Result::unwrap);
+        let files = files.filter(|entry| entry.file_type().is_file());
+        let files = files.map(|entry| entry.path().strip_prefix(dir).unwrap().to_owned());
+        files.map(|path| path.to_string_lossy().replace('\\', "/")).collect()
+    }
+
+    #[test]
+    fn test_contents_round_trip() {
+        let dir = test_dir("encrypt-contents");
+        let Some(keys) = new_keys(&dir) else {
+            eprintln!("skipped, `age` is not installed");
+            return;
+        };
+        let from = new_item(&dir);
+        let (repo, item) = (dir.join("repo"), Path::new("work/clients"));
+        let stats = encrypt_into(&repo, &keys, &from, item, Encryption::Contents).unwrap();
+        assert_eq!(stats.files_copied, 2);
+        let mut stored = stored_files(&repo);
+        stored.sort();
+        assert_eq!(
+            stored,
+            ["work/clients/acme/contract.pdf.age", "work/clients/notes.md.age"]
+        );
+        // Unchanged files are not encrypted again.
+        let stats = encrypt_into(&repo, &keys, &from, item, Encryption::Contents).unwrap();
+        assert_eq!((stats.files_copied, stats.files_skipped), (0, 2));
+
+        let original = item.join("acme/contract.pdf");
+        let scratch = dir.join("scratch");
+        let (file, extracted) = decrypt_from(&repo, &keys, &scratch, &original).unwrap();
+        assert_eq!(std::fs::read(&file).unwrap(), b"signed");
+        drop(extracted);
+        assert!(!file.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_names_round_trip() {
+        let dir = test_dir("encrypt-names");
+        let Some(keys) = new_keys(&dir) else {
+            eprintln!("skipped, `age` is not installed");
+            return;
+        };
+        let from = new_item(&dir);
+        let (repo, item) = (dir.join("repo"), Path::new("work/clients"));
+        let stats = encrypt_into(&repo, &keys, &from, item, Encryption::Names).unwrap();
+        assert_eq!(stats.files_copied, 2);
+        let stored = stored_files(&repo);
+        assert_eq!(stored.len(), 3);
+        assert!(stored.iter().all(|path| path.starts_with("work/clients.enc/")));
+        assert!(!stored.iter().any(|path| path.contains("acme") || path.contains("notes")));
+        let stats = encrypt_into(&repo, &keys, &from, item, Encryption::Names).unwrap();
+        assert_eq!((stats.files_copied, stats.files_skipped), (0, 2));
+
+        // A removed file leaves the repository.
+        std::fs::remove_file(from.join("notes.md")).unwrap();
+        encrypt_into(&repo, &keys, &from, item, Encryption::Names).unwrap();
+        assert_eq!(stored_files(&repo).len(), 2);
+
+        let scratch = dir.join("scratch");
+        let (root, _extracted) = decrypt_item_from(&repo, &keys, &scratch, item).unwrap();
+        assert_eq!(stored_files(&root), ["acme/contract.pdf"]);
+        assert_eq!(std::fs::read(root.join("acme/contract.pdf")).unwrap(), b"signed");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_encrypted_paths() {
+        let config: Config = toml::from_str(
+            r#"
+            device_name = "a"
+            [backup_group]
+            [sync_group.notes]
+            path_on_devices = {}
+            is_hardlink = false
+            encrypt = "contents"
+            [sync_group.work]
+            path_on_devices = {}
+            is_hardlink = false
+            encrypt = "names"
+            "#,
+        )
+        .unwrap();
+        let encrypted = |path| encrypted_file(&config, Path::new(path));
+        assert_eq!(encrypted("notes/a.md.age"), Some(PathBuf::from("notes/a.md")));
+        assert_eq!(encrypted("notes/a.md"), None);
+        assert_eq!(encrypted("work/a.md.age"), None);
+        let item = |path| encrypted_item(&config, Path::new(path));
+        assert_eq!(item("work.enc/index.age"), Some(PathBuf::from("work")));
+        assert_eq!(item("notes/a.md.age"), None);
+        assert!(is_index(Path::new("work.enc/index.age")));
+    }
+}
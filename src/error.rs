@@ -0,0 +1,170 @@
+use thiserror::Error;
+
+/// The errors of gsb that callers need to tell apart, e.g. to retry only the
+/// transient ones.
+#[derive(Error, Debug)]
+pub enum GsbError {
+    /// The remote could not be reached, which is usually transient.
+    #[error("network error in `git {command}`: {stderr}")]
+    Network { command: String, stderr: String },
+    /// The histories could not be merged, which needs a human.
+    #[error("merge error in `git {command}`: {stderr}")]
+    Merge { command: String, stderr: String },
+    #[error("`git {command}` failed: {stderr}")]
+    Git { command: String, stderr: String },
+    /// There is no git to run, so nothing that needs git works.
+    #[error("`{binary}` not found, install git or set `binary` in the `[git]` section")]
+    NotFound { binary: String },
+}
+
+impl GsbError {
+    /// Classify a failed git command by its stderr.
+    pub fn from_git(command: String, stderr: String) -> Self {
+        // Rejected credentials are reported along with network like messages,
+        // but retrying cannot fix them.
+        const AUTH: &[&str] = &[
+            "authentication failed",
+            "permission denied",
+            "could not read username",
+            "host key verification failed",
+            "the requested url returned error: 401",
+            "the requested url returned error: 403",
+        ];
+        const NETWORK: &[&str] = &[
+            "could not resolve host",
+            "could not read from remote repository",
+            "unable to access",
+            "connection timed out",
+            "connection refused",
+            "connection reset",
+            "network is unreachable",
+            "operation timed out",
+            "early eof",
+            "the remote end hung up",
+        ];
+        const MERGE: &[&str] = &[
+            "conflict",
+            "automatic merge failed",
+            "non-fast-forward",
+            "[rejected]",
+            "not possible to fast-forward",
+        ];
+        let lower = stderr.to_lowercase();
+        if AUTH.iter().any(|pattern| lower.contains(pattern)) {
+            Self::Git { command, stderr }
+        } else if NETWORK.iter().any(|pattern| lower.contains(pattern)) {
+            Self::Network { command, stderr }
+        } else if MERGE.iter().any(|pattern| lower.contains(pattern)) {
+            Self::Merge { command, stderr }
+        } else {
+            Self::Git { command, stderr }
+        }
+    }
+
+    /// Turn a [`GsbError::Git`] into a [`GsbError::NotFound`] if the shell could
+    /// not find `binary`.
+    pub fn or_not_found(self, binary: String) -> Self {
+        const NOT_FOUND: &[&str] = &["is not recognized as", "command not found"];
+        let not_found = |stderr: &str| NOT_FOUND.iter().any(|pattern| stderr.contains(pattern));
+        match &self {
+            Self::Git { stderr, .. } if not_found(stderr) => Self::NotFound { binary },
+            _ => self,
+        }
+    }
+
+    pub fn is_network(&self) -> bool {
+        matches!(self, Self::Network { .. })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_git() {
+        let cases = [
+            // Authentication.
+            (
+                "git@github.com: Permission denied (publickey).\r\nfatal: Could not read from \
+                 remote repository.\n\nPlease make sure you have the correct access rights\n\
+                 and the repository exists.",
+                "git",
+            ),
+            (
+                "remote: Invalid username or password.\nfatal: Authentication failed for \
+                 'https://github.com/user/dotfiles.git/'",
+                "git",
+            ),
+            (
+                "fatal: could not read Username for 'https://github.com': terminal prompts \
+                 disabled",
+                "git",
+            ),
+            (
+                "fatal: unable to access 'https://github.com/user/dotfiles.git/': The requested \
+                 URL returned error: 403",
+                "git",
+            ),
+            ("Host key verification failed.\nfatal: Could not read from remote repository.", "git"),
+            // Network.
+            (
+                "fatal: unable to access 'https://github.com/user/dotfiles.git/': Could not \
+                 resolve host: github.com",
+                "network",
+            ),
+            (
+                "ssh: connect to host github.com port 22: Connection timed out\nfatal: Could not \
+                 read from remote repository.",
+                "network",
+            ),
+            (
+                "error: RPC failed; curl 56 GnuTLS recv error (-9): A TLS packet with unexpected \
+                 length was received.\nfatal: early EOF",
+                "network",
+            ),
+            ("fatal: the remote end hung up unexpectedly", "network"),
+            // Conflicts.
+            (
+                "CONFLICT (content): Merge conflict in notes.md\nAutomatic merge failed; fix \
+                 conflicts and then commit the result.",
+                "merge",
+            ),
+            (
+                "To github.com:user/dotfiles.git\n ! [rejected]        main -> main \
+                 (non-fast-forward)\nerror: failed to push some refs",
+                "merge",
+            ),
+            ("fatal: Not possible to fast-forward, aborting.", "merge"),
+            // Locks and the rest.
+            (
+                "fatal: Unable to create '/home/user/.dotfiles/.git/index.lock': File exists.\n\n\
+                 Another git process seems to be running in this repository",
+                "git",
+            ),
+            (
+                "remote: Repository not found.\nfatal: repository \
+                 'https://github.com/user/missing.git/' not found",
+                "git",
+            ),
+            ("fatal: not a git repository (or any of the parent directories): .git", "git"),
+        ];
+        for (stderr, expected) in cases {
+            let kind = match GsbError::from_git("pull".to_string(), stderr.to_string()) {
+                GsbError::Network { .. } => "network",
+                GsbError::Merge { .. } => "merge",
+                GsbError::Git { .. } => "git",
+                GsbError::NotFound { .. } => "not found",
+            };
+            assert_eq!(kind, expected, "{}", stderr);
+        }
+    }
+
+    #[test]
+    fn test_or_not_found() {
+        let error = GsbError::from_git("status".to_string(), "sh: git: command not found".into());
+        assert!(matches!(error.or_not_found("git".to_string()), GsbError::NotFound { .. }));
+        let error = GsbError::from_git("status".to_string(), "fatal: early EOF".into());
+        assert!(error.or_not_found("git".to_string()).is_network());
+    }
+}
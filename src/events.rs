@@ -0,0 +1,73 @@
+//! Typed events of a run, one model for everything following a run as it
+//! goes: `--output events`, the metrics and embedders, instead of parsing the
+//! logs.
+
+use std::{path::PathBuf, sync::RwLock};
+
+use serde::Serialize;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::utils::CopyStats;
+
+static SINKS: RwLock<Vec<Box<dyn EventSink>>> = RwLock::new(Vec::new());
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// An item (a file or a whole directory) starts being copied.
+    ItemStarted { from: PathBuf, to: PathBuf },
+    FileCopied { from: PathBuf, to: PathBuf, bytes: u64 },
+    Skipped { path: PathBuf, reason: SkipReason },
+    /// A run like `collect` or `restore` finished, see [`crate::report`].
+    CycleFinished {
+        action: &'static str,
+        items: usize,
+        #[serde(flatten)]
+        stats: CopyStats,
+        errors: usize,
+        elapsed_ms: u64,
+    },
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// The copy is up to date.
+    Unchanged,
+    /// A cloud-only placeholder, see [`crate::config::PlaceholderPolicy`].
+    Placeholder,
+}
+
+/// Receives every event emitted after it was subscribed. Called on the thread
+/// emitting the event, so it should return quickly.
+pub trait EventSink: Send + Sync {
+    fn event(&self, event: &Event);
+}
+
+/// Events sent into a channel, to be consumed as a stream.
+impl EventSink for UnboundedSender<Event> {
+    fn event(&self, event: &Event) {
+        let _ = self.send(event.clone());
+    }
+}
+
+/// Prints every event as a line of JSON on stdout, for `--output events`.
+pub struct JsonLines;
+
+impl EventSink for JsonLines {
+    fn event(&self, event: &Event) {
+        if let Ok(line) = serde_json::to_string(event) {
+            println!("{}", line);
+        }
+    }
+}
+
+pub fn subscribe(sink: impl EventSink + 'static) {
+    SINKS.write().unwrap().push(Box::new(sink));
+}
+
+pub fn emit(event: Event) {
+    for sink in SINKS.read().unwrap().iter() {
+        sink.event(&event);
+    }
+}
@@ -1,18 +1,49 @@
-use std::{path::PathBuf, process::Command, sync::LazyLock};
+use std::{
+    io,
+    path::PathBuf,
+    process::{Command, Output},
+    sync::LazyLock,
+    time::Duration,
+};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use die_exit::{die, Die, DieWith};
-use whoami::devicename;
+use indicatif::HumanBytes;
+use log::{info, warn};
 
-use crate::cli::CLI;
+use crate::{
+    cli::{SubCommand, CLI},
+    config::CONFIG,
+    device::devicename,
+    error::GsbError,
+};
 
 pub const REMOTE_NAME: &str = "origin";
 pub const SYNC_BRANCH: &str = "sync";
-pub static BACKUP_BRANCH: LazyLock<String> =
-    LazyLock::new(|| "backup-".to_string() + devicename().as_str());
+pub static BACKUP_BRANCH: LazyLock<String> = LazyLock::new(|| backup_branch(&devicename()));
 
-/// Read from env first, parameter second, cwd third.
+/// The branch `device` collects onto with `device_branches`.
+pub fn device_branch(device: &str) -> String {
+    format!("{}-{}", SYNC_BRANCH, device)
+}
+
+/// The branch holding the backup group of `device`.
+pub fn backup_branch(device: &str) -> String {
+    "backup-".to_string() + device
+}
+
+/// Read from env first, parameter second, cwd third. `gsb clone <url> <path>`
+/// and `gsb init <path>` work in `path`.
 pub static REPO_PATH: LazyLock<PathBuf> = LazyLock::new(|| {
+    if let Some(
+        SubCommand::Clone {
+            path: Some(path), ..
+        }
+        | SubCommand::Init { path: Some(path) },
+    ) = CLI.get().map(|cli| &cli.command)
+    {
+        return path.clone();
+    }
     std::env::var(env!("CARGO_PKG_NAME").to_uppercase())
         .map(PathBuf::from)
         .unwrap_or_else(|_| {
@@ -22,23 +53,169 @@ pub static REPO_PATH: LazyLock<PathBuf> = LazyLock::new(|| {
         })
 });
 
+/// The git binary set in the `[git]` section, read once.
+static GIT_BINARY: LazyLock<Option<PathBuf>> =
+    LazyLock::new(|| CONFIG.read().unwrap().git.binary.clone());
+
+/// The name and email of the commits: the ones of the `[git]` section, else
+/// the ones of the git config, else `gsb <gsb@localhost>`. `None` leaves it to
+/// the git config.
+static IDENTITY: LazyLock<(Option<String>, Option<String>)> = LazyLock::new(|| {
+    let git = CONFIG.read().unwrap().git.clone();
+    let unset = |key: &str| {
+        base_command()
+            .args(["config", key])
+            .output()
+            .map_or(true, |output| output.stdout.trim_ascii().is_empty())
+    };
+    let name = git.name.or_else(|| unset("user.name").then(|| "gsb".to_string()));
+    let email = git.email.or_else(|| unset("user.email").then(|| "gsb@localhost".to_string()));
+    (name, email)
+});
+
+/// Whether the commits are signed, `git.sign` read once.
+static SIGN: LazyLock<bool> = LazyLock::new(|| CONFIG.read().unwrap().git.sign);
+
+/// A git command in [`REPO_PATH`], run by the git binary of the `[git]`
+/// section, or by `git` found through the shell.
+fn base_command() -> Command {
+    let mut command = match GIT_BINARY.as_ref() {
+        Some(binary) => Command::new(binary),
+        None => {
+            let mut command = Command::new("cmd");
+            command.args(["/C", "git"]);
+            command
+        }
+    };
+    command.current_dir(REPO_PATH.as_path());
+    command
+}
+
+/// Like [`base_command`], committing as the identity of [`IDENTITY`], and
+/// signing every commit, merge and `commit-tree` if `git.sign` is set.
+pub fn git_command() -> Command {
+    let mut command = base_command();
+    if *SIGN {
+        command.args(["-c", "commit.gpgsign=true"]);
+    }
+    let (name, email) = &*IDENTITY;
+    if let Some(name) = name {
+        command.env("GIT_AUTHOR_NAME", name).env("GIT_COMMITTER_NAME", name);
+    }
+    if let Some(email) = email {
+        command.env("GIT_AUTHOR_EMAIL", email).env("GIT_COMMITTER_EMAIL", email);
+    }
+    command
+}
+
+/// Run `command` from [`git_command`], failing with [`GsbError::NotFound`] if
+/// there is no git to run.
+pub fn git_output(command: &mut Command) -> Result<Output> {
+    command.output().map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => GsbError::NotFound {
+            binary: git_binary_name(),
+        }
+        .into(),
+        _ => e.into(),
+    })
+}
+
+fn git_binary_name() -> String {
+    GIT_BINARY
+        .as_ref()
+        .map_or_else(|| "git".to_string(), |binary| binary.display().to_string())
+}
+
 pub fn ensure_utf8() -> Result<()> {
     #[cfg(target_os = "windows")]
     Command::new("cmd").args(["/C", "chcp", "65001"]).output()?;
     Ok(())
 }
 
+#[cfg_attr(feature = "profiling", tracing::instrument(skip_all))]
 pub fn git(args: impl AsRef<[&str]>) -> Result<String> {
     let _ = ensure_utf8();
-    let mut command = Command::new("cmd");
-    let output = command
-        .args(["/C", "git"])
-        .args(args.as_ref())
-        .current_dir(REPO_PATH.as_path())
-        .output()?;
+    let output = git_output(git_command().args(args.as_ref()))?;
     Ok(String::from_utf8(output.stdout)?)
 }
 
+/// Like [`git`], but fails with a [`GsbError`] if git does.
+pub fn git_checked(args: impl AsRef<[&str]>) -> Result<String> {
+    let _ = ensure_utf8();
+    let args = args.as_ref();
+    let output = git_output(git_command().args(args))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(GsbError::from_git(args.join(" "), stderr)
+            .or_not_found(git_binary_name())
+            .into());
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Run a git command talking to the remote, retrying with an exponential
+/// backoff on network errors, see [`crate::config::RetryConfig`].
+pub async fn git_remote(args: impl AsRef<[&str]>) -> Result<String> {
+    let retry = CONFIG.read().unwrap().retry;
+    let mut delay = Duration::from_millis(retry.initial_delay_ms);
+    let mut attempt = 0;
+    loop {
+        match git_checked(args.as_ref()) {
+            Err(e)
+                if attempt < retry.max_retries
+                    && e.downcast_ref::<GsbError>().is_some_and(GsbError::is_network) =>
+            {
+                attempt += 1;
+                warn!("{}, retry {} of {} in {:?}", e, attempt, retry.max_retries, delay);
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(Duration::from_millis(retry.max_delay_ms));
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Fetch `branch` of `remote` into `FETCH_HEAD`, shallow if `git.depth` is
+/// set.
+pub async fn fetch(remote: &str, branch: &str) -> Result<()> {
+    let depth = CONFIG.read().unwrap().git.depth;
+    let depth = depth.map(|depth| format!("--depth={}", depth));
+    let mut args = vec!["fetch"];
+    args.extend(depth.as_deref());
+    args.extend([remote, branch]);
+    git_remote(args).await?;
+    Ok(())
+}
+
+/// Push `branch` to `remote` after showing how much it sends, refusing to send
+/// more than `push_size_limit` without `--force-push-large`. `branch` can be a
+/// refspec like `sync:sync-laptop`.
+pub async fn push_branch(remote: &str, branch: &str) -> Result<()> {
+    let local = branch.split_once(':').map_or(branch, |(local, _)| local);
+    let remote_refs = format!("--remotes={}", remote);
+    // Counts everything if the remote branch was never fetched, which only
+    // overestimates.
+    let args = ["rev-list", "--objects", "--disk-usage", local, "--not", &remote_refs];
+    let size = git_checked(args)
+        .ok()
+        .and_then(|size| size.trim().parse::<u64>().ok());
+    if let Some(size) = size {
+        info!("pushing about {} of new objects", HumanBytes(size));
+        let limit = CONFIG.read().unwrap().push_size_limit;
+        let forced = CLI.get().is_some_and(|cli| cli.force_push_large);
+        if let Some(limit) = limit.filter(|limit| size > *limit && !forced) {
+            bail!(
+                "refused to push {}, more than the `push_size_limit` of {}; push anyway with \
+                 `--force-push-large`",
+                HumanBytes(size),
+                HumanBytes(limit)
+            );
+        }
+    }
+    git_remote(["push", remote, branch]).await?;
+    Ok(())
+}
+
 mod tests {
     use super::*;
 
@@ -0,0 +1,87 @@
+//! `gsb history`: the commits that changed an item.
+
+use std::{
+    path::Path,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+use crate::{
+    cli::json_output,
+    commit::DEVICE_TRAILER,
+    config::CONFIG,
+    git_command::git_checked,
+};
+
+#[derive(Serialize, Debug)]
+struct Change {
+    commit: String,
+    time: String,
+    /// From the trailer of the commit, `None` for commits made before gsb
+    /// wrote them.
+    device: Option<String>,
+    subject: String,
+    /// Lines like `M\tpath`, as printed by `git log --name-status`.
+    files: Vec<String>,
+}
+
+/// Print the commits of the current branch that changed `item`, newest first.
+pub fn history(item: &Path, limit: Option<usize>) -> Result<()> {
+    let config = CONFIG.read().unwrap().clone();
+    if !config.sync_group.0.contains_key(item) && !config.backup_group.0.contains_key(item) {
+        bail!("`{}` is not an item, see the keys of the groups in the config", item.display());
+    }
+    let format = format!(
+        "--format=%x1e%H%x1f%ct%x1f%s%x1f%(trailers:key={},valueonly,separator=%x2C)",
+        DEVICE_TRAILER
+    );
+    let mut args = vec!["log".to_string(), format, "--name-status".to_string()];
+    if let Some(limit) = limit {
+        args.push(format!("--max-count={}", limit));
+    }
+    args.extend(["--".to_string(), item.to_string_lossy().to_string()]);
+    let log = git_checked(args.iter().map(String::as_str).collect::<Vec<_>>())?;
+
+    let mut changes = Vec::new();
+    for record in log.split('\x1e').filter(|record| !record.trim().is_empty()) {
+        let mut lines = record.lines();
+        let header = lines.next().unwrap_or_default();
+        let mut fields = header.split('\x1f');
+        let mut next = || fields.next().unwrap_or_default().trim().to_string();
+        let (commit, time, subject, device) = (next(), next(), next(), next());
+        let time = UNIX_EPOCH + Duration::from_secs(time.parse().unwrap_or(0));
+        changes.push(Change {
+            commit,
+            time: humantime::format_rfc3339_seconds(time).to_string(),
+            device: (!device.is_empty()).then(|| config.device_label(&device)),
+            subject,
+            files: lines
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect(),
+        });
+    }
+    if json_output() {
+        println!("{}", serde_json::to_string(&changes)?);
+        return Ok(());
+    }
+    if changes.is_empty() {
+        println!("no commit of this branch changed `{}`", item.display());
+    }
+    for change in changes {
+        println!(
+            "{} {} {} {}",
+            &change.commit[..change.commit.len().min(8)],
+            change.time,
+            change.device.as_deref().unwrap_or("-"),
+            change.subject
+        );
+        for file in change.files {
+            println!("    {}", file.replace('\t', " "));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,144 @@
+//! Running the hooks of the config. Every hook gets these environment
+//! variables:
+//!
+//! - `GSB_HOOK`: the name of the hook.
+//! - `GSB_ACTION`: the phase it runs at, e.g. `post-restore`, or `run` if run
+//!   by `gsb run`.
+//! - `GSB_DEVICE_ALIAS`: the name of this device in the config.
+//! - `GSB_REPO_ROOT`: the absolute path of the repository.
+//! - `GSB_ITEM`: the item of the hook, if it has one.
+//! - `GSB_CHANGED_FILES`: the path of a file listing the changed files, one
+//!   path relative to the repository per line. Empty if not known, e.g. before
+//!   a collect.
+
+use std::{
+    path::{Path, PathBuf},
+    process::{Child, Command},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use clap::ValueEnum;
+use log::info;
+
+use crate::{
+    cli::json_output,
+    config::{Hook, HookPhase, CONFIG},
+    git_command::REPO_PATH,
+    state::state_dir,
+};
+
+/// Run a hook in the shell, in the repository or its `cwd`.
+fn run_hook(name: &str, hook: &Hook, action: &str, changed: &Path) -> Result<()> {
+    info!("running hook `{}`", name);
+    #[cfg(windows)]
+    let mut command = Command::new("cmd");
+    #[cfg(windows)]
+    command.args(["/C", &hook.command]);
+    #[cfg(not(windows))]
+    let mut command = Command::new("sh");
+    #[cfg(not(windows))]
+    command.args(["-c", &hook.command]);
+
+    let repo = REPO_PATH.canonicalize()?;
+    command
+        .current_dir(repo.join(hook.cwd.as_deref().unwrap_or(Path::new(""))))
+        .env("GSB_HOOK", name)
+        .env("GSB_ACTION", action)
+        .env("GSB_DEVICE_ALIAS", CONFIG.read().unwrap().this_device())
+        .env("GSB_REPO_ROOT", &repo)
+        .env("GSB_CHANGED_FILES", changed);
+    if let Some(item) = hook.item.as_ref() {
+        command.env("GSB_ITEM", item);
+    }
+    // Stdout only holds the JSON documents of gsb.
+    if json_output() {
+        command.stdout(std::io::stderr());
+    }
+    let timeout = hook
+        .timeout
+        .as_deref()
+        .map(humantime::parse_duration)
+        .transpose()
+        .with_context(|| format!("invalid timeout of hook `{}`", name))?;
+    let status = wait(command.spawn()?, timeout)
+        .with_context(|| format!("hook `{}` timed out", name))?;
+    ensure!(status.success(), "hook `{}` failed: {}", name, status);
+    Ok(())
+}
+
+/// Wait for `child`, killing it once `timeout` is over.
+fn wait(mut child: Child, timeout: Option<Duration>) -> Result<std::process::ExitStatus> {
+    let Some(timeout) = timeout else {
+        return Ok(child.wait()?);
+    };
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if started.elapsed() > timeout {
+            child.kill()?;
+            child.wait()?;
+            bail!("killed after {}", humantime::format_duration(timeout));
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Write the list of `GSB_CHANGED_FILES`.
+fn changed_files(changed: &[&str]) -> Result<PathBuf> {
+    let path = state_dir()?.join("changed_files");
+    let mut content = changed.join("\n");
+    if !content.is_empty() {
+        content.push('\n');
+    }
+    std::fs::write(&path, content)?;
+    Ok(path)
+}
+
+/// Run all hooks of `phase`, in the order of their names. `changed` are the
+/// files changed by the phase, relative to the repository; a hook with an item
+/// is skipped if none of them is in it. Returns the names of the hooks run.
+pub fn run_hooks(phase: HookPhase, changed: &[&str]) -> Result<Vec<String>> {
+    let hooks = CONFIG.read().unwrap().hooks.clone();
+    let mut hooks = hooks
+        .iter()
+        .filter(|(_, hook)| hook.on == Some(phase))
+        .filter(|(_, hook)| {
+            hook.item.as_ref().is_none_or(|item| {
+                changed.is_empty() || changed.iter().any(|path| Path::new(path).starts_with(item))
+            })
+        })
+        .peekable();
+    if hooks.peek().is_none() {
+        return Ok(Vec::new());
+    }
+    let changed = changed_files(changed)?;
+    let action = phase.to_possible_value().map(|value| value.get_name().to_string());
+    let mut ran = Vec::new();
+    for (name, hook) in hooks {
+        run_hook(name, hook, action.as_deref().unwrap_or_default(), &changed)?;
+        ran.push(name.clone());
+    }
+    Ok(ran)
+}
+
+/// Run a hook by name, or all hooks of `phase`, from the command line.
+pub fn run(name: Option<&str>, phase: Option<HookPhase>) -> Result<()> {
+    let ran = match (phase, name) {
+        (Some(phase), _) => run_hooks(phase, &[])?,
+        (None, Some(name)) => {
+            let Some(hook) = CONFIG.read().unwrap().hooks.get(name).cloned() else {
+                bail!("no hook named `{}`", name);
+            };
+            run_hook(name, &hook, "run", &changed_files(&[])?)?;
+            vec![name.to_string()]
+        }
+        (None, None) => bail!("no hook given"),
+    };
+    if json_output() {
+        println!("{}", serde_json::json!({ "ran": ran }));
+    }
+    Ok(())
+}
@@ -0,0 +1,36 @@
+//! `gsb init`: start a new repository on the first device.
+
+use anyhow::{bail, Result};
+use log::info;
+
+use crate::{
+    cli::CLI,
+    commit::commit,
+    config::{reload_config, save_config, CONFIG_NAME},
+    git_command::{git, git_checked, REPO_PATH, SYNC_BRANCH},
+    lock::lock_repo,
+};
+
+/// Create the repository with the `sync` branch and a default config, and
+/// commit the config.
+pub fn init() -> Result<()> {
+    let repo = REPO_PATH.as_path();
+    if repo.join(CONFIG_NAME).exists() {
+        bail!("{:?} is a gsb repository already", repo);
+    }
+    std::fs::create_dir_all(repo)?;
+    git_checked(["init", "--quiet", "--initial-branch", SYNC_BRANCH])?;
+    let _lock = lock_repo(CLI.get().is_some_and(|cli| cli.wait))?;
+    // Read before the repository existed.
+    reload_config();
+    save_config()?;
+    git(["add", "--", CONFIG_NAME])?;
+    commit("init", [])?;
+    info!("initialized {:?}", repo);
+    println!(
+        "add the remote with `git -C {:?} remote add origin <url>`, then track paths with \
+         `gsb track`",
+        repo
+    );
+    Ok(())
+}
@@ -0,0 +1,85 @@
+//! Git LFS for the items with `lfs = true`: their paths are kept in a block of
+//! `.gitattributes` managed by gsb, so collect and backup commit them as LFS
+//! pointers instead of blobs.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use log::info;
+
+use crate::{
+    config::CONFIG,
+    git_command::{git, git_checked, REPO_PATH},
+};
+
+const ATTRIBUTES_NAME: &str = ".gitattributes";
+const BEGIN: &str = "# gsb lfs begin";
+const END: &str = "# gsb lfs end";
+
+/// The `.gitattributes` pattern of all files of `item`.
+fn pattern(item: &Path) -> String {
+    // Spaces end a pattern.
+    let path = item.to_string_lossy().replace('\\', "/").replace(' ', "[[:space:]]");
+    if REPO_PATH.join(item).is_dir() {
+        format!("{}/**", path)
+    } else {
+        path
+    }
+}
+
+/// Write the patterns of the LFS items to `.gitattributes`, before their
+/// files are added. Sets up LFS in the repository the first time.
+pub fn ensure_attributes() -> Result<()> {
+    let mut items = {
+        let config = CONFIG.read().unwrap();
+        let sync = config.sync_group.0.iter().filter(|(_, info)| info.lfs);
+        let backup = config.backup_group.0.iter().filter(|(_, info)| info.lfs);
+        sync.map(|(item, _)| item.clone())
+            .chain(backup.map(|(item, _)| item.clone()))
+            .collect::<Vec<_>>()
+    };
+    items.sort();
+    let path = REPO_PATH.join(ATTRIBUTES_NAME);
+    let current = std::fs::read_to_string(&path).unwrap_or_default();
+    // Everything but the block of gsb is left as it is.
+    let mut others = Vec::new();
+    let mut in_block = false;
+    for line in current.lines() {
+        match line.trim() {
+            BEGIN => in_block = true,
+            END => in_block = false,
+            _ if !in_block => others.push(line),
+            _ => {}
+        }
+    }
+    let mut attributes = others.join("\n");
+    if !items.is_empty() {
+        if !attributes.is_empty() {
+            attributes.push('\n');
+        }
+        attributes.push_str(BEGIN);
+        for item in items.iter() {
+            attributes.push('\n');
+            attributes.push_str(&pattern(item));
+            attributes.push_str(" filter=lfs diff=lfs merge=lfs -text");
+        }
+        attributes.push('\n');
+        attributes.push_str(END);
+    }
+    if !attributes.is_empty() {
+        attributes.push('\n');
+    }
+    if attributes == current {
+        return Ok(());
+    }
+    if !items.is_empty() {
+        git_checked(["lfs", "install", "--local"])
+            .context("the items with `lfs = true` need Git LFS, install `git lfs`")?;
+        info!("storing {:?} with Git LFS", items);
+    }
+    std::fs::write(&path, attributes)?;
+    git(["add", ATTRIBUTES_NAME])?;
+    // The files committed before are turned into pointers, or back into blobs.
+    git(["add", "--renormalize", "."])?;
+    Ok(())
+}
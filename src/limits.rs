@@ -0,0 +1,49 @@
+//! `[limits]`: the sizes of single files collect warns about or refuses, so a
+//! video that landed in a collected folder does not end up in a multi-GB
+//! commit. An item can override them with a `limits` of its own.
+
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use indicatif::HumanBytes;
+use log::warn;
+
+use crate::{
+    config::{Limits, CONFIG},
+    utils::walk,
+};
+
+/// Check the files at `from` of `item` against its `limits` and the global
+/// ones, failing if one is larger than `max_file_size`.
+pub fn check(from: &Path, item: &Path, limits: Option<Limits>) -> Result<()> {
+    let limits = limits.unwrap_or_default().or(CONFIG.read().unwrap().limits);
+    if limits.warn_file_size.is_none() && limits.max_file_size.is_none() {
+        return Ok(());
+    }
+    for entry in walk(from) {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let size = entry.metadata()?.len();
+        if let Some(max) = limits.max_file_size.filter(|max| size > *max) {
+            bail!(
+                "refused to collect {:?} of `{}`, it is {}, more than the `max_file_size` of {}",
+                entry.path(),
+                item.display(),
+                HumanBytes(size),
+                HumanBytes(max)
+            );
+        }
+        if let Some(warn) = limits.warn_file_size.filter(|warn| size > *warn) {
+            warn!(
+                "{:?} of `{}` is {}, more than the `warn_file_size` of {}",
+                entry.path(),
+                item.display(),
+                HumanBytes(size),
+                HumanBytes(warn)
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,105 @@
+//! An advisory lock of the repository, so a collect fired by cron does not race
+//! with a running `gsb sync` on the index and working tree.
+//!
+//! The lock is held by the OS on `.gsb/lock`, which is released as soon as the
+//! holding process exits. A lock file left behind by a killed run is therefore
+//! stale by definition and simply taken over.
+
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    io::Write,
+    path::Path,
+    time::SystemTime,
+};
+
+use anyhow::{bail, Result};
+use log::info;
+
+use crate::state::state_dir;
+
+const LOCK_NAME: &str = "lock";
+
+/// The held lock, released when dropped.
+pub struct RepoLock {
+    _file: File,
+}
+
+/// Lock the repository. If another gsb holds the lock, wait for it if `wait`,
+/// fail otherwise.
+pub fn lock_repo(wait: bool) -> Result<RepoLock> {
+    lock_file(&state_dir()?.join(LOCK_NAME), wait)
+}
+
+/// [`lock_repo`] with the lock file at `path`.
+fn lock_file(path: &Path, wait: bool) -> Result<RepoLock> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            let holder = std::fs::read_to_string(path).unwrap_or_default();
+            let holder = holder.trim();
+            if !wait {
+                bail!(
+                    "the repository is locked by another gsb ({}), retry with `--wait`",
+                    holder
+                );
+            }
+            info!("waiting for another gsb ({}) to finish", holder);
+            file.lock()?;
+        }
+        Err(TryLockError::Error(e)) => return Err(e.into()),
+    }
+    let since = humantime::format_rfc3339_seconds(SystemTime::now());
+    file.set_len(0)?;
+    writeln!(file, "pid {} since {}", std::process::id(), since)?;
+    Ok(RepoLock { _file: file })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_dir;
+
+    #[test]
+    fn test_lock_twice() {
+        let dir = test_dir("lock_twice");
+        let path = dir.join(LOCK_NAME);
+        let lock = lock_file(&path, false).unwrap();
+        let holder = format!("pid {} since", std::process::id());
+        assert!(std::fs::read_to_string(&path).unwrap().starts_with(&holder));
+        let e = lock_file(&path, false).err().unwrap();
+        assert!(e.to_string().contains(&holder), "{:#}", e);
+
+        drop(lock);
+        let lock = lock_file(&path, false).unwrap();
+        // A waiting gsb gets the lock once it is released.
+        let waiting = std::thread::spawn({
+            let path = path.clone();
+            move || lock_file(&path, true).map(|_| ())
+        });
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert!(!waiting.is_finished());
+        drop(lock);
+        waiting.join().unwrap().unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_stale_lock() {
+        let dir = test_dir("lock_stale");
+        let path = dir.join(LOCK_NAME);
+        // Left behind by a gsb that was killed.
+        std::fs::write(&path, "pid 4194304 since 2024-01-01T00:00:00Z with a longer line\n")
+            .unwrap();
+        let _lock = lock_file(&path, false).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with(&format!("pid {} since", std::process::id())));
+        assert_eq!(content.lines().count(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,13 +1,262 @@
 #![feature(anonymous_lifetime_in_impl_trait)]
+mod absorb;
+mod archive;
+mod attic;
 mod backup;
+mod batch;
+mod capabilities;
+mod chunk;
 mod cli;
+mod clone;
+mod commit;
+mod compress;
 mod config;
+mod config_pull;
+mod conflicts;
+mod daemon;
+mod device;
+mod diff;
+mod doctor;
+mod encrypt;
+mod error;
+mod events;
 mod git_command;
+mod history;
+mod hooks;
+mod init;
+mod lfs;
+mod limits;
+mod lock;
+mod manifest;
+mod metrics;
+mod mounts;
+mod mv;
+mod notification;
+mod preset;
+mod profile;
+mod progress;
+mod prune_history;
+mod publish;
+mod quarantine;
+mod receipt;
+mod report;
+mod restart;
+mod scan;
+mod service;
+mod shadow;
+mod show;
+mod shutdown;
+mod sparse;
+mod split;
+mod state;
+mod stats;
+mod status;
 mod sync;
+mod system_log;
+mod track;
+mod trash;
+mod undo;
+mod untrack;
+mod utils;
+mod verify;
+mod watch;
+mod web;
+mod webhook;
+mod workspace;
 
+use anyhow::Result;
 use clap::Parser;
-use cli::{Cli, CLI};
+use cli::{
+    AtticCommand, Cli, ConfigCommand, ConflictsCommand, DeviceCommand, PresetCommand,
+    ServiceCommand, SubCommand, CLI,
+};
+use sync::SyncOptions;
 
-fn main() {
-    CLI.get_or_init(Cli::parse);
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = CLI.get_or_init(Cli::parse);
+    utils::log_init();
+    config::report_config_errors();
+    config::check_identity()?;
+    let _profile = profile::init()?;
+    if cli::events_output() {
+        events::subscribe(events::JsonLines);
+    }
+    let _lock = if cli.command.is_mutating() {
+        Some(lock::lock_repo(cli.wait)?)
+    } else {
+        None
+    };
+    let result = match &cli.command {
+        SubCommand::Batch { keep_going } => batch::batch(*keep_going).await,
+        command => run(command).await,
+    };
+    profile::print_timings();
+    result
+}
+
+/// Run a single command. The global options are the ones of [`CLI`].
+async fn run(command: &SubCommand) -> Result<()> {
+    match command {
+        SubCommand::Sync {
+            no_restart,
+            shadow,
+            accept_config,
+            force,
+            non_interactive,
+        } => {
+            let result = sync::sync(SyncOptions {
+                restart: !no_restart,
+                shadow: *shadow,
+                accept_config: *accept_config,
+                force: *force,
+                non_interactive: *non_interactive,
+            })
+            .await;
+            webhook::ping(&result);
+            result
+        }
+        SubCommand::Pull {
+            accept_config,
+            force,
+            non_interactive,
+        } => {
+            sync::pull(SyncOptions {
+                accept_config: *accept_config,
+                force: *force,
+                non_interactive: *non_interactive,
+                ..Default::default()
+            })
+            .await
+        }
+        SubCommand::Restore {
+            no_restart,
+            shadow,
+            force,
+            non_interactive,
+            at,
+        } => {
+            let options = SyncOptions {
+                restart: !no_restart,
+                shadow: *shadow,
+                force: *force,
+                non_interactive: *non_interactive,
+                ..Default::default()
+            };
+            let result = match at {
+                Some(rev) => sync::restore_at(rev, options).await,
+                None => sync::restore(options).await,
+            };
+            webhook::ping(&result);
+            result
+        }
+        SubCommand::Collect => {
+            let result = sync::sync_push(false).await;
+            webhook::ping(&result);
+            result
+        }
+        SubCommand::Push => sync::push().await,
+        SubCommand::Add { paths, group } => track::add(paths, group.unwrap_or_default()).await,
+        SubCommand::Init { .. } => init::init(),
+        SubCommand::Clone {
+            url,
+            depth,
+            alias,
+            restore,
+            ..
+        } => clone::clone(url, *depth, alias.as_deref(), *restore).await,
+        SubCommand::Approve {
+            ids,
+            reject,
+            elevate,
+        } => quarantine::approve(ids, *reject, *elevate).await,
+        SubCommand::Conflicts { command } => match command {
+            None => conflicts::detect(),
+            Some(ConflictsCommand::List) => conflicts::list(),
+            Some(ConflictsCommand::Show { id }) => conflicts::show(id),
+            Some(ConflictsCommand::Resolve {
+                id,
+                take_local,
+                take_repo,
+                merge: _,
+            }) => conflicts::resolve(
+                id,
+                match (take_local, take_repo) {
+                    (true, _) => conflicts::Resolution::TakeLocal,
+                    (_, true) => conflicts::Resolution::TakeRepo,
+                    _ => conflicts::Resolution::Merge,
+                },
+            ),
+        },
+        SubCommand::Daemon { web, shadow } => daemon::daemon(web.as_deref(), *shadow).await,
+        SubCommand::Watch { no_push } => watch::watch(!no_push).await,
+        SubCommand::DiffDevices { a, b } => diff::diff_devices(a, b),
+        SubCommand::Doctor => doctor::doctor(),
+        SubCommand::Show { rev, output } => match show::split_file_rev(rev) {
+            Some((path, rev)) => show::show_file(path, rev, output.as_deref()),
+            None if output.is_some() => anyhow::bail!("`-o` needs a file, like `<item>@<rev>`"),
+            None => show::show(rev),
+        },
+        SubCommand::History { item, limit } => history::history(item, *limit),
+        SubCommand::Stats => stats::stats(),
+        SubCommand::Status { short } => status::status(*short),
+        SubCommand::Run { hook, all } => hooks::run(hook.as_deref(), *all),
+        SubCommand::Split { items, to, history } => split::split(items, to, *history).await,
+        SubCommand::Track { path, group, yes } => track::track(path, *group, *yes).await,
+        SubCommand::ScanProjects { yes } => scan::scan_projects(*yes).await,
+        SubCommand::Untrack {
+            item,
+            remove_files,
+            unlink,
+            keep,
+        } => untrack::untrack(
+            item,
+            untrack::UntrackOptions {
+                remove_files: *remove_files,
+                unlink: *unlink,
+                keep: *keep,
+            },
+        ),
+        SubCommand::Undo => undo::undo(),
+        SubCommand::Attic { command } => match command {
+            AtticCommand::List => attic::list(),
+            AtticCommand::Restore { path, date, to } => {
+                attic::restore(path, date.as_deref(), to.as_deref())
+            }
+        },
+        SubCommand::Mv { old, new } => mv::mv(old, new),
+        SubCommand::PruneHistory {
+            keep,
+            branch,
+            yes,
+            push,
+        } => prune_history::prune_history(branch, *keep, *yes, *push).await,
+        SubCommand::Publish => publish::publish().await,
+        SubCommand::Absorb { path, history } => absorb::absorb(path, *history).await,
+        SubCommand::Service {
+            command:
+                ServiceCommand::Install {
+                    name,
+                    interval,
+                    daemon,
+                    print,
+                },
+        } => service::install(service::ServiceOptions {
+            name,
+            interval,
+            daemon: *daemon,
+            print: *print,
+        }),
+        SubCommand::Config {
+            command: ConfigCommand::Pull { review },
+        } => config_pull::config_pull(*review).await,
+        SubCommand::Preset {
+            command: PresetCommand::Games { yes },
+        } => preset::games(*yes).await,
+        SubCommand::Devices => device::devices(),
+        SubCommand::Device {
+            command: DeviceCommand::Alias { command },
+        } => device::alias(command),
+        SubCommand::Batch { .. } => anyhow::bail!("`batch` cannot run another batch"),
+    }
 }
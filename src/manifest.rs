@@ -0,0 +1,380 @@
+//! Every collect on a device with a signing key writes a manifest of the
+//! hashes of all synced files, signed with the SSH key of the device. A restore
+//! verifies the signature against the public keys of the devices in
+//! `devices.toml` before trusting the pulled files, so a tampered remote cannot
+//! push changes to all devices. The keys are read from the commit before the
+//! pull, so a pushed commit cannot bring the key it is signed with.
+
+use std::{
+    collections::BTreeMap,
+    fs::{File, Metadata},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::{bail, ensure, Result};
+use config_file::*;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    archive::packed_path,
+    chunk::chunk_dir,
+    compress::compressed_name,
+    config::{Encryption, SignaturePolicy, SyncFile, CONFIG},
+    device::devicename,
+    encrypt::{encrypted_dir, encrypted_name},
+    git_command::{git, git_checked, git_command, git_output, REPO_PATH},
+    state::{state_dir, FileState, STATE},
+    utils::hash_file,
+};
+
+pub const MANIFEST_NAME: &str = ".gsb.manifest.toml";
+const SIGNATURE_NAME: &str = ".gsb.manifest.toml.sig";
+pub const DEVICES_NAME: &str = "devices.toml";
+const NAMESPACE: &str = "gsb-manifest";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Manifest {
+    /// The device that wrote the manifest.
+    pub device: String,
+    /// Key: relative path of the file in the repository.
+    /// Value: the hex encoded BLAKE3 hash of its content.
+    pub files: BTreeMap<PathBuf, String>,
+}
+
+/// The `devices.toml` in the repository, listing the public keys of the
+/// devices.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Devices {
+    #[serde(default)]
+    pub devices: BTreeMap<String, DeviceInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeviceInfo {
+    /// The public SSH key, e.g. `ssh-ed25519 AAAA...`.
+    pub public_key: String,
+}
+
+/// The manifest of the sync group as it is in the repository now.
+#[cfg_attr(feature = "profiling", tracing::instrument(skip_all))]
+fn current_manifest() -> Result<Manifest> {
+    manifest_of(None)
+}
+
+/// The manifest of the sync group in the working tree, or as committed in
+/// `rev`.
+fn manifest_of(rev: Option<&str>) -> Result<Manifest> {
+    let config = CONFIG.read().unwrap().clone();
+    let mut files = BTreeMap::new();
+    for (item, info) in config.sync_group.0.iter() {
+        let is_dir = match rev {
+            Some(rev) => {
+                let object = format!("{}:{}", rev, item.to_string_lossy().replace('\\', "/"));
+                git(["cat-file", "-t", &object])?.trim() == "tree"
+            }
+            None => REPO_PATH.join(item).is_dir(),
+        };
+        let path = stored_path(item, info, is_dir);
+        if let Some(rev) = rev {
+            committed_hashes(rev, &path, &mut files)?;
+            continue;
+        }
+        if !REPO_PATH.join(&path).exists() {
+            committed_hashes("HEAD", &path, &mut files)?;
+            continue;
+        }
+        for entry in walkdir::WalkDir::new(REPO_PATH.join(path)) {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                let hash = match recorded_hash(entry.path(), &entry.metadata()?) {
+                    Some(hash) => hash,
+                    None => hash_file(entry.path())?,
+                };
+                let path = entry.path().strip_prefix(REPO_PATH.as_path())?;
+                files.insert(path.to_path_buf(), hash);
+            }
+        }
+    }
+    Ok(Manifest {
+        device: devicename(),
+        files,
+    })
+}
+
+/// The hash recorded in [`STATE`] when the file at `path` was collected, if it
+/// is unchanged since. A collected file keeps the size and mtime of its
+/// source, so the recorded ones are its own.
+fn recorded_hash(path: &Path, meta: &Metadata) -> Option<String> {
+    let current = FileState::from_metadata(meta).ok()?;
+    let state = STATE.lock().unwrap();
+    let recorded = state.files.get(path)?;
+    recorded.same_stat(&current).then(|| recorded.hash.clone())?
+}
+
+/// Where the files of `item` are in the repository, in the way `info` stores
+/// them. `is_dir` is whether the item is stored as a directory.
+fn stored_path(item: &Path, info: &SyncFile, is_dir: bool) -> PathBuf {
+    match info.archive {
+        Some(format) => packed_path(item, format),
+        None if info.encrypt == Some(Encryption::Names) => encrypted_dir(item),
+        None if is_dir => item.to_path_buf(),
+        None if info.encrypt.is_some() => encrypted_name(item),
+        None if info.compress.is_some() => compressed_name(item),
+        None if info.chunk => chunk_dir(item),
+        None => item.to_path_buf(),
+    }
+}
+
+/// Add the hashes of the files under `path` as committed in `rev`, e.g. for
+/// the items left out of the working tree by `sparse_checkout`.
+fn committed_hashes(rev: &str, path: &Path, files: &mut BTreeMap<PathBuf, String>) -> Result<()> {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let listed = git_checked(["ls-tree", "-r", "--name-only", rev, "--", &path])?;
+    for file in listed.lines().map(str::trim).filter(|file| !file.is_empty()) {
+        let content = git_output(git_command().args(["show", &format!("{}:{}", rev, file)]))?;
+        ensure!(content.status.success(), "failed to read `{}` in `{}`", file, rev);
+        files.insert(PathBuf::from(file), blake3::hash(&content.stdout).to_hex().to_string());
+    }
+    Ok(())
+}
+
+/// Write the manifest and sign it, if a signing key is configured. Without a
+/// key nothing is hashed, no device could trust the manifest anyway.
+pub fn write_manifest() -> Result<()> {
+    if CONFIG.read().unwrap().signing.key.is_none() {
+        return Ok(());
+    }
+    let manifest_path = REPO_PATH.join(MANIFEST_NAME);
+    current_manifest()?.to_config_file(&manifest_path)?;
+    sign(&manifest_path, NAMESPACE)?;
+    Ok(())
+}
+
+/// Sign `path` into `<path>.sig` with the key of the `[signing]` section, in
+/// the `namespace` of `ssh-keygen -Y`. Returns whether a key is configured.
+pub fn sign(path: &Path, namespace: &str) -> Result<bool> {
+    let Some(key) = CONFIG.read().unwrap().signing.key.clone() else {
+        return Ok(false);
+    };
+    let mut signature_path = path.as_os_str().to_owned();
+    signature_path.push(".sig");
+    let signature_path = PathBuf::from(signature_path);
+    if signature_path.exists() {
+        std::fs::remove_file(&signature_path)?;
+    }
+    let status = Command::new("ssh-keygen")
+        .args(["-Y", "sign", "-n", namespace, "-f"])
+        .arg(&key)
+        .arg(path)
+        .stdout(Stdio::null())
+        .status()?;
+    ensure!(status.success(), "failed to sign {:?} with `{:?}`", path, key);
+    Ok(true)
+}
+
+/// Verify the pulled manifest according to the [`SignaturePolicy`], with the
+/// keys of the `trusted` commit from before the pull. Fails only if the policy
+/// requires a valid signature.
+pub fn verify_manifest(trusted: &str) -> Result<()> {
+    verify_with_policy(None, trusted)
+}
+
+/// Like [`verify_manifest`], for the manifest and the files committed in
+/// `rev` instead of the working tree.
+pub fn verify_manifest_at(rev: &str, trusted: &str) -> Result<()> {
+    verify_with_policy(Some(rev), trusted)
+}
+
+fn verify_with_policy(rev: Option<&str>, trusted: &str) -> Result<()> {
+    with_policy(|| {
+        let device = verify(rev, trusted)?;
+        info!("manifest signed by `{}` verified", device);
+        Ok(())
+    })
+}
+
+/// Verify the tree merged onto `base` from the `branches` verified before:
+/// every file of the sync group is as in the signed manifest of one of them,
+/// or as committed in `base`. A merge cannot combine them into content no
+/// device signed.
+pub fn verify_merged(base: &str, branches: &[&str]) -> Result<()> {
+    with_policy(|| {
+        let base = manifest_of(Some(base))?;
+        let signed = branches
+            .iter()
+            .map(|branch| committed_manifest(branch))
+            .collect::<Result<Vec<_>>>()?;
+        for (path, hash) in manifest_of(None)?.files.iter() {
+            let known = base.files.get(path) == Some(hash)
+                || signed.iter().any(|manifest| manifest.files.get(path) == Some(hash));
+            ensure!(known, "`{:?}` of the merge is in no signed manifest", path);
+        }
+        info!("merged {} verified branches", branches.len());
+        Ok(())
+    })
+}
+
+/// The manifest committed in `rev`.
+pub fn committed_manifest(rev: &str) -> Result<Manifest> {
+    let object = format!("{}:{}", rev, MANIFEST_NAME);
+    let output = git_output(git_command().args(["show", &object]))?;
+    ensure!(output.status.success(), "`{}` has no manifest", rev);
+    Ok(toml::from_str(std::str::from_utf8(&output.stdout)?)?)
+}
+
+/// Run `check` according to the [`SignaturePolicy`]. Fails only if the policy
+/// requires a valid signature.
+fn with_policy(check: impl FnOnce() -> Result<()>) -> Result<()> {
+    let policy = CONFIG.read().unwrap().signing.policy;
+    if policy == SignaturePolicy::Off {
+        return Ok(());
+    }
+    match check() {
+        Ok(()) => Ok(()),
+        Err(e) if policy == SignaturePolicy::Warn => {
+            warn!("{:?}", e);
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// The `devices.toml` committed in `rev`, empty if there is none.
+fn devices_at(rev: &str) -> Result<Devices> {
+    let object = format!("{}:{}", rev, DEVICES_NAME);
+    let output = git_output(git_command().args(["show", &object]))?;
+    if !output.status.success() {
+        return Ok(Devices::default());
+    }
+    Ok(toml::from_str(std::str::from_utf8(&output.stdout)?)?)
+}
+
+/// Verify the manifest with the keys of `trusted`, returning the device that
+/// signed it.
+fn verify(rev: Option<&str>, trusted: &str) -> Result<String> {
+    let (manifest_path, signature_path) = match rev {
+        None => (REPO_PATH.join(MANIFEST_NAME), REPO_PATH.join(SIGNATURE_NAME)),
+        // Written out of the commit for `ssh-keygen`.
+        Some(rev) => {
+            let dir = state_dir()?.join("verify");
+            std::fs::create_dir_all(&dir)?;
+            for name in [MANIFEST_NAME, SIGNATURE_NAME] {
+                let blob = git_output(git_command().args(["show", &format!("{}:{}", rev, name)]))?;
+                if blob.status.success() {
+                    std::fs::write(dir.join(name), blob.stdout)?;
+                } else if dir.join(name).exists() {
+                    std::fs::remove_file(dir.join(name))?;
+                }
+            }
+            (dir.join(MANIFEST_NAME), dir.join(SIGNATURE_NAME))
+        }
+    };
+    let Ok(manifest) = Manifest::from_config_file(&manifest_path) else {
+        bail!("the manifest is missing or invalid");
+    };
+    ensure!(signature_path.exists(), "the manifest is not signed");
+
+    // Not the pulled `devices.toml`, anyone who can push could add a key.
+    let devices = devices_at(trusted)?;
+    let Some(info) = devices.devices.get(&manifest.device) else {
+        bail!(
+            "no public key of `{}` in the {} of {}",
+            manifest.device,
+            DEVICES_NAME,
+            trusted
+        );
+    };
+    let allowed_signers = state_dir()?.join("allowed_signers");
+    std::fs::write(
+        &allowed_signers,
+        format!(
+            "{} namespaces=\"{}\" {}\n",
+            manifest.device, NAMESPACE, info.public_key
+        ),
+    )?;
+    let status = Command::new("ssh-keygen")
+        .args(["-Y", "verify", "-n", NAMESPACE, "-I", &manifest.device, "-f"])
+        .arg(&allowed_signers)
+        .arg("-s")
+        .arg(&signature_path)
+        .stdin(File::open(&manifest_path)?)
+        .stdout(Stdio::null())
+        .status()?;
+    ensure!(
+        status.success(),
+        "invalid manifest signature of `{}`",
+        manifest.device
+    );
+
+    let current = manifest_of(rev)?;
+    for (path, hash) in current.files.iter() {
+        ensure!(
+            manifest.files.get(path) == Some(hash),
+            "`{:?}` does not match the signed manifest",
+            path
+        );
+    }
+    Ok(manifest.device)
+}
+
+/// The last manifest `device` committed on any branch, if it ever wrote one.
+pub fn last_manifest_of(device: &str) -> Result<Option<Manifest>> {
+    let revs = git_checked(["log", "--all", "--format=%H", "--", MANIFEST_NAME])?;
+    for rev in revs.lines().map(str::trim).filter(|rev| !rev.is_empty()) {
+        // Deleted in `rev`, or written by an older version.
+        let Ok(manifest) = committed_manifest(rev) else {
+            continue;
+        };
+        if manifest.device == device {
+            return Ok(Some(manifest));
+        }
+    }
+    Ok(None)
+}
+
+/// The hashes of the files of `item` in `manifest`, however `info` stores it.
+pub fn item_hashes<'a>(
+    manifest: &'a Manifest,
+    item: &Path,
+    info: &SyncFile,
+) -> Vec<(&'a PathBuf, &'a String)> {
+    let stored = [stored_path(item, info, true), stored_path(item, info, false)];
+    manifest
+        .files
+        .iter()
+        .filter(|(path, _)| stored.iter().any(|stored| path.starts_with(stored)))
+        .collect()
+}
+
+/// Whether `path` (relative to the repository) is one of the manifest files.
+pub fn is_manifest_file(path: &Path) -> bool {
+    path == Path::new(MANIFEST_NAME) || path == Path::new(SIGNATURE_NAME)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(storage: &str) -> SyncFile {
+        toml::from_str(&format!("path_on_devices = {{}}\nis_hardlink = false\n{}", storage))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_stored_path() {
+        let item = Path::new("work/clients");
+        let names = info("encrypt = \"names\"");
+        assert_eq!(stored_path(item, &names, true), Path::new("work/clients.enc"));
+        assert_eq!(stored_path(item, &names, false), Path::new("work/clients.enc"));
+        let contents = info("encrypt = \"contents\"");
+        // The files of a directory are encrypted one by one in it.
+        assert_eq!(stored_path(item, &contents, true), item);
+        assert_eq!(stored_path(item, &contents, false), Path::new("work/clients.age"));
+        // Encryption takes the place of compression.
+        let compressed = info("encrypt = \"contents\"\ncompress = \"zstd\"");
+        assert_eq!(stored_path(item, &compressed, false), Path::new("work/clients.age"));
+        assert_eq!(stored_path(item, &info(""), false), item);
+    }
+}
@@ -0,0 +1,183 @@
+//! Prometheus metrics of `gsb daemon`, served over HTTP on
+//! `daemon.metrics_addr`.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{Read, Write},
+    net::TcpListener,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use log::{debug, info};
+
+use crate::{
+    config::CONFIG,
+    events::{self, Event, EventSink},
+};
+
+static METRICS: Mutex<Metrics> = Mutex::new(Metrics {
+    actions: BTreeMap::new(),
+    syncs_failed: 0,
+    files_drifted: 0,
+    last_success: None,
+    pull_duration: Duration::ZERO,
+});
+
+struct Metrics {
+    /// Key: the action of a [`crate::report::Report`], e.g. `collect`.
+    actions: BTreeMap<&'static str, ActionMetrics>,
+    syncs_failed: u64,
+    files_drifted: u64,
+    last_success: Option<SystemTime>,
+    pull_duration: Duration,
+}
+
+#[derive(Default)]
+struct ActionMetrics {
+    files_copied: u64,
+    bytes_copied: u64,
+    errors: u64,
+}
+
+/// Counts the results of the runs.
+struct RunCounter;
+
+impl EventSink for RunCounter {
+    fn event(&self, event: &Event) {
+        let Event::CycleFinished {
+            action,
+            stats,
+            errors,
+            ..
+        } = event
+        else {
+            return;
+        };
+        let mut metrics = METRICS.lock().unwrap();
+        let action = metrics.actions.entry(*action).or_default();
+        action.files_copied += stats.files_copied;
+        action.bytes_copied += stats.bytes_copied;
+        action.errors += *errors as u64;
+    }
+}
+
+pub fn record_pull(duration: Duration) {
+    METRICS.lock().unwrap().pull_duration = duration;
+}
+
+pub fn record_drift(files: u64) {
+    METRICS.lock().unwrap().files_drifted += files;
+}
+
+/// Record the end of a whole sync.
+pub fn record_sync(success: bool) {
+    let mut metrics = METRICS.lock().unwrap();
+    if success {
+        metrics.last_success = Some(SystemTime::now());
+    } else {
+        metrics.syncs_failed += 1;
+    }
+}
+
+/// The metrics in the Prometheus text format.
+fn render() -> String {
+    let device = CONFIG.read().unwrap().this_device().replace(['\\', '"'], "_");
+    let metrics = METRICS.lock().unwrap();
+    let mut out = String::new();
+    let mut metric = |name: &str, help: &str, values: Vec<(Option<&str>, f64)>| {
+        let _ = writeln!(out, "# HELP gsb_{} {}", name, help);
+        let kind = if name.ends_with("_total") { "counter" } else { "gauge" };
+        let _ = writeln!(out, "# TYPE gsb_{} {}", name, kind);
+        for (action, value) in values {
+            let action = action.map(|action| format!(",action=\"{}\"", action));
+            let _ = writeln!(
+                out,
+                "gsb_{}{{device=\"{}\"{}}} {}",
+                name,
+                device,
+                action.unwrap_or_default(),
+                value
+            );
+        }
+    };
+    let per_action = |get: fn(&ActionMetrics) -> u64| {
+        metrics
+            .actions
+            .iter()
+            .map(|(action, values)| (Some(*action), get(values) as f64))
+            .collect::<Vec<_>>()
+    };
+    metric(
+        "files_copied_total",
+        "Files copied since the daemon started.",
+        per_action(|action| action.files_copied),
+    );
+    metric(
+        "bytes_copied_total",
+        "Bytes copied since the daemon started.",
+        per_action(|action| action.bytes_copied),
+    );
+    metric(
+        "item_errors_total",
+        "Items that failed since the daemon started.",
+        per_action(|action| action.errors),
+    );
+    metric(
+        "syncs_failed_total",
+        "Syncs that failed since the daemon started.",
+        vec![(None, metrics.syncs_failed as f64)],
+    );
+    metric(
+        "files_drifted_total",
+        "Files found differing from their copy by the background verification.",
+        vec![(None, metrics.files_drifted as f64)],
+    );
+    let last_success = metrics
+        .last_success
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |time| time.as_secs());
+    metric(
+        "last_success_timestamp_seconds",
+        "When the last sync succeeded, 0 if none did yet.",
+        vec![(None, last_success as f64)],
+    );
+    metric(
+        "pull_duration_seconds",
+        "How long the last pull took.",
+        vec![(None, metrics.pull_duration.as_secs_f64())],
+    );
+    out
+}
+
+/// Serve the metrics on `addr`, e.g. `127.0.0.1:9898`, in the background.
+pub fn serve(addr: &str) -> Result<()> {
+    let listener =
+        TcpListener::bind(addr).with_context(|| format!("failed to listen on `{}`", addr))?;
+    info!("serving metrics on http://{}/metrics", addr);
+    events::subscribe(RunCounter);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                continue;
+            };
+            // Any request gets the metrics, only the request line is read.
+            let mut request = [0; 1024];
+            let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+            let _ = stream.read(&mut request);
+            let body = render();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            if let Err(e) = stream.write_all(response.as_bytes()) {
+                debug!("failed to send the metrics: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,110 @@
+//! The mounted filesystems, to adjust to the filesystem a path lives on.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use crate::capabilities::common_capabilities;
+
+/// The filesystem types of network filesystems, on which mtimes and hardlinks
+/// are unreliable.
+const NETWORK_FS: &[&str] = &[
+    "nfs", "nfs4", "cifs", "smb3", "smbfs", "afpfs", "9p", "fuse.sshfs", "afs", "ceph",
+    "glusterfs", "webdav", "davfs",
+];
+
+static MOUNTS: LazyLock<Vec<Mount>> = LazyLock::new(load_mounts);
+
+#[derive(Debug, Clone)]
+pub struct Mount {
+    pub point: PathBuf,
+    pub fs_type: String,
+}
+
+#[cfg(target_os = "linux")]
+fn load_mounts() -> Vec<Mount> {
+    let Ok(mounts) = std::fs::read_to_string("/proc/self/mounts") else {
+        return Vec::new();
+    };
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace().skip(1);
+            Some(Mount {
+                point: PathBuf::from(unescape(fields.next()?)),
+                fs_type: fields.next()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Undo the octal escapes of `/proc/self/mounts`, e.g. `\040` for a space.
+#[cfg(target_os = "linux")]
+fn unescape(field: &str) -> String {
+    field
+        .replace("\\040", " ")
+        .replace("\\011", "\t")
+        .replace("\\012", "\n")
+        .replace("\\134", "\\")
+}
+
+/// Parse the output of `mount`, e.g. `//me@nas/share on /Volumes/share (smbfs,
+/// nodev, nosuid)`.
+#[cfg(target_os = "macos")]
+fn load_mounts() -> Vec<Mount> {
+    let Ok(output) = std::process::Command::new("mount").output() else {
+        return Vec::new();
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let (_, rest) = line.split_once(" on ")?;
+            let (point, options) = rest.rsplit_once(" (")?;
+            let fs_type = options.split([',', ')']).next()?;
+            Some(Mount {
+                point: PathBuf::from(point),
+                fs_type: fs_type.trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn load_mounts() -> Vec<Mount> {
+    Vec::new()
+}
+
+/// The mount `path` lives on. `path` does not need to exist, its nearest
+/// existing ancestor is used then.
+pub fn mount_of(path: &Path) -> Option<&'static Mount> {
+    let path = path
+        .ancestors()
+        .find_map(|ancestor| ancestor.canonicalize().ok())?;
+    MOUNTS
+        .iter()
+        .filter(|mount| path.starts_with(&mount.point))
+        .max_by_key(|mount| mount.point.as_os_str().len())
+}
+
+/// Whether `path` lives on a network filesystem like NFS or SMB.
+pub fn is_network_fs(path: &Path) -> bool {
+    #[cfg(windows)]
+    {
+        // A UNC path, canonicalized to `\\?\UNC\server\share`.
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let path = path.to_string_lossy();
+        if path.starts_with(r"\\?\UNC\") || (path.starts_with(r"\\") && !path.starts_with(r"\\?\"))
+        {
+            return true;
+        }
+    }
+    mount_of(path).is_some_and(|mount| NETWORK_FS.contains(&mount.fs_type.as_str()))
+}
+
+/// Whether a hardlink between `a` and `b` can be relied on. Not on network
+/// filesystems or filesystems without hardlinks, where they are copied
+/// instead.
+pub fn hardlink_usable(a: &Path, b: &Path) -> bool {
+    !is_network_fs(a) && !is_network_fs(b) && common_capabilities(a, b).hardlink
+}
@@ -0,0 +1,97 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context, Result};
+use log::info;
+
+use crate::{
+    cli::json_output,
+    commit::commit,
+    config::{save_config, CONFIG, CONFIG_NAME},
+    git_command::{git_checked, REPO_PATH},
+    state::{save_state, STATE},
+};
+
+/// Move the item `old` to `new` in the repository with `git mv`, so the history
+/// follows it, and update the config and the state to match.
+pub fn mv(old: &Path, new: &Path) -> Result<()> {
+    ensure!(new.is_relative(), "the path in the repository must be relative");
+    {
+        let config = CONFIG.read().unwrap();
+        let groups_contain = |item: &Path| {
+            config.sync_group.0.contains_key(item) || config.backup_group.0.contains_key(item)
+        };
+        if !groups_contain(old) {
+            bail!("`{}` is not an item", old.display());
+        }
+        if let Some(file) = config.included_item(old) {
+            bail!(
+                "`{}` comes from the included `{}`, move it there",
+                old.display(),
+                file.display()
+            );
+        }
+        if groups_contain(new) || REPO_PATH.join(new).exists() {
+            bail!("`{}` exists already", new.display());
+        }
+    }
+    if let Some(parent) = REPO_PATH.join(new).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let (old_str, new_str) = (
+        old.to_str().context("the path is not valid UTF-8")?,
+        new.to_str().context("the path is not valid UTF-8")?,
+    );
+    if REPO_PATH.join(old).exists() {
+        git_checked(["mv", "--", old_str, new_str])?;
+    }
+
+    {
+        let mut config = CONFIG.write().unwrap();
+        if let Some(info) = config.sync_group.0.remove(old) {
+            config.sync_group.0.insert(new.to_path_buf(), info);
+        }
+        if let Some(info) = config.backup_group.0.remove(old) {
+            config.backup_group.0.insert(new.to_path_buf(), info);
+        }
+        if let Some(service) = config.services.remove(old) {
+            config.services.insert(new.to_path_buf(), service);
+        }
+        for item in config.publish.items.iter_mut().filter(|item| *item == old) {
+            *item = new.to_path_buf();
+        }
+    }
+    save_config()?;
+    move_state(&REPO_PATH.join(old), &REPO_PATH.join(new));
+    save_state()?;
+
+    git_checked(["add", CONFIG_NAME])?;
+    commit("mv", [old, new])?;
+    info!("moved `{}` to `{}`", old.display(), new.display());
+    if json_output() {
+        println!("{}", serde_json::json!({ "moved": old, "to": new }));
+    }
+    Ok(())
+}
+
+/// Move the recorded states of the files under `old` to `new`, so the moved
+/// files are not copied again.
+fn move_state(old: &Path, new: &Path) {
+    let mut state = STATE.lock().unwrap();
+    let moved = state
+        .files
+        .keys()
+        .filter(|path| path.starts_with(old))
+        .cloned()
+        .collect::<Vec<PathBuf>>();
+    for path in moved {
+        if let Some(file) = state.files.remove(&path) {
+            let relative = path.strip_prefix(old).unwrap_or(Path::new(""));
+            let path = if relative.as_os_str().is_empty() {
+                new.to_path_buf()
+            } else {
+                new.join(relative)
+            };
+            state.files.insert(path, file);
+        }
+    }
+}
@@ -0,0 +1,49 @@
+//! Desktop notifications of the background runs (`gsb daemon` and `gsb
+//! watch`), so a file changing under the user does not go unnoticed.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use log::debug;
+use notify_rust::Notification;
+
+use crate::config::CONFIG;
+
+static BACKGROUND: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    Success,
+    Conflict,
+    Error,
+}
+
+/// Show notifications from now on. Interactive runs report on the terminal
+/// instead.
+pub fn enable() {
+    BACKGROUND.store(true, Ordering::Relaxed);
+}
+
+/// Show a notification if enabled and its category is not turned off. A
+/// failure to show it is only logged.
+pub fn notify(category: Category, summary: &str, body: &str) {
+    if !BACKGROUND.load(Ordering::Relaxed) {
+        return;
+    }
+    let config = CONFIG.read().unwrap().notifications;
+    let shown = match category {
+        Category::Success => config.success,
+        Category::Conflict => config.conflict,
+        Category::Error => config.error,
+    };
+    if !shown {
+        return;
+    }
+    if let Err(e) = Notification::new()
+        .appname("gsb")
+        .summary(summary)
+        .body(body)
+        .show()
+    {
+        debug!("failed to show a notification: {}", e);
+    }
+}
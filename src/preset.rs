@@ -0,0 +1,117 @@
+//! Presets tracking well-known locations in one go. Game saves are set up to
+//! survive being played on two machines: the newest save wins a conflict, and
+//! the other one is kept as a conflict copy.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use log::warn;
+
+use crate::{
+    cli::print_text,
+    config::{save_config, ConflictPolicy, SyncFile, CONFIG},
+    device::devicename,
+    sync::sync_push,
+    utils::confirm,
+    workspace::expand_home,
+};
+
+/// The globs of the directories holding the saves of one game each.
+#[cfg(windows)]
+const GAME_SAVES: &[&str] = &[
+    "~/Saved Games/*",
+    "~/Documents/My Games/*",
+    // Unity games, by company and game.
+    "~/AppData/LocalLow/*/*",
+];
+#[cfg(target_os = "macos")]
+const GAME_SAVES: &[&str] = &[
+    "~/Library/Application Support/unity.*",
+    "~/Library/Application Support/Godot/app_userdata/*",
+];
+#[cfg(not(any(windows, target_os = "macos")))]
+const GAME_SAVES: &[&str] = &[
+    "~/.config/unity3d/*/*",
+    "~/.local/share/godot/app_userdata/*",
+    "~/.local/share/Steam/steamapps/compatdata/*/pfx/drive_c/users/steamuser/Saved Games/*",
+];
+
+/// The files a publisher leaves in a save directory it syncs itself. Syncing
+/// such a directory twice only makes conflicts.
+const CLOUD_MARKERS: &[(&str, &str)] = &[("steam_autocloud.vdf", "Steam Cloud")];
+
+/// Track the game saves found in the usual places as items `games/<game>` of
+/// the sync group, then collect them. Asks for each unless `yes`; saves synced
+/// by the publisher's cloud are only tracked when asked.
+pub async fn games(yes: bool) -> Result<()> {
+    let mut added = 0;
+    for dir in find_game_saves()? {
+        let Some(name) = dir.file_name() else {
+            continue;
+        };
+        let item = Path::new("games").join(name);
+        let cloud = cloud_marker(&dir);
+        if let Some(cloud) = cloud {
+            warn!("`{}` is synced by {} already", dir.display(), cloud);
+        }
+        let question = format!("Sync the saves in `{}` as `{}`?", dir.display(), item.display());
+        let wanted = match (yes, cloud) {
+            (true, None) => true,
+            (true, Some(_)) => false,
+            (false, _) => confirm(&question)?,
+        };
+        if !wanted {
+            continue;
+        }
+        let mut config = CONFIG.write().unwrap();
+        if config.sync_group.0.contains_key(&item) {
+            continue;
+        }
+        let info = SyncFile {
+            path_on_devices: [(devicename(), dir.clone())].into(),
+            is_hardlink: false,
+            placeholder: Default::default(),
+            conflict: Some(ConflictPolicy::Newest),
+            conflict_copies: true,
+            profiles: Vec::new(),
+            tags: vec!["games".to_string()],
+            archive: None,
+            compress: None,
+            chunk: false,
+            encrypt: None,
+            limits: None,
+            lfs: false,
+        };
+        config.sync_group.0.insert(item, info);
+        added += 1;
+    }
+    if added == 0 {
+        print_text("no new game saves found");
+        return Ok(());
+    }
+    save_config()?;
+    sync_push(false).await
+}
+
+/// The existing directories matching [`GAME_SAVES`].
+fn find_game_saves() -> Result<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+    for pattern in GAME_SAVES {
+        let pattern = expand_home(Path::new(pattern));
+        for dir in glob::glob(&pattern.to_string_lossy())? {
+            let dir = dir?;
+            if dir.is_dir() {
+                dirs.push(dir);
+            }
+        }
+    }
+    Ok(dirs)
+}
+
+/// The cloud syncing the saves in `dir`, if a publisher's marker is found.
+fn cloud_marker(dir: &Path) -> Option<&'static str> {
+    CLOUD_MARKERS
+        .iter()
+        .find(|(marker, _)| dir.join(marker).exists())
+        .map(|(_, cloud)| *cloud)
+}
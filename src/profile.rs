@@ -0,0 +1,89 @@
+//! Profiling of a run with `--timings`: the time spent in each phase is
+//! printed as a table at the end. Built with the `profiling` feature, the main
+//! functions are also instrumented with `tracing` spans, written to
+//! `gsb.folded` in the flamegraph format (e.g. for `inferno-flamegraph`).
+
+use std::{
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+use crate::cli::CLI;
+
+static TIMINGS: LazyLock<Mutex<Vec<(&'static str, Duration)>>> =
+    LazyLock::new(|| Mutex::new(Vec::new()));
+
+#[cfg(feature = "profiling")]
+pub type Guard = tracing_flame::FlushGuard<std::io::BufWriter<std::fs::File>>;
+#[cfg(not(feature = "profiling"))]
+pub type Guard = ();
+
+fn enabled() -> bool {
+    CLI.get().is_some_and(|cli| cli.timings)
+}
+
+/// Start profiling if `--timings` is given. The guard has to be kept until the
+/// end of the run.
+pub fn init() -> Result<Option<Guard>> {
+    if !enabled() {
+        return Ok(None);
+    }
+    #[cfg(feature = "profiling")]
+    {
+        use tracing_subscriber::prelude::*;
+
+        let (layer, guard) = tracing_flame::FlameLayer::with_file("gsb.folded")?;
+        tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))?;
+        log::info!("writing the trace to `gsb.folded`");
+        Ok(Some(guard))
+    }
+    #[cfg(not(feature = "profiling"))]
+    Ok(Some(()))
+}
+
+/// A phase of the run, timed until dropped.
+pub struct Phase {
+    name: &'static str,
+    started: Instant,
+}
+
+pub fn phase(name: &'static str) -> Phase {
+    Phase {
+        name,
+        started: Instant::now(),
+    }
+}
+
+impl Drop for Phase {
+    fn drop(&mut self) {
+        if enabled() {
+            TIMINGS
+                .lock()
+                .unwrap()
+                .push((self.name, self.started.elapsed()));
+        }
+    }
+}
+
+/// Print the time spent in each phase, in the order they first ran.
+pub fn print_timings() {
+    if !enabled() {
+        return;
+    }
+    let mut phases: Vec<(&'static str, u32, Duration)> = Vec::new();
+    for (name, elapsed) in TIMINGS.lock().unwrap().iter() {
+        match phases.iter_mut().find(|(phase, ..)| phase == name) {
+            Some((_, count, total)) => {
+                *count += 1;
+                *total += *elapsed;
+            }
+            None => phases.push((name, 1, *elapsed)),
+        }
+    }
+    eprintln!("{:<12} {:>6} {:>12}", "phase", "count", "total");
+    for (name, count, total) in phases {
+        eprintln!("{:<12} {:>6} {:>12}", name, count, format!("{:.2?}", total));
+    }
+}
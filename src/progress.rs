@@ -0,0 +1,36 @@
+use std::{path::Path, sync::LazyLock, time::Duration};
+
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
+
+/// All progress bars are drawn together on stderr. They are hidden when
+/// stderr is not a terminal, e.g. in cron jobs.
+static PROGRESS: LazyLock<MultiProgress> = LazyLock::new(MultiProgress::new);
+
+/// The progress of a whole run over `len` items, e.g. `collect` or `restore`.
+pub fn overall_bar(action: &str, len: u64) -> ProgressBar {
+    let style = ProgressStyle::with_template("{prefix:>8} [{bar:30}] {pos}/{len} items ({elapsed})")
+        .unwrap()
+        .progress_chars("=> ");
+    PROGRESS.add(
+        ProgressBar::new(len)
+            .with_style(style)
+            .with_prefix(action.to_string()),
+    )
+}
+
+/// The progress of a single item, counting the processed files. Use
+/// [`set_bytes`] to show the copied bytes.
+pub fn item_bar(item: &Path) -> ProgressBar {
+    let style = ProgressStyle::with_template("{spinner} {prefix}: {pos} files, {msg}").unwrap();
+    let bar = PROGRESS.add(
+        ProgressBar::new_spinner()
+            .with_style(style)
+            .with_prefix(item.display().to_string()),
+    );
+    bar.enable_steady_tick(Duration::from_millis(100));
+    bar
+}
+
+pub fn set_bytes(bar: &ProgressBar, bytes: u64) {
+    bar.set_message(format!("{} copied", HumanBytes(bytes)));
+}
@@ -0,0 +1,185 @@
+//! `gsb prune-history`: squash the old commits of a branch into a fresh root
+//! commit, keeping the recent ones as they are, so frequent automated collects
+//! do not grow the repository forever.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, ensure, Context, Result};
+use log::info;
+
+use crate::{
+    git_command::{git, git_checked, git_command, git_output, git_remote, REMOTE_NAME},
+    utils::confirm,
+};
+
+/// How much of the history `gsb prune-history` keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keep {
+    /// The newest commits.
+    Commits(usize),
+    /// The commits younger than this.
+    Since(Duration),
+}
+
+impl std::str::FromStr for Keep {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Ok(commits) = s.parse() {
+            return Ok(Self::Commits(commits));
+        }
+        let duration = humantime::parse_duration(s)
+            .with_context(|| format!("`{}` is neither a number of commits nor a duration", s))?;
+        Ok(Self::Since(duration))
+    }
+}
+
+/// Squash the commits of `branch` older than `keep` into one root commit with
+/// the tree of the newest of them, then drop the old objects. The remote is
+/// only rewritten with `push`, which force-pushes.
+pub async fn prune_history(branch: &str, keep: Keep, yes: bool, push: bool) -> Result<()> {
+    // Newest first. Merges are flattened to their first parent, their trees
+    // are kept as they are.
+    let log = git_checked(["log", "--first-parent", "--format=%H %ct", branch, "--"])?;
+    let commits = log
+        .lines()
+        .filter_map(|line| line.trim().split_once(' '))
+        .map(|(commit, time)| (commit.to_string(), time.parse::<u64>().unwrap_or(0)))
+        .collect::<Vec<_>>();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let kept = kept(&commits, keep, now)?;
+    if kept >= commits.len().saturating_sub(1) {
+        info!(
+            "nothing to prune in `{}`, it has {} commits",
+            branch,
+            commits.len()
+        );
+        return Ok(());
+    }
+    let dropped = commits.len() - kept;
+    let (squashed, time) = &commits[kept];
+    let time = humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(*time));
+    if !yes
+        && !confirm(&format!(
+            "Squash the {} commits of `{}` up to {} into one? This rewrites the history.",
+            dropped, branch, time
+        ))?
+    {
+        return Ok(());
+    }
+
+    let message = format!("squash {} commits up to {}", dropped, time);
+    let tree = format!("{}^{{tree}}", squashed);
+    let mut parent = git_checked(["commit-tree", &tree, "-m", &message])?
+        .trim()
+        .to_string();
+    for (commit, _) in commits[..kept].iter().rev() {
+        parent = replay(commit, &parent)?;
+    }
+    git_checked(["update-ref", &format!("refs/heads/{}", branch), &parent])?;
+    info!(
+        "squashed {} commits of `{}` into one, kept {}",
+        dropped, branch, kept
+    );
+    git(["reflog", "expire", "--expire=now", "--all"])?;
+    git(["gc", "--prune=now", "--quiet"])?;
+
+    if push {
+        git_remote(["push", "--force", REMOTE_NAME, branch]).await?;
+        info!(
+            "rewrote `{}` on the remote, the other devices take it at their next pull",
+            branch
+        );
+    } else {
+        info!("the remote still has the old history, rewrite it with `--push`");
+    }
+    Ok(())
+}
+
+/// How many of `commits`, newest first with their commit times, `keep` keeps
+/// at `now`.
+fn kept(commits: &[(String, u64)], keep: Keep, now: u64) -> Result<usize> {
+    match keep {
+        Keep::Commits(count) => {
+            ensure!(count > 0, "prune-history keeps at least one commit");
+            Ok(count)
+        }
+        Keep::Since(duration) => {
+            let cutoff = now.saturating_sub(duration.as_secs());
+            let kept = commits
+                .iter()
+                .take_while(|(_, time)| *time >= cutoff)
+                .count();
+            ensure!(
+                kept > 0,
+                "no commit is younger than {}, keep a number of commits instead",
+                humantime::format_duration(duration)
+            );
+            Ok(kept)
+        }
+    }
+}
+
+/// Commit the tree of `commit` on `parent` with its message, authors and
+/// dates, returning the new commit.
+fn replay(commit: &str, parent: &str) -> Result<String> {
+    let format = "--format=%an%x00%ae%x00%aI%x00%cn%x00%ce%x00%cI%x00%B";
+    let info = git_checked(["log", "-1", format, commit])?;
+    let fields = info.splitn(7, '\0').collect::<Vec<_>>();
+    let [author, author_email, author_date, committer, committer_email, committer_date, message] =
+        fields[..]
+    else {
+        bail!("failed to read the commit `{}`", commit);
+    };
+    let tree = format!("{}^{{tree}}", commit);
+    let output = git_output(
+        git_command()
+            .env("GIT_AUTHOR_NAME", author)
+            .env("GIT_AUTHOR_EMAIL", author_email)
+            .env("GIT_AUTHOR_DATE", author_date)
+            .env("GIT_COMMITTER_NAME", committer)
+            .env("GIT_COMMITTER_EMAIL", committer_email)
+            .env("GIT_COMMITTER_DATE", committer_date)
+            .args(["commit-tree", &tree, "-p", parent, "-m", message.trim_end()]),
+    )?;
+    ensure!(
+        output.status.success(),
+        "failed to rewrite the commit `{}`: {}",
+        commit,
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    Ok(String::from_utf8(output.stdout)?.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commits(times: &[u64]) -> Vec<(String, u64)> {
+        let commits = times.iter().enumerate();
+        commits.map(|(i, time)| (i.to_string(), *time)).collect()
+    }
+
+    #[test]
+    fn test_keep_from_str() {
+        assert_eq!("5".parse::<Keep>().unwrap(), Keep::Commits(5));
+        assert_eq!(
+            "2h".parse::<Keep>().unwrap(),
+            Keep::Since(Duration::from_secs(2 * 3600))
+        );
+        assert!("garbage".parse::<Keep>().is_err());
+        assert!("-3".parse::<Keep>().is_err());
+    }
+
+    #[test]
+    fn test_kept() {
+        let commits = commits(&[1000, 900, 500, 100]);
+        assert_eq!(kept(&commits, Keep::Commits(2), 1000).unwrap(), 2);
+        assert!(kept(&commits, Keep::Commits(0), 1000).is_err());
+        let since = |secs| Keep::Since(Duration::from_secs(secs));
+        assert_eq!(kept(&commits, since(100), 1000).unwrap(), 2);
+        assert_eq!(kept(&commits, since(1000), 1000).unwrap(), 4);
+        let e = kept(&commits, since(10), 2000).unwrap_err();
+        assert!(e.to_string().contains("no commit is younger than"));
+    }
+}
@@ -0,0 +1,92 @@
+//! Publishing a subset of the items to a branch of its own, e.g. to share the
+//! public dotfiles while the repository itself stays private.
+
+use std::path::Path;
+
+use anyhow::{ensure, Context, Result};
+use log::info;
+
+use crate::{
+    cli::json_output,
+    config::CONFIG,
+    git_command::{git, git_checked, git_command, git_output, git_remote, REMOTE_NAME, REPO_PATH},
+    state::state_dir,
+};
+
+/// Commit the items of `[publish]` as they are in the repository to the publish
+/// branch, then push it. Nothing else is published, not even the config file,
+/// which contains the paths on the devices.
+pub async fn publish() -> Result<()> {
+    let config = CONFIG.read().unwrap().publish.clone();
+    ensure!(!config.items.is_empty(), "no item to publish in `[publish]`");
+    let items = config
+        .items
+        .iter()
+        .map(|item| item.to_str().context("the item path is not valid UTF-8"))
+        .collect::<Result<Vec<_>>>()?;
+    for item in items.iter() {
+        ensure!(
+            REPO_PATH.join(item).exists(),
+            "`{}` does not exist in the repository",
+            item
+        );
+    }
+
+    let index = state_dir()?.join("publish-index");
+    if index.exists() {
+        std::fs::remove_file(&index)?;
+    }
+    git_with_index(&index, ["read-tree", "--empty"])?;
+    git_with_index(&index, ["add", "--"].into_iter().chain(items.iter().copied()))?;
+    let tree = git_with_index(&index, ["write-tree"])?;
+    std::fs::remove_file(&index)?;
+
+    let reference = format!("refs/heads/{}", config.branch);
+    let parent = git(["rev-parse", "--verify", "--quiet", &reference])?;
+    let parent = parent.trim();
+    if !parent.is_empty() && git(["rev-parse", &format!("{}^{{tree}}", parent)])?.trim() == tree {
+        info!("nothing changed since the last publish");
+        print_published(&items, &config.branch, None);
+        return Ok(());
+    }
+    let mut args = vec!["commit-tree", tree.as_str(), "-m", "publish"];
+    if !parent.is_empty() {
+        args.extend(["-p", parent]);
+    }
+    let commit = git_checked(args)?;
+    git(["update-ref", &reference, commit.trim()])?;
+
+    let remote = config.remote.as_deref().unwrap_or(REMOTE_NAME);
+    git_remote(["push", remote, &format!("{0}:{0}", config.branch)]).await?;
+    info!("published {} items to `{}` of `{}`", items.len(), config.branch, remote);
+    print_published(&items, &config.branch, Some(remote));
+    Ok(())
+}
+
+/// The result of a publish with `--output json`, pushed to `remote` unless
+/// nothing changed.
+fn print_published(items: &[&str], branch: &str, remote: Option<&str>) {
+    if json_output() {
+        let output = serde_json::json!({
+            "items": items,
+            "branch": branch,
+            "pushed_to": remote,
+        });
+        println!("{}", output);
+    }
+}
+
+/// Run git on the index file `index` instead of the one of the repository,
+/// returning the trimmed stdout.
+fn git_with_index<'a>(
+    index: &Path,
+    args: impl IntoIterator<Item = &'a str>,
+) -> Result<String> {
+    let output = git_output(git_command().args(args).env("GIT_INDEX_FILE", index))?;
+    ensure!(
+        output.status.success(),
+        "git failed: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    Ok(String::from_utf8(output.stdout)?.trim().to_string())
+}
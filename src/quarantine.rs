@@ -0,0 +1,302 @@
+use std::{
+    collections::BTreeMap,
+    fs::Metadata,
+    io,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex},
+};
+
+use anyhow::{anyhow, Result};
+use config_file::*;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cli::json_output,
+    config::PlaceholderPolicy,
+    git_command::REPO_PATH,
+    state::{state_dir, STATE_DIR},
+    utils::copy_item,
+};
+
+const QUARANTINE_DIR: &str = "quarantine";
+const QUARANTINE_NAME: &str = "quarantine.toml";
+
+pub static QUARANTINE: LazyLock<Mutex<Quarantine>> =
+    LazyLock::new(|| Mutex::new(Quarantine::load_or_default()));
+
+/// A restore that was held back because it looked suspicious.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QuarantineEntry {
+    /// Relative path of the item in the repository.
+    pub item: PathBuf,
+    /// Where the item would have been restored to.
+    pub target: PathBuf,
+    pub reason: String,
+}
+
+/// The restores waiting for `gsb approve`. Their content is kept in
+/// `.gsb/quarantine/<id>`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Quarantine {
+    /// Key: the id of the entry.
+    #[serde(default)]
+    pub entries: BTreeMap<String, QuarantineEntry>,
+}
+
+impl Quarantine {
+    /// Read the quarantine from disk, as another process may have changed it.
+    pub fn load_or_default() -> Self {
+        Self::from_config_file(REPO_PATH.join(STATE_DIR).join(QUARANTINE_NAME))
+            .unwrap_or_default()
+    }
+
+    fn next_id(&self) -> String {
+        let max = self.entries.keys().filter_map(|id| id.parse::<u64>().ok()).max();
+        max.map_or(1, |max| max + 1).to_string()
+    }
+}
+
+pub fn save_quarantine() -> Result<()> {
+    let path = state_dir()?.join(QUARANTINE_NAME);
+    QUARANTINE.lock().unwrap().to_config_file(path)?;
+    Ok(())
+}
+
+/// Why restoring `from` over `to` looks suspicious, if it does: a file shrank
+/// by more than 90% or became empty, a file turned into a directory or the
+/// other way around, or a file became executable.
+pub fn suspicion(from: &Path, to: &Path) -> Result<Option<String>> {
+    for entry in walkdir::WalkDir::new(from) {
+        let entry = entry?;
+        let relative = entry.path().strip_prefix(from)?;
+        let target = if relative.as_os_str().is_empty() {
+            to.to_path_buf()
+        } else {
+            to.join(relative)
+        };
+        if let Some(reason) = file_suspicion(&entry.metadata()?, &target) {
+            return Ok(Some(format!("`{}` {}", target.display(), reason)));
+        }
+    }
+    Ok(None)
+}
+
+fn file_suspicion(from: &Metadata, to: &Path) -> Option<&'static str> {
+    let to = to.symlink_metadata().ok()?;
+    if from.is_dir() && !to.is_dir() {
+        return Some("would turn from a file into a directory");
+    }
+    if !from.is_dir() && to.is_dir() {
+        return Some("would turn from a directory into a file");
+    }
+    if from.is_file() && from.len() == 0 && to.len() > 0 {
+        return Some("would become empty");
+    }
+    if from.is_file() && from.len() * 10 < to.len() {
+        return Some("would shrink by more than 90%");
+    }
+    if gained_executable(from, &to) {
+        return Some("would become executable");
+    }
+    None
+}
+
+#[cfg(unix)]
+fn gained_executable(from: &Metadata, to: &Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    let executable = |meta: &Metadata| meta.is_file() && meta.permissions().mode() & 0o111 != 0;
+    executable(from) && !executable(to)
+}
+
+#[cfg(not(unix))]
+fn gained_executable(_from: &Metadata, _to: &Metadata) -> bool {
+    false
+}
+
+/// Hold back the restore of `item` (at `from` in the repository) to `to`.
+pub async fn quarantine(item: &Path, from: &Path, to: &Path, reason: String) -> Result<()> {
+    // The id is taken with the entry, so parallel restores cannot share it.
+    let id = {
+        let mut quarantine = QUARANTINE.lock().unwrap();
+        let id = quarantine.next_id();
+        let entry = QuarantineEntry {
+            item: item.to_path_buf(),
+            target: to.to_path_buf(),
+            reason: reason.clone(),
+        };
+        quarantine.entries.insert(id.clone(), entry);
+        id
+    };
+    let held = state_dir()?.join(QUARANTINE_DIR).join(&id);
+    if let Err(e) = copy_item(from, &held, PlaceholderPolicy::Hydrate).await {
+        QUARANTINE.lock().unwrap().entries.remove(&id);
+        return Err(e);
+    }
+    let item_name = item.to_string_lossy();
+    warn!(
+        item = item_name.as_ref(), action = "quarantine";
+        "quarantined `{}`: {}; run `gsb approve {}` to restore it anyway",
+        item_name, reason, id
+    );
+    Ok(())
+}
+
+/// Whether `e` was caused by missing permissions, e.g. a restore target that
+/// needs admin rights.
+pub fn is_access_denied(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        cause
+            .downcast_ref::<io::Error>()
+            .is_some_and(|e| e.kind() == io::ErrorKind::PermissionDenied)
+    })
+}
+
+/// Run `gsb approve` with `ids` again as an administrator, through UAC.
+#[cfg(windows)]
+fn approve_elevated(ids: &[String]) -> Result<()> {
+    let quote = |arg: &str| format!("'\"{}\"'", arg.replace('\'', "''"));
+    let exe = std::env::current_exe()?;
+    let repo = REPO_PATH.canonicalize()?;
+    let args = ["--repo", &repo.to_string_lossy(), "approve"]
+        .into_iter()
+        .chain(ids.iter().map(String::as_str))
+        .map(quote)
+        .collect::<Vec<_>>()
+        .join(",");
+    let status = std::process::Command::new("powershell")
+        .args(["-NoProfile", "-Command"])
+        .arg(format!(
+            "Start-Process -FilePath {} -ArgumentList {} -Verb RunAs -Wait",
+            quote(&exe.to_string_lossy()),
+            args
+        ))
+        .status()?;
+    anyhow::ensure!(status.success(), "failed to run elevated: {}", status);
+    Ok(())
+}
+
+#[cfg(not(windows))]
+fn approve_elevated(_ids: &[String]) -> Result<()> {
+    anyhow::bail!("`--elevate` is only supported on Windows, run gsb with sudo instead")
+}
+
+/// Restore the quarantined entries `ids`, or drop them if `reject`. Without
+/// any id, list the entries instead. With `elevate`, the restores are run as
+/// an administrator.
+pub async fn approve(ids: &[String], reject: bool, elevate: bool) -> Result<()> {
+    if elevate && !ids.is_empty() {
+        return approve_elevated(ids);
+    }
+    if ids.is_empty() {
+        let quarantine = QUARANTINE.lock().unwrap();
+        if json_output() {
+            println!("{}", serde_json::to_string(&quarantine.entries)?);
+            return Ok(());
+        }
+        for (id, entry) in quarantine.entries.iter() {
+            println!(
+                "{}: `{}` to `{}`, {}",
+                id,
+                entry.item.display(),
+                entry.target.display(),
+                entry.reason
+            );
+        }
+        return Ok(());
+    }
+
+    for id in ids {
+        let entry = QUARANTINE
+            .lock()
+            .unwrap()
+            .entries
+            .remove(id)
+            .ok_or_else(|| anyhow!("no quarantined restore with id `{}`", id))?;
+        let content = state_dir()?.join(QUARANTINE_DIR).join(id);
+        if reject {
+            info!("rejected `{:?}`", entry.item);
+        } else {
+            copy_item(&content, &entry.target, PlaceholderPolicy::Hydrate).await?;
+            info!("restored `{:?}` to `{:?}`", entry.item, entry.target);
+        }
+        if content.is_dir() {
+            std::fs::remove_dir_all(content)?;
+        } else {
+            std::fs::remove_file(content)?;
+        }
+    }
+    save_quarantine()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_dir;
+
+    /// The suspicion of restoring `from` over `to`, given as file contents or
+    /// `None` for a directory.
+    fn suspicion_of(name: &str, from: Option<&[u8]>, to: Option<&[u8]>) -> Option<&'static str> {
+        let dir = test_dir(name);
+        for (path, content) in [(dir.join("from"), from), (dir.join("to"), to)] {
+            match content {
+                Some(content) => std::fs::write(&path, content).unwrap(),
+                None => std::fs::create_dir(&path).unwrap(),
+            }
+        }
+        let from = std::fs::metadata(dir.join("from")).unwrap();
+        let suspicion = file_suspicion(&from, &dir.join("to"));
+        std::fs::remove_dir_all(dir).unwrap();
+        suspicion
+    }
+
+    #[test]
+    fn test_file_suspicion() {
+        let big = [b'a'; 1000];
+        assert_eq!(suspicion_of("same", Some(&big), Some(&big)), None);
+        assert_eq!(suspicion_of("grow", Some(&big), Some(b"a")), None);
+        assert_eq!(suspicion_of("dirs", None, None), None);
+        assert_eq!(suspicion_of("empty", Some(b""), Some(b"a")), Some("would become empty"));
+        assert_eq!(suspicion_of("both_empty", Some(b""), Some(b"")), None);
+        // Exactly 90% smaller is still fine.
+        assert_eq!(suspicion_of("shrink_90", Some(&big[..100]), Some(&big)), None);
+        assert_eq!(
+            suspicion_of("shrink", Some(&big[..99]), Some(&big)),
+            Some("would shrink by more than 90%")
+        );
+        assert_eq!(
+            suspicion_of("to_dir", None, Some(b"a")),
+            Some("would turn from a file into a directory")
+        );
+        assert_eq!(
+            suspicion_of("to_file", Some(b"a"), None),
+            Some("would turn from a directory into a file")
+        );
+    }
+
+    #[test]
+    fn test_file_suspicion_new_target() {
+        let dir = test_dir("new_target");
+        std::fs::write(dir.join("from"), "").unwrap();
+        let from = std::fs::metadata(dir.join("from")).unwrap();
+        assert_eq!(file_suspicion(&from, &dir.join("missing")), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_suspicion_executable() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = test_dir("executable");
+        let (from, to) = (dir.join("from"), dir.join("to"));
+        std::fs::write(&from, "echo").unwrap();
+        std::fs::write(&to, "echo").unwrap();
+        let from_meta = || std::fs::metadata(&from).unwrap();
+        assert_eq!(file_suspicion(&from_meta(), &to), None);
+        std::fs::set_permissions(&from, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(file_suspicion(&from_meta(), &to), Some("would become executable"));
+        std::fs::set_permissions(&to, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(file_suspicion(&from_meta(), &to), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,133 @@
+//! Receipts of the restores: every restore writes what it wrote where, with the
+//! hashes and the source commit, into `.gsb/receipts/`, signed like the
+//! manifest. With `restore.commit_receipts`, a copy is committed in
+//! `.gsb-receipts/<device>/`, to prove later what state a device was put into.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{Mutex, Once},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use config_file::*;
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::{
+    commit::commit,
+    config::CONFIG,
+    events::{self, Event, EventSink},
+    git_command::{git, REPO_PATH},
+    manifest::sign,
+    state::state_dir,
+    utils::hash_file,
+};
+
+const RECEIPTS_DIR: &str = "receipts";
+/// Where the receipts are committed, in a directory per device.
+pub const COMMITTED_RECEIPTS_DIR: &str = ".gsb-receipts";
+const NAMESPACE: &str = "gsb-receipt";
+
+/// The files written since [`begin`], `None` outside of a restore.
+static WRITTEN: Mutex<Option<Vec<(PathBuf, PathBuf)>>> = Mutex::new(None);
+static SUBSCRIBE: Once = Once::new();
+
+#[derive(Serialize, Debug)]
+struct Receipt {
+    device: String,
+    time: String,
+    /// The commit the files were restored from.
+    commit: String,
+    files: Vec<ReceiptFile>,
+}
+
+#[derive(Serialize, Debug)]
+struct ReceiptFile {
+    /// Relative to the repository if the file came from it.
+    source: PathBuf,
+    target: PathBuf,
+    /// The blake3 hash of the written file.
+    hash: String,
+}
+
+/// Notes the files copied while a restore runs.
+struct Recorder;
+
+impl EventSink for Recorder {
+    fn event(&self, event: &Event) {
+        if let Event::FileCopied { from, to, .. } = event {
+            if let Some(written) = WRITTEN.lock().unwrap().as_mut() {
+                written.push((from.clone(), to.clone()));
+            }
+        }
+    }
+}
+
+/// Start noting the files written by a restore.
+pub fn begin() {
+    SUBSCRIBE.call_once(|| events::subscribe(Recorder));
+    *WRITTEN.lock().unwrap() = Some(Vec::new());
+}
+
+/// Write the receipt of the files written since [`begin`] from `commit`, if
+/// any were.
+pub fn write(commit: &str) -> Result<()> {
+    let Some(written) = WRITTEN.lock().unwrap().take() else {
+        return Ok(());
+    };
+    if written.is_empty() {
+        return Ok(());
+    }
+    let mut files = Vec::new();
+    for (from, to) in written {
+        let hash = match hash_file(&to) {
+            Ok(hash) => hash,
+            Err(e) => {
+                warn!("failed to hash {:?} for the receipt: {}", to, e);
+                continue;
+            }
+        };
+        let source = from
+            .strip_prefix(REPO_PATH.as_path())
+            .map_or(from.clone(), Path::to_path_buf);
+        files.push(ReceiptFile {
+            source,
+            target: to,
+            hash,
+        });
+    }
+    let now = SystemTime::now();
+    let config = CONFIG.read().unwrap().clone();
+    let receipt = Receipt {
+        device: config.this_device(),
+        time: humantime::format_rfc3339_seconds(now).to_string(),
+        commit: commit.to_string(),
+        files,
+    };
+    let name = format!("{}.toml", now.duration_since(UNIX_EPOCH)?.as_secs());
+    let dir = state_dir()?.join(RECEIPTS_DIR);
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(&name);
+    receipt.to_config_file(&path)?;
+    let signed = sign(&path, NAMESPACE)?;
+    info!("wrote the receipt of the restore to {:?}", path);
+
+    if config.restore.commit_receipts {
+        let committed = Path::new(COMMITTED_RECEIPTS_DIR).join(config.this_device());
+        std::fs::create_dir_all(REPO_PATH.join(&committed))?;
+        std::fs::copy(&path, REPO_PATH.join(committed.join(&name)))?;
+        if signed {
+            let signature = format!("{}.sig", name);
+            std::fs::copy(dir.join(&signature), REPO_PATH.join(committed.join(signature)))?;
+        }
+        git(["add", "--", &committed.to_string_lossy()])?;
+        commit("receipt", [committed.as_path()])?;
+    }
+    Ok(())
+}
+
+/// Whether `path` (relative to the repository) is a committed receipt.
+pub fn is_receipt_file(path: &Path) -> bool {
+    path.starts_with(COMMITTED_RECEIPTS_DIR)
+}
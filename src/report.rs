@@ -0,0 +1,242 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{anyhow, Result};
+use indicatif::{HumanBytes, HumanDuration};
+use log::{debug, info, warn};
+use serde::Serialize;
+
+use crate::{
+    cli::{events_output, json_output},
+    config::CONFIG,
+    events::{emit, Event},
+    state::state_dir,
+    utils::CopyStats,
+    webhook,
+};
+
+/// The report of the last run in `.gsb/`, for `gsb status` and external tools.
+pub const LAST_RUN_NAME: &str = "last-run.json";
+
+/// The result of a single item in a [`Report`].
+#[derive(Serialize, Debug, Clone)]
+struct ItemResult {
+    item: PathBuf,
+    #[serde(flatten)]
+    stats: CopyStats,
+    error: Option<String>,
+    elapsed_ms: u64,
+}
+
+/// The summary of a whole run (e.g. `collect` or `restore`), printed at the
+/// end of it.
+pub struct Report {
+    action: &'static str,
+    started: Instant,
+    items: Vec<ItemResult>,
+    stats: CopyStats,
+    errors: Vec<anyhow::Error>,
+}
+
+impl Report {
+    pub fn new(action: &'static str) -> Self {
+        Self {
+            action,
+            started: Instant::now(),
+            items: Vec::new(),
+            stats: CopyStats::default(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Add the result of an item, and log it with the `item` and `action`
+    /// fields, see [`crate::system_log`].
+    pub fn add(&mut self, item: PathBuf, result: Result<CopyStats>, elapsed: Duration) {
+        let (stats, error) = match result {
+            Ok(stats) => (stats, None),
+            Err(e) => {
+                let error = format!("{:#}", e);
+                self.errors.push(e);
+                (CopyStats::default(), Some(error))
+            }
+        };
+        let (name, action) = (item.to_string_lossy(), self.action);
+        match error.as_ref() {
+            Some(error) => warn!(
+                item = name.as_ref(), action = action;
+                "{} of `{}` failed: {}", action, name, error
+            ),
+            None if stats.files_copied > 0 => info!(
+                item = name.as_ref(), action = action;
+                "{} of `{}`: {} files copied", action, name, stats.files_copied
+            ),
+            None => debug!(
+                item = name.as_ref(), action = action;
+                "{} of `{}`: unchanged", action, name
+            ),
+        }
+        self.stats += stats;
+        self.items.push(ItemResult {
+            item,
+            stats,
+            error,
+            elapsed_ms: elapsed.as_millis() as u64,
+        });
+    }
+
+    /// Add the results of the items run by [`crate::utils::run_items`].
+    pub fn add_all<E>(
+        &mut self,
+        results: Vec<Result<(PathBuf, Result<CopyStats>, Duration), E>>,
+    ) where
+        E: Into<anyhow::Error>,
+    {
+        for result in results {
+            match result {
+                Ok((item, result, elapsed)) => self.add(item, result, elapsed),
+                Err(e) => self.add(PathBuf::from("unknown"), Err(e.into()), Duration::ZERO),
+            }
+        }
+    }
+
+    /// The totals of all items so far.
+    pub fn stats(&self) -> CopyStats {
+        self.stats
+    }
+
+    /// The report as JSON, for `--output json` and the webhook.
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "action": self.action,
+            "timestamp": humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            "device": CONFIG.read().unwrap().this_device(),
+            "items": self.items,
+            "total": self.stats,
+            "errors": self.errors.len(),
+            "elapsed_ms": self.started.elapsed().as_millis() as u64,
+        })
+    }
+
+    pub fn print(&self) {
+        if events_output() {
+            return;
+        }
+        if json_output() {
+            println!("{}", self.to_json());
+            return;
+        }
+        println!("{} summary", self.action);
+        println!("  items          {}", self.items.len());
+        println!("  files copied   {}", self.stats.files_copied);
+        println!("  files skipped  {}", self.stats.files_skipped);
+        println!("  bytes written  {}", HumanBytes(self.stats.bytes_copied));
+        println!("  errors         {}", self.errors.len());
+        println!("  elapsed        {}", HumanDuration(self.started.elapsed()));
+        for item in self.items.iter() {
+            if let Some(error) = item.error.as_ref() {
+                println!("  error in `{}`: {}", item.item.display(), error);
+            }
+        }
+    }
+
+    /// Print the summary, then fail if any item failed. The other items are
+    /// processed anyway, so one bad item does not stop the whole run.
+    pub fn finish(self) -> Result<()> {
+        self.print();
+        let result = if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "{} of {} items failed in {}",
+                self.errors.len(),
+                self.items.len(),
+                self.action
+            ))
+        };
+        let summary = match result.as_ref() {
+            Ok(()) => format!(
+                "{} on {}: {} items, {} files copied",
+                self.action,
+                CONFIG.read().unwrap().this_device(),
+                self.items.len(),
+                self.stats.files_copied
+            ),
+            Err(e) => format!("{} on {}: {}", self.action, CONFIG.read().unwrap().this_device(), e),
+        };
+        emit(Event::CycleFinished {
+            action: self.action,
+            items: self.items.len(),
+            stats: self.stats,
+            errors: self.errors.len(),
+            elapsed_ms: self.started.elapsed().as_millis() as u64,
+        });
+        let json = self.to_json();
+        if let Err(e) = write_last_run(&json) {
+            warn!("failed to write {}: {:#}", LAST_RUN_NAME, e);
+        }
+        webhook::send(&json, &summary);
+        result
+    }
+}
+
+fn write_last_run(json: &serde_json::Value) -> Result<()> {
+    std::fs::write(state_dir()?.join(LAST_RUN_NAME), serde_json::to_string_pretty(json)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use log::{
+        kv::{self, Key, VisitSource},
+        Log, Metadata, Record,
+    };
+
+    use super::*;
+
+    /// The key-values of the records logged so far.
+    static LOGGED: Mutex<Vec<Vec<(String, String)>>> = Mutex::new(Vec::new());
+
+    struct Capture;
+
+    struct Fields(Vec<(String, String)>);
+
+    impl<'kvs> VisitSource<'kvs> for Fields {
+        fn visit_pair(&mut self, key: Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+            self.0.push((key.to_string(), value.to_string()));
+            Ok(())
+        }
+    }
+
+    impl Log for Capture {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            let mut fields = Fields(Vec::new());
+            let _ = record.key_values().visit(&mut fields);
+            LOGGED.lock().unwrap().push(fields.0);
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn test_item_fields() {
+        log::set_logger(&Capture).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+        let mut report = Report::new("collect");
+        report.add(PathBuf::from("notes"), Err(anyhow!("denied")), Duration::ZERO);
+        let fields = |item: &str| {
+            vec![
+                ("item".to_string(), item.to_string()),
+                ("action".to_string(), "collect".to_string()),
+            ]
+        };
+        assert!(LOGGED.lock().unwrap().contains(&fields("notes")));
+    }
+}
@@ -0,0 +1,62 @@
+use std::{path::Path, process::Command};
+
+use anyhow::{ensure, Result};
+use log::{info, warn};
+
+use crate::config::CONFIG;
+
+/// Restart the services (see [`crate::config::Config::services`]) whose items
+/// contain any of the `changed` paths. Each service is restarted at most once.
+/// A failed restart is only warned about, the files are restored anyway.
+pub fn restart_services(changed: &[&Path]) {
+    let services = CONFIG.read().unwrap().services.clone();
+    for (item, service) in services
+        .iter()
+        .filter(|(item, _)| changed.iter().any(|path| path.starts_with(item)))
+    {
+        let item = item.to_string_lossy();
+        info!(
+            item = item.as_ref(), action = "restart";
+            "`{}` changed, restarting `{}`", item, service
+        );
+        if let Err(e) = restart(service) {
+            warn!("{:?}", e);
+        }
+    }
+}
+
+fn restart(service: &str) -> Result<()> {
+    let status = restart_command(service)?.status()?;
+    ensure!(status.success(), "failed to restart `{}`: {}", service, status);
+    Ok(())
+}
+
+/// A systemd user unit. Reloaded if it supports that, restarted otherwise.
+#[cfg(target_os = "linux")]
+fn restart_command(service: &str) -> Result<Command> {
+    let mut command = Command::new("systemctl");
+    command.args(["--user", "reload-or-restart", service]);
+    Ok(command)
+}
+
+/// A launchd label in the GUI domain of the current user.
+#[cfg(target_os = "macos")]
+fn restart_command(service: &str) -> Result<Command> {
+    let uid = String::from_utf8(Command::new("id").arg("-u").output()?.stdout)?;
+    let mut command = Command::new("launchctl");
+    command.args(["kickstart", "-k", &format!("gui/{}/{}", uid.trim(), service)]);
+    Ok(command)
+}
+
+/// A Windows service.
+#[cfg(target_os = "windows")]
+fn restart_command(service: &str) -> Result<Command> {
+    let mut command = Command::new("powershell");
+    command.args(["-NoProfile", "-Command", "Restart-Service", "-Name", service]);
+    Ok(command)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+fn restart_command(service: &str) -> Result<Command> {
+    anyhow::bail!("restarting `{}` is not supported on this platform", service)
+}
@@ -0,0 +1,136 @@
+//! `gsb scan-projects`: find the files ignored by the projects in the
+//! workspaces that are worth a backup, like `.env`, and propose items for
+//! them. These are exactly the files lost with a laptop.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use log::debug;
+
+use crate::{
+    backup::backup,
+    cli::print_text,
+    config::{save_config, BackupFile, CONFIG},
+    git_command::git_command,
+    utils::{confirm, prompt},
+    workspace::workspace_root,
+};
+
+/// The directories never worth looking into.
+const SKIP_DIRS: &[&str] = &[".git", "node_modules", "target", ".venv", "venv", "__pycache__"];
+
+/// Walk the workspaces and propose a backup item for every kind of ignored
+/// file matching `scan.patterns`, e.g. `*/.env` in the workspace `projects`.
+/// Adds them all without asking if `yes`, then backs them up.
+pub async fn scan_projects(yes: bool) -> Result<()> {
+    let (workspaces, patterns) = {
+        let config = CONFIG.read().unwrap();
+        let patterns = config
+            .scan
+            .patterns
+            .iter()
+            .map(|pattern| glob::Pattern::new(pattern))
+            .collect::<Result<Vec<_>, _>>()?;
+        (config.workspaces.keys().cloned().collect::<Vec<_>>(), patterns)
+    };
+    let mut added = 0;
+    for workspace in workspaces {
+        let root = workspace_root(&workspace)?;
+        for (glob, files) in find(&root, &patterns)? {
+            print_text(&format!("{}: `{}`, {} files", workspace, glob, files.len()));
+            for file in files.iter() {
+                print_text(&format!("  {}", file.display()));
+            }
+            let mut item = default_item(&workspace, &glob);
+            if !yes {
+                let answer = prompt("Path in the repository", &item.to_string_lossy())?;
+                item = PathBuf::from(answer);
+                if !confirm(&format!("Back up `{}` as `{}`?", glob, item.display()))? {
+                    continue;
+                }
+            }
+            let mut config = CONFIG.write().unwrap();
+            if config.backup_group.0.contains_key(&item) {
+                print_text(&format!("`{}` is already an item", item.display()));
+                continue;
+            }
+            let info = BackupFile {
+                path_on_device: PathBuf::from(&glob),
+                is_hardlink: false,
+                placeholder: Default::default(),
+                profiles: Vec::new(),
+                tags: Vec::new(),
+                workspace: Some(workspace.clone()),
+                limits: None,
+                lfs: false,
+            };
+            config.backup_group.0.insert(item, info);
+            added += 1;
+        }
+    }
+    if added == 0 {
+        print_text("nothing to add");
+        return Ok(());
+    }
+    save_config()?;
+    backup().await
+}
+
+/// The ignored files under `root` matching `patterns`, by their glob relative
+/// to `root` with the project directory replaced by `*`.
+fn find(root: &Path, patterns: &[glob::Pattern]) -> Result<BTreeMap<String, Vec<PathBuf>>> {
+    let mut found: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    let walker = walkdir::WalkDir::new(root).into_iter().filter_entry(|entry| {
+        !(entry.file_type().is_dir()
+            && SKIP_DIRS.contains(&entry.file_name().to_string_lossy().as_ref()))
+    });
+    for entry in walker {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy();
+        if !entry.file_type().is_file() || !patterns.iter().any(|p| p.matches(&name)) {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(root)?;
+        let mut components = relative.components();
+        if components.next().is_none() || components.as_path().as_os_str().is_empty() {
+            continue;
+        }
+        if !is_ignored(entry.path()) {
+            debug!("`{}` is not ignored by its project, skipped", entry.path().display());
+            continue;
+        }
+        let glob = Path::new("*").join(components.as_path());
+        found
+            .entry(glob.to_string_lossy().replace('\\', "/"))
+            .or_default()
+            .push(relative.to_path_buf());
+    }
+    Ok(found)
+}
+
+/// Whether `path` is ignored by the git repository it is in. Not if it is in
+/// none.
+fn is_ignored(path: &Path) -> bool {
+    let Some(dir) = path.parent() else {
+        return false;
+    };
+    git_command()
+        .current_dir(dir)
+        .args(["check-ignore", "-q"])
+        .arg(path)
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// The default item of `glob` in `workspace`, e.g. `projects/env` for `*/.env`.
+fn default_item(workspace: &str, glob: &str) -> PathBuf {
+    let name = glob
+        .trim_start_matches("*/")
+        .replace(['*', '/'], "_")
+        .trim_start_matches('.')
+        .to_string();
+    Path::new(workspace).join(name)
+}
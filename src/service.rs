@@ -0,0 +1,325 @@
+//! Generating the units running gsb in the background, with the repository
+//! baked in: systemd user units on Linux, a launchd agent on macOS, a task of
+//! the Task Scheduler on Windows.
+
+use std::path::{Path, PathBuf};
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+use std::process::Command;
+
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+use anyhow::ensure;
+use anyhow::{Context, Result};
+use log::info;
+
+use crate::cli::json_output;
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+use crate::git_command::REPO_PATH;
+
+/// The options of `gsb service install`.
+pub struct ServiceOptions<'a> {
+    pub name: &'a str,
+    /// Run `gsb sync` by a timer with this interval, e.g. `15m`.
+    pub interval: &'a str,
+    /// Run `gsb daemon` in a long running service instead of a timer.
+    pub daemon: bool,
+    /// Only print the units.
+    pub print: bool,
+}
+
+/// Generate and install the units, then enable them.
+pub fn install(options: ServiceOptions) -> Result<()> {
+    humantime::parse_duration(options.interval).context("invalid interval")?;
+    let units = units(&options)?;
+    if options.print {
+        if json_output() {
+            let units = units
+                .iter()
+                .map(|(file, content)| serde_json::json!({ "file": file, "content": content }))
+                .collect::<Vec<_>>();
+            println!("{}", serde_json::Value::Array(units));
+            return Ok(());
+        }
+        for (file, content) in units.iter() {
+            println!("# {}\n{}", file, content);
+        }
+        return Ok(());
+    }
+    let dir = unit_dir()?;
+    std::fs::create_dir_all(&dir)?;
+    for (file, content) in units.iter() {
+        write_unit(&dir.join(file), content)?;
+        info!("installed `{:?}`", dir.join(file));
+    }
+    enable(&options, &dir)?;
+    if json_output() {
+        let files = units.iter().map(|(file, _)| dir.join(file)).collect::<Vec<_>>();
+        println!("{}", serde_json::json!({ "name": options.name, "installed": files }));
+    }
+    Ok(())
+}
+
+#[cfg(not(windows))]
+fn write_unit(path: &Path, content: &str) -> Result<()> {
+    std::fs::write(path, content)?;
+    Ok(())
+}
+
+/// The Task Scheduler only reads UTF-16 reliably.
+#[cfg(windows)]
+fn write_unit(path: &Path, content: &str) -> Result<()> {
+    let bytes = [0xfeff]
+        .into_iter()
+        .chain(content.encode_utf16())
+        .flat_map(u16::to_le_bytes)
+        .collect::<Vec<_>>();
+    std::fs::write(path, bytes)?;
+    Ok(())
+}
+
+/// The systemd units: a service, and a timer starting it unless `daemon`.
+#[cfg(target_os = "linux")]
+fn units(options: &ServiceOptions) -> Result<Vec<(String, String)>> {
+    let exe = std::env::current_exe()?;
+    let repo = REPO_PATH.canonicalize()?;
+    let (kind, command, restart) = if options.daemon {
+        ("simple", "daemon", "Restart=on-failure\nRestartSec=30\n")
+    } else {
+        ("oneshot", "--wait sync", "")
+    };
+    let service = format!(
+        "[Unit]\n\
+         Description=gsb {command} of {repo}\n\
+         Wants=network-online.target\n\
+         After=network-online.target\n\
+         \n\
+         [Service]\n\
+         Type={kind}\n\
+         ExecStart=\"{exe}\" --repo \"{repo}\" {command}\n\
+         {restart}\
+         \n\
+         [Install]\n\
+         WantedBy=default.target\n",
+        exe = exe.display(),
+        repo = repo.display(),
+    );
+    let mut units = vec![(format!("{}.service", options.name), service)];
+    if !options.daemon {
+        let timer = format!(
+            "[Unit]\n\
+             Description=gsb sync of {repo} every {interval}\n\
+             \n\
+             [Timer]\n\
+             OnBootSec=1min\n\
+             OnUnitActiveSec={interval}\n\
+             Persistent=true\n\
+             \n\
+             [Install]\n\
+             WantedBy=timers.target\n",
+            repo = repo.display(),
+            interval = options.interval,
+        );
+        units.push((format!("{}.timer", options.name), timer));
+    }
+    Ok(units)
+}
+
+/// The launchd agent, started every interval, or kept alive if `daemon`.
+#[cfg(target_os = "macos")]
+fn units(options: &ServiceOptions) -> Result<Vec<(String, String)>> {
+    let exe = std::env::current_exe()?;
+    let repo = REPO_PATH.canonicalize()?;
+    let (command, schedule) = if options.daemon {
+        (
+            "<string>daemon</string>",
+            "<key>KeepAlive</key>\n  <true/>".to_string(),
+        )
+    } else {
+        let interval = humantime::parse_duration(options.interval)?.as_secs();
+        (
+            "<string>--wait</string>\n    <string>sync</string>",
+            format!("<key>StartInterval</key>\n  <integer>{}</integer>", interval),
+        )
+    };
+    let plist = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+  <key>Label</key>
+  <string>{name}</string>
+  <key>ProgramArguments</key>
+  <array>
+    <string>{exe}</string>
+    <string>--repo</string>
+    <string>{repo}</string>
+    {command}
+  </array>
+  <key>RunAtLoad</key>
+  <true/>
+  {schedule}
+</dict>
+</plist>
+"#,
+        name = xml_escape(options.name),
+        exe = xml_escape(&exe.to_string_lossy()),
+        repo = xml_escape(&repo.to_string_lossy()),
+    );
+    Ok(vec![(format!("{}.plist", options.name), plist)])
+}
+
+/// The task of the Task Scheduler, run as the current user at logon and
+/// repeated every interval, or kept running with restarts if `daemon`.
+#[cfg(windows)]
+fn units(options: &ServiceOptions) -> Result<Vec<(String, String)>> {
+    let exe = std::env::current_exe()?;
+    let repo = REPO_PATH.canonicalize()?;
+    let (command, trigger, settings) = if options.daemon {
+        (
+            "daemon",
+            "<LogonTrigger />".to_string(),
+            "<ExecutionTimeLimit>PT0S</ExecutionTimeLimit>\n    \
+             <RestartOnFailure><Interval>PT1M</Interval><Count>10</Count></RestartOnFailure>",
+        )
+    } else {
+        let interval = humantime::parse_duration(options.interval)?.as_secs();
+        (
+            "--wait sync",
+            format!(
+                "<LogonTrigger><Repetition><Interval>PT{}S</Interval></Repetition></LogonTrigger>",
+                interval
+            ),
+            "<ExecutionTimeLimit>PT1H</ExecutionTimeLimit>",
+        )
+    };
+    let task = format!(
+        r#"<?xml version="1.0" encoding="UTF-16"?>
+<Task version="1.2" xmlns="http://schemas.microsoft.com/windows/2004/02/mit/task">
+  <RegistrationInfo>
+    <Description>gsb {command} of {repo}</Description>
+  </RegistrationInfo>
+  <Triggers>
+    {trigger}
+  </Triggers>
+  <Principals>
+    <Principal id="Author">
+      <LogonType>InteractiveToken</LogonType>
+      <RunLevel>LeastPrivilege</RunLevel>
+    </Principal>
+  </Principals>
+  <Settings>
+    <MultipleInstancesPolicy>IgnoreNew</MultipleInstancesPolicy>
+    <DisallowStartIfOnBatteries>false</DisallowStartIfOnBatteries>
+    <StopIfGoingOnBatteries>false</StopIfGoingOnBatteries>
+    <StartWhenAvailable>true</StartWhenAvailable>
+    <Hidden>true</Hidden>
+    {settings}
+  </Settings>
+  <Actions Context="Author">
+    <Exec>
+      <Command>{exe}</Command>
+      <Arguments>--repo "{repo}" {command}</Arguments>
+    </Exec>
+  </Actions>
+</Task>
+"#,
+        exe = xml_escape(&exe.to_string_lossy()),
+        repo = xml_escape(&repo.to_string_lossy()),
+    );
+    Ok(vec![(format!("{}.xml", options.name), task)])
+}
+
+#[cfg(any(target_os = "macos", windows))]
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn units(_options: &ServiceOptions) -> Result<Vec<(String, String)>> {
+    anyhow::bail!("installing a service is not supported on this platform")
+}
+
+/// `$XDG_CONFIG_HOME/systemd/user`.
+#[cfg(target_os = "linux")]
+fn unit_dir() -> Result<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .context("neither XDG_CONFIG_HOME nor HOME is set")?;
+    Ok(config.join("systemd").join("user"))
+}
+
+/// `~/Library/LaunchAgents`.
+#[cfg(target_os = "macos")]
+fn unit_dir() -> Result<PathBuf> {
+    let home = std::env::var_os("HOME").context("HOME is not set")?;
+    Ok(PathBuf::from(home).join("Library").join("LaunchAgents"))
+}
+
+/// `%LOCALAPPDATA%\gsb`, where the task definitions are kept.
+#[cfg(windows)]
+fn unit_dir() -> Result<PathBuf> {
+    let local = std::env::var_os("LOCALAPPDATA").context("LOCALAPPDATA is not set")?;
+    Ok(PathBuf::from(local).join("gsb"))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn unit_dir() -> Result<PathBuf> {
+    anyhow::bail!("installing a service is not supported on this platform")
+}
+
+#[cfg(target_os = "linux")]
+fn enable(options: &ServiceOptions, _dir: &Path) -> Result<()> {
+    run("systemctl", &["--user", "daemon-reload"])?;
+    let unit = if options.daemon {
+        format!("{}.service", options.name)
+    } else {
+        format!("{}.timer", options.name)
+    };
+    run("systemctl", &["--user", "enable", "--now", &unit])?;
+    info!("enabled `{}`", unit);
+    Ok(())
+}
+
+/// Load the agent into the GUI domain of the current user, replacing an older
+/// version of it.
+#[cfg(target_os = "macos")]
+fn enable(options: &ServiceOptions, dir: &Path) -> Result<()> {
+    let uid = String::from_utf8(Command::new("id").arg("-u").output()?.stdout)?;
+    let domain = format!("gui/{}", uid.trim());
+    let _ = Command::new("launchctl")
+        .args(["bootout", &format!("{}/{}", domain, options.name)])
+        .status();
+    let plist = dir.join(format!("{}.plist", options.name));
+    run("launchctl", &["bootstrap", &domain, &plist.to_string_lossy()])?;
+    info!("loaded `{}`", options.name);
+    Ok(())
+}
+
+/// Register the task, replacing an older version of it, and start it.
+#[cfg(windows)]
+fn enable(options: &ServiceOptions, dir: &Path) -> Result<()> {
+    let task = dir.join(format!("{}.xml", options.name));
+    let task = task.to_string_lossy();
+    run("schtasks", &["/Create", "/TN", options.name, "/XML", &task, "/F"])?;
+    run("schtasks", &["/Run", "/TN", options.name])?;
+    info!("registered the task `{}`", options.name);
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn enable(_options: &ServiceOptions, _dir: &Path) -> Result<()> {
+    anyhow::bail!("installing a service is not supported on this platform")
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+fn run(program: &str, args: &[&str]) -> Result<()> {
+    let mut command = Command::new(program);
+    if json_output() {
+        command.stdout(std::io::stderr());
+    }
+    let status = command.args(args).status()?;
+    ensure!(status.success(), "`{} {}` failed: {}", program, args.join(" "), status);
+    Ok(())
+}
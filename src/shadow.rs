@@ -0,0 +1,64 @@
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use log::info;
+
+use crate::{git_command::git, state::state_dir};
+
+const SHADOW_DIR: &str = "shadow";
+
+/// Where a shadow sync records what it would have restored: a `plan.txt`
+/// listing every restore and a `diff.patch` with their contents, in
+/// `.gsb/shadow/<unix timestamp>/`.
+pub struct ShadowReport {
+    dir: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl ShadowReport {
+    pub fn new() -> Result<Self> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let dir = state_dir()?.join(SHADOW_DIR).join(timestamp.to_string());
+        std::fs::create_dir_all(&dir)?;
+        info!("shadow mode, the restores are recorded in `{:?}`", dir);
+        Ok(Self {
+            dir,
+            lock: Mutex::new(()),
+        })
+    }
+
+    /// Record that `from` in the repository would have been restored to `to`.
+    pub fn record(&self, from: &Path, to: &Path) -> Result<()> {
+        let (action, diff) = if to.exists() {
+            let diff = git([
+                "diff",
+                "--no-index",
+                "--",
+                &to.to_string_lossy(),
+                &from.to_string_lossy(),
+            ])?;
+            ("overwrite", diff)
+        } else {
+            ("create", String::new())
+        };
+
+        let _guard = self.lock.lock().unwrap();
+        let mut plan = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join("plan.txt"))?;
+        writeln!(plan, "{} `{}` from `{}`", action, to.display(), from.display())?;
+        let mut patch = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join("diff.patch"))?;
+        patch.write_all(diff.as_bytes())?;
+        Ok(())
+    }
+}
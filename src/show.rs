@@ -0,0 +1,166 @@
+//! `gsb show`: what a commit of gsb recorded, to pick a revision to go back
+//! to, or the content of a file at a revision.
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use anyhow::{bail, ensure, Result};
+use serde::Serialize;
+
+use crate::{
+    cli::json_output,
+    commit::{ACTION_TRAILER, DEVICE_TRAILER, VERSION_TRAILER},
+    config::CONFIG,
+    git_command::{git, git_checked, git_command, git_output},
+    manifest::MANIFEST_NAME,
+};
+
+#[derive(Serialize, Debug)]
+struct Snapshot {
+    commit: String,
+    time: String,
+    subject: String,
+    action: Option<String>,
+    device: Option<String>,
+    version: Option<String>,
+    /// The items with files changed by the commit.
+    items: Vec<PathBuf>,
+    /// The BLAKE3 hash of the manifest at the commit.
+    manifest: Option<String>,
+}
+
+/// Print the metadata gsb recorded in the commit `rev`. Commits made before
+/// gsb wrote trailers only have the subject.
+pub fn show(rev: &str) -> Result<()> {
+    // Without a separator, every value ends with a newline of its own.
+    let trailer = |key| format!("%(trailers:key={},valueonly,separator=%x2C)", key);
+    let format = format!(
+        "--format=%H%n%ct%n%s%n{}%n{}%n{}",
+        trailer(ACTION_TRAILER),
+        trailer(DEVICE_TRAILER),
+        trailer(VERSION_TRAILER)
+    );
+    let log = git_checked(["log", "-1", &format, rev, "--"])?;
+    let mut lines = log.lines().map(str::trim);
+    let mut next = || lines.next().filter(|line| !line.is_empty()).map(str::to_string);
+    let commit = next().unwrap_or_default();
+    let time = next().and_then(|secs| secs.parse().ok()).unwrap_or(0);
+    let time = humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(time));
+    let subject = next().unwrap_or_default();
+    let (action, device, version) = (next(), next(), next());
+
+    let changed = git(["diff-tree", "--no-commit-id", "--name-only", "-r", "--root", &commit])?;
+    let config = CONFIG.read().unwrap();
+    let mut items = config
+        .sync_group
+        .0
+        .keys()
+        .chain(config.backup_group.0.keys())
+        .filter(|item| changed.lines().any(|path| PathBuf::from(path.trim()).starts_with(item)))
+        .cloned()
+        .collect::<Vec<_>>();
+    items.sort();
+    items.dedup();
+    let manifest = git_checked(["show", &format!("{}:{}", commit, MANIFEST_NAME)])
+        .ok()
+        .map(|manifest| blake3::hash(manifest.as_bytes()).to_hex().to_string());
+
+    let snapshot = Snapshot {
+        commit,
+        time: time.to_string(),
+        subject,
+        action,
+        device: device.map(|device| config.device_label(&device)),
+        version,
+        items,
+        manifest,
+    };
+    if json_output() {
+        println!("{}", serde_json::to_string(&snapshot)?);
+        return Ok(());
+    }
+    let unknown = || "unknown".to_string();
+    println!("commit:   {}", snapshot.commit);
+    println!("time:     {}", snapshot.time);
+    println!("subject:  {}", snapshot.subject);
+    println!("action:   {}", snapshot.action.unwrap_or_else(unknown));
+    println!("device:   {}", snapshot.device.unwrap_or_else(unknown));
+    println!("version:  {}", snapshot.version.unwrap_or_else(unknown));
+    println!("manifest: {}", snapshot.manifest.unwrap_or_else(|| "none".to_string()));
+    println!("items:");
+    for item in snapshot.items.iter() {
+        println!("  {}", item.display());
+    }
+    Ok(())
+}
+
+/// Split `<path>@<rev>` if `path` is in an item, so revisions like
+/// `main@{1}` are still shown as commits.
+pub fn split_file_rev(arg: &str) -> Option<(&Path, &str)> {
+    let config = CONFIG.read().unwrap();
+    let items = config.sync_group.0.keys().chain(config.backup_group.0.keys());
+    split_rev(arg, items)
+}
+
+fn split_rev<'a, 'b>(
+    arg: &'a str,
+    mut items: impl Iterator<Item = &'b PathBuf>,
+) -> Option<(&'a Path, &'a str)> {
+    let (path, rev) = arg.split_once('@')?;
+    let path = Path::new(path);
+    items.any(|item| path.starts_with(item)).then_some((path, rev))
+}
+
+/// Print the content of `path` (relative to the repository) at `rev`, or
+/// write it to `output`. `rev` is a revision, or a date like `2026-03-01`
+/// for the last commit before it.
+pub fn show_file(path: &Path, rev: &str, output: Option<&Path>) -> Result<()> {
+    let spec = format!("{}^{{commit}}", rev);
+    let commit = match git_checked(["rev-parse", "--verify", "--quiet", &spec]) {
+        Ok(commit) => commit,
+        Err(_) => git_checked(["rev-list", "-1", &format!("--before={}", rev), "HEAD"])?,
+    };
+    let commit = commit.trim();
+    if commit.is_empty() {
+        bail!("`{}` is neither a revision nor a date with commits before it", rev);
+    }
+    let object = format!("{}:{}", commit, path.to_string_lossy().replace('\\', "/"));
+    let kind = git(["cat-file", "-t", &object])?;
+    match kind.trim() {
+        "blob" => {}
+        "tree" => bail!("`{}` is a directory at `{}`, show one of its files", path.display(), rev),
+        _ => bail!("`{}` does not exist at `{}`", path.display(), rev),
+    }
+    let content = git_output(git_command().args(["cat-file", "blob", &object]))?;
+    ensure!(
+        content.status.success(),
+        "failed to read `{}`: {}",
+        object,
+        String::from_utf8_lossy(&content.stderr).trim()
+    );
+    match output {
+        Some(output) => std::fs::write(output, &content.stdout)?,
+        None => std::io::stdout().write_all(&content.stdout)?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_rev() {
+        let items = [PathBuf::from("notes"), PathBuf::from("dots/vimrc")];
+        let split = |arg| split_rev(arg, items.iter());
+        assert_eq!(split("notes/a.md@HEAD~2"), Some((Path::new("notes/a.md"), "HEAD~2")));
+        assert_eq!(split("dots/vimrc@2026-03-01"), Some((Path::new("dots/vimrc"), "2026-03-01")));
+        // Not in an item, a revision like `main@{1}`.
+        assert_eq!(split("main@{1}"), None);
+        assert_eq!(split("notesx@HEAD"), None);
+        assert_eq!(split("notes/a.md"), None);
+    }
+}
@@ -0,0 +1,55 @@
+use anyhow::Result;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, Signal, SignalKind};
+#[cfg(windows)]
+use tokio::signal::windows::{ctrl_c, ctrl_close, ctrl_shutdown, CtrlC, CtrlClose, CtrlShutdown};
+
+/// The shutdown requests of the long running commands: SIGTERM (e.g. from
+/// `systemctl stop`) or Ctrl+C. Once created, they no longer kill the process,
+/// so the current cycle can finish and the locks are released.
+pub struct Shutdown {
+    #[cfg(unix)]
+    signals: [Signal; 2],
+    #[cfg(windows)]
+    signals: (CtrlC, CtrlClose, CtrlShutdown),
+}
+
+impl Shutdown {
+    #[cfg(unix)]
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            signals: [
+                signal(SignalKind::terminate())?,
+                signal(SignalKind::interrupt())?,
+            ],
+        })
+    }
+
+    #[cfg(windows)]
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            signals: (ctrl_c()?, ctrl_close()?, ctrl_shutdown()?),
+        })
+    }
+
+    /// Wait until a shutdown is requested. Returns at once if it was requested
+    /// since the last call.
+    #[cfg(unix)]
+    pub async fn requested(&mut self) {
+        let [terminate, interrupt] = &mut self.signals;
+        tokio::select! {
+            _ = terminate.recv() => {}
+            _ = interrupt.recv() => {}
+        }
+    }
+
+    #[cfg(windows)]
+    pub async fn requested(&mut self) {
+        let (c, close, shutdown) = &mut self.signals;
+        tokio::select! {
+            _ = c.recv() => {}
+            _ = close.recv() => {}
+            _ = shutdown.recv() => {}
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! With `sparse_checkout`, only the items used on this device are in the
+//! working tree, in a cone-mode sparse checkout, so constrained machines do
+//! not materialize the items of all the others.
+
+use std::{collections::BTreeSet, path::Path};
+
+use anyhow::Result;
+use log::info;
+
+use crate::{
+    archive::ARCHIVE_DIR,
+    chunk::chunk_dir,
+    config::{Getable, CONFIG},
+    encrypt::encrypted_dir,
+    git_command::{git, git_checked},
+};
+
+fn slashed(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+/// Set the sparse checkout to the items of this device, or disable it if
+/// `sparse_checkout` was turned off. The files at the top of the repository
+/// are always checked out.
+pub fn apply() -> Result<()> {
+    let config = CONFIG.read().unwrap().clone();
+    let enabled = git(["config", "--bool", "core.sparseCheckout"])?.trim() == "true";
+    if !config.sparse_checkout {
+        if enabled {
+            git_checked(["sparse-checkout", "disable"])?;
+            info!("checked out all items again");
+        }
+        return Ok(());
+    }
+
+    let trees = git(["ls-tree", "-r", "-d", "--name-only", "HEAD"])?;
+    let trees = trees.lines().map(str::trim).collect::<BTreeSet<_>>();
+    let mut dirs = BTreeSet::from([ARCHIVE_DIR.to_string()]);
+    let mut add = |item: &Path, is_dir: bool, packed: bool| {
+        let chunks = slashed(&chunk_dir(item));
+        if is_dir || trees.contains(slashed(item).as_str()) {
+            dirs.insert(slashed(item));
+        } else if trees.contains(chunks.as_str()) {
+            dirs.insert(chunks);
+        }
+        let encrypted = slashed(&encrypted_dir(item));
+        if trees.contains(encrypted.as_str()) {
+            dirs.insert(encrypted);
+        }
+        // Files, and packs of directories, are next to where the item is.
+        if packed || !is_dir {
+            if let Some(parent) = item.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                dirs.insert(slashed(parent));
+            }
+        }
+    };
+    for (item, info) in config.sync_group.0.iter() {
+        let Some(on_device) = info.get_on_device() else {
+            continue;
+        };
+        if config.selects(&info.profiles, &info.tags) {
+            add(item, on_device.is_dir(), info.archive.is_some());
+        }
+    }
+    for (item, info) in config.backup_group.0.iter() {
+        let is_dir = info.workspace.is_some() || info.path_on_device.is_dir();
+        add(item, is_dir, false);
+    }
+
+    let current = git(["sparse-checkout", "list"])?;
+    let current = current.lines().map(|dir| dir.trim().to_string()).collect::<BTreeSet<_>>();
+    if enabled && current == dirs {
+        return Ok(());
+    }
+    let mut args = vec!["sparse-checkout", "set", "--cone"];
+    args.extend(dirs.iter().map(String::as_str));
+    git_checked(args)?;
+    info!("checked out only the {} directories of the items of this device", dirs.len());
+    Ok(())
+}
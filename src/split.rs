@@ -0,0 +1,119 @@
+//! Moving items of the sync group into a repository of their own, for when a
+//! repository grows to contain unrelated concerns.
+
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{ensure, Context, Result};
+use log::info;
+
+use crate::{
+    cli::json_output,
+    commit::commit,
+    config::{save_config, Config, PlaceholderPolicy, SyncGroup, CONFIG, CONFIG_NAME},
+    git_command::{git, git_checked, git_command, git_remote, REMOTE_NAME, REPO_PATH, SYNC_BRANCH},
+    state::state_dir,
+    utils::copy_item,
+};
+
+/// Move `items` of the sync group into a new repository pushed to `to`, with
+/// their history if `history`, and remove them from this one.
+pub async fn split(items: &[PathBuf], to: &str, history: bool) -> Result<()> {
+    let config = CONFIG.read().unwrap().clone();
+    for item in items {
+        ensure!(
+            config.sync_group.0.contains_key(item),
+            "`{:?}` is not an item of the sync group",
+            item
+        );
+    }
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let dir = state_dir()?.join(format!("split-{}", secs));
+    let dir_str = dir.to_str().context("the repository path is not valid UTF-8")?;
+    let item_strs = items
+        .iter()
+        .map(|item| item.to_str().context("the item path is not valid UTF-8"))
+        .collect::<Result<Vec<_>>>()?;
+
+    if history {
+        git(["clone", "--quiet", "--branch", SYNC_BRANCH, ".", dir_str])?;
+        filter_history(&dir, &item_strs)?;
+        git(["-C", dir_str, "remote", "remove", REMOTE_NAME])?;
+    } else {
+        git(["init", "--quiet", "--initial-branch", SYNC_BRANCH, dir_str])?;
+        for item in items {
+            copy_item(&REPO_PATH.join(item), &dir.join(item), PlaceholderPolicy::Hydrate).await?;
+        }
+    }
+
+    let split_config = Config {
+        device_name: config.device_name.clone(),
+        remote: Some(to.to_string()),
+        sync_group: SyncGroup(
+            config
+                .sync_group
+                .0
+                .iter()
+                .filter(|(item, _)| items.contains(item))
+                .map(|(item, info)| (item.clone(), info.clone()))
+                .collect(),
+        ),
+        services: config
+            .services
+            .iter()
+            .filter(|(item, _)| items.contains(item))
+            .map(|(item, service)| (item.clone(), service.clone()))
+            .collect(),
+        ..Default::default()
+    };
+    std::fs::write(dir.join(CONFIG_NAME), toml::to_string_pretty(&split_config)?)?;
+    let subject = format!("split from {}", REPO_PATH.display());
+    git(["-C", dir_str, "add", "--all"])?;
+    git_checked(["-C", dir_str, "commit", "--quiet", "-m", &subject])?;
+    git(["-C", dir_str, "remote", "add", REMOTE_NAME, to])?;
+    git_remote(["-C", dir_str, "push", "--set-upstream", REMOTE_NAME, SYNC_BRANCH]).await?;
+    info!("pushed {} items to `{}`", items.len(), to);
+
+    git(
+        ["rm", "-r", "--quiet", "--"]
+            .into_iter()
+            .chain(item_strs.iter().copied())
+            .collect::<Vec<_>>(),
+    )?;
+    {
+        let mut config = CONFIG.write().unwrap();
+        config.sync_group.0.retain(|item, _| !items.contains(item));
+        config.services.retain(|item, _| !items.contains(item));
+    }
+    save_config()?;
+    git(["add", CONFIG_NAME])?;
+    commit("split", items.iter().map(PathBuf::as_path))?;
+    std::fs::remove_dir_all(&dir)?;
+    if json_output() {
+        println!("{}", serde_json::json!({ "split": items, "to": to }));
+    }
+    Ok(())
+}
+
+/// Keep only `items` in the whole history of the repository in `dir`.
+fn filter_history(dir: &Path, items: &[&str]) -> Result<()> {
+    let keep = items
+        .iter()
+        .map(|item| format!("'{}'", item.replace('\'', r"'\''")))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let status = git_command()
+        .args(["filter-branch", "--force", "--prune-empty", "--index-filter"])
+        .arg(format!(
+            "git rm --cached -qr --ignore-unmatch -- . && git reset -q $GIT_COMMIT -- {}",
+            keep
+        ))
+        .args(["--", "HEAD"])
+        .env("FILTER_BRANCH_SQUELCH_WARNING", "1")
+        .current_dir(dir)
+        .status()?;
+    ensure!(status.success(), "failed to filter the history: {}", status);
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use std::{
+    collections::BTreeMap,
+    fs::Metadata,
+    path::PathBuf,
+    sync::{LazyLock, Mutex},
+    time::UNIX_EPOCH,
+};
+
+use anyhow::Result;
+use config_file::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{capabilities::Capabilities, git_command::REPO_PATH};
+
+/// The directory in the repository holding the local state of gsb. It ignores
+/// itself, so nothing inside is ever committed.
+pub const STATE_DIR: &str = ".gsb";
+const STATE_NAME: &str = "state.toml";
+
+pub static STATE: LazyLock<Mutex<State>> = LazyLock::new(|| Mutex::new(State::load_or_default()));
+
+pub fn state_dir() -> Result<PathBuf> {
+    let dir = REPO_PATH.join(STATE_DIR);
+    std::fs::create_dir_all(&dir)?;
+    let ignore = dir.join(".gitignore");
+    if !ignore.exists() {
+        std::fs::write(ignore, "*\n")?;
+    }
+    Ok(dir)
+}
+
+/// The source file a copied file came from, as it was when copied.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FileState {
+    pub size: u64,
+    /// Nanoseconds since the unix epoch.
+    pub mtime: u64,
+    /// The hex encoded BLAKE3 hash, only recorded by
+    /// [`crate::config::CompareMode::Hash`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+impl FileState {
+    pub fn from_metadata(meta: &Metadata) -> Result<Self> {
+        Ok(Self {
+            size: meta.len(),
+            mtime: meta.modified()?.duration_since(UNIX_EPOCH)?.as_nanos() as u64,
+            hash: None,
+        })
+    }
+
+    /// Whether the size and mtime are identical, ignoring the hash.
+    pub fn same_stat(&self, other: &Self) -> bool {
+        self.size == other.size && self.mtime == other.mtime
+    }
+}
+
+/// The persistent state of the files gsb has copied, letting repeated copies
+/// of unchanged files be skipped without reading them.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct State {
+    /// Key: the absolute path of the copied file.
+    /// Value: the state of its source when it was copied.
+    #[serde(default)]
+    pub files: BTreeMap<PathBuf, FileState>,
+    /// Key: the mount point.
+    /// Value: the capabilities of its filesystem.
+    #[serde(default)]
+    pub mounts: BTreeMap<PathBuf, Capabilities>,
+    /// The last file checked by [`crate::verify`], where the next check goes
+    /// on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verify_cursor: Option<PathBuf>,
+    /// The commit before the pulls whose changes are not restored yet, see
+    /// [`crate::sync::restore`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restore_base: Option<String>,
+}
+
+impl State {
+    fn load_or_default() -> Self {
+        Self::from_config_file(REPO_PATH.join(STATE_DIR).join(STATE_NAME)).unwrap_or_default()
+    }
+}
+
+pub fn save_state() -> Result<()> {
+    let path = state_dir()?.join(STATE_NAME);
+    STATE.lock().unwrap().to_config_file(path)?;
+    Ok(())
+}
@@ -0,0 +1,141 @@
+//! `gsb stats`: how much each item takes in the working tree and in the
+//! history, to find out what makes the repository grow.
+
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use anyhow::{ensure, Result};
+use indicatif::HumanBytes;
+use serde::Serialize;
+
+use crate::{
+    archive::archive_path,
+    cli::json_output,
+    config::CONFIG,
+    git_command::{git_checked, git_command, REPO_PATH},
+};
+
+#[derive(Serialize, Debug, Default)]
+struct ItemStats {
+    item: PathBuf,
+    files: u64,
+    /// Bytes of the item in the working tree.
+    size: u64,
+    /// Bytes of all versions of the item in the history, as stored by git.
+    history_size: u64,
+}
+
+/// Whether `path` in the repository holds files of `item`, in any of the ways
+/// an item can be stored: as is, packed, compressed, chunked, encrypted or
+/// archived.
+fn belongs(path: &str, item: &Path) -> bool {
+    let item = item.to_string_lossy().replace('\\', "/");
+    path == item
+        || path.starts_with(&format!("{}/", item))
+        || path.starts_with(&format!("{}.", item))
+        || Path::new(path) == archive_path(Path::new(&item))
+}
+
+/// The bytes stored by git of every blob of every branch, by path. A blob
+/// shared by several paths is counted for the first one.
+fn history_sizes() -> Result<BTreeMap<String, u64>> {
+    let objects = git_checked(["rev-list", "--objects", "--all"])?;
+    let mut child = git_command()
+        .args(["cat-file", "--batch-check=%(objecttype) %(objectsize:disk) %(rest)"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    // Written from another thread, so a full stdout does not block it.
+    let writer = std::thread::spawn(move || stdin.write_all(objects.as_bytes()));
+    let output = child.wait_with_output()?;
+    writer.join().unwrap()?;
+    ensure!(output.status.success(), "failed to read the sizes of the objects");
+
+    let mut sizes = BTreeMap::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let mut fields = line.splitn(3, ' ');
+        let (Some("blob"), Some(size), Some(path)) = (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        *sizes.entry(path.to_string()).or_default() += size.parse::<u64>().unwrap_or(0);
+    }
+    Ok(sizes)
+}
+
+/// The size of every file of the working tree, by path.
+fn tree_sizes() -> Vec<(String, u64)> {
+    let walker = walkdir::WalkDir::new(REPO_PATH.as_path())
+        .into_iter()
+        .filter_entry(|entry| entry.file_name() != ".git");
+    walker
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| {
+            let relative = entry.path().strip_prefix(REPO_PATH.as_path()).ok()?;
+            let relative = relative.to_string_lossy().replace('\\', "/");
+            Some((relative, entry.metadata().map_or(0, |meta| meta.len())))
+        })
+        .collect()
+}
+
+/// Print the files, size and history size of every item, largest history
+/// first.
+pub fn stats() -> Result<()> {
+    let items = {
+        let config = CONFIG.read().unwrap();
+        let mut items = config
+            .sync_group
+            .0
+            .keys()
+            .chain(config.backup_group.0.keys())
+            .cloned()
+            .collect::<Vec<_>>();
+        items.sort();
+        items.dedup();
+        items
+    };
+    let history = history_sizes()?;
+    let tree = tree_sizes();
+    let mut stats = Vec::new();
+    for item in items {
+        let mut item_stats = ItemStats {
+            item: item.clone(),
+            ..Default::default()
+        };
+        for (path, size) in tree.iter().filter(|(path, _)| belongs(path, &item)) {
+            item_stats.files += 1;
+            item_stats.size += size;
+        }
+        item_stats.history_size = history
+            .iter()
+            .filter(|(path, _)| belongs(path, &item))
+            .map(|(_, size)| size)
+            .sum();
+        stats.push(item_stats);
+    }
+    stats.sort_by(|a, b| b.history_size.cmp(&a.history_size).then(b.size.cmp(&a.size)));
+
+    if json_output() {
+        println!("{}", serde_json::to_string(&stats)?);
+        return Ok(());
+    }
+    println!("{:>10} {:>10} {:>8}  item", "history", "size", "files");
+    for item in stats.iter() {
+        println!(
+            "{:>10} {:>10} {:>8}  {}",
+            HumanBytes(item.history_size).to_string(),
+            HumanBytes(item.size).to_string(),
+            item.files,
+            item.item.display()
+        );
+    }
+    let total = history.values().sum::<u64>();
+    println!("{:>10} in the history of the whole repository", HumanBytes(total).to_string());
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+
+use crate::{cli::json_output, git_command::REPO_PATH, report::LAST_RUN_NAME, state::STATE_DIR};
+
+/// Print how the last run went, e.g. `last collect: 2h ago, OK`, or with
+/// `short` a line like `✓ synced 12m ago` for status bars. Only the
+/// `last-run.json` is read, so this returns at once.
+pub fn status(short: bool) -> Result<()> {
+    let path = REPO_PATH.join(STATE_DIR).join(LAST_RUN_NAME);
+    if !path.exists() {
+        println!("{}", if short { "? never synced" } else { "no run yet" });
+        return Ok(());
+    }
+    let content = std::fs::read_to_string(&path)?;
+    if json_output() && !short {
+        println!("{}", content.trim());
+        return Ok(());
+    }
+    let run: serde_json::Value =
+        serde_json::from_str(&content).with_context(|| format!("invalid `{:?}`", path))?;
+    let ago = run["timestamp"]
+        .as_str()
+        .and_then(|timestamp| humantime::parse_rfc3339(timestamp).ok())
+        .and_then(|time| SystemTime::now().duration_since(time).ok());
+    let errors = run["errors"].as_u64().unwrap_or_default();
+    if short {
+        let ago = ago.map_or_else(|| "?".to_string(), short_duration);
+        if errors == 0 {
+            println!("✓ synced {} ago", ago);
+        } else {
+            println!("✗ {} items failed {} ago", errors, ago);
+        }
+        return Ok(());
+    }
+    let ago = ago
+        .map(|ago| {
+            // Whole seconds are precise enough and much shorter to read.
+            let ago = Duration::from_secs(ago.as_secs());
+            format!("{} ago", humantime::format_duration(ago))
+        })
+        .unwrap_or_else(|| "at an unknown time".to_string());
+    let result = if errors == 0 {
+        "OK".to_string()
+    } else {
+        format!("{} items failed", errors)
+    };
+    println!(
+        "last {}: {}, {}",
+        run["action"].as_str().unwrap_or("run"),
+        ago,
+        result
+    );
+    Ok(())
+}
+
+/// `duration` in its largest unit, e.g. `12m` or `3d`.
+fn short_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
@@ -1,87 +1,914 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::HashSet,
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use anyhow::{Ok, Result};
-use die_exit::Die;
+use anyhow::{bail, ensure, Context, Ok, Result};
+use log::{info, warn};
 
 use crate::{
-    config::{Config, Getable, CONFIG},
-    git_command::{git, REMOTE_NAME, REPO_PATH, SYNC_BRANCH},
+    archive::{
+        archive_item, extract, is_archived, item_of_archive, pack, packed_item, unarchive_item,
+        unpack, untouched_for, Extracted,
+    },
+    chunk::{chunk_item, chunked_file, is_chunk_list, reassemble},
+    commit::commit,
+    compress::{compress_item, compressed_file, decompress},
+    config::{
+        reload_config, Config, ConflictPolicy, Getable, HookPhase, SyncFile, CONFIG, CONFIG_NAME,
+    },
+    conflicts::{Inbox, Side},
+    device::devicename,
+    encrypt::{decrypt, decrypt_item, encrypt_item, encrypted_file, encrypted_item, is_index},
+    git_command::{
+        device_branch, fetch, git, git_checked, git_command, git_output, git_remote, push_branch,
+        REMOTE_NAME, REPO_PATH, SYNC_BRANCH,
+    },
+    hooks::run_hooks,
+    lfs,
+    limits,
+    manifest::{
+        is_manifest_file, verify_manifest, verify_manifest_at, verify_merged, write_manifest,
+    },
+    metrics,
+    mounts::hardlink_usable,
+    notification::{self, Category},
+    profile::phase,
+    progress::overall_bar,
+    quarantine::{is_access_denied, quarantine, save_quarantine, suspicion},
+    receipt::{self, is_receipt_file},
+    report::Report,
+    restart::restart_services,
+    shadow::ShadowReport,
+    sparse,
+    state::{save_state, state_dir, FileState, STATE},
+    trash::keep_overwritten,
+    undo,
+    utils::{copy_item, prompt, run_items, CopyStats},
 };
 
+/// Options of a sync run, from the command line.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SyncOptions {
+    /// Restart the services of the restored files.
+    pub restart: bool,
+    /// Only record what would have been restored, see [`ShadowReport`].
+    pub shadow: bool,
+    /// Adopt a config changed by the remote. Otherwise such a pull is refused,
+    /// so a bad config pushed from one device does not reach all the others.
+    pub accept_config: bool,
+    /// Overwrite the files changed on this device since the last collect, as
+    /// if the conflict policy was `remote`.
+    pub force: bool,
+    /// The policy replacing `ask` when nobody can answer, see
+    /// [`ConflictPolicy::Ask`].
+    pub non_interactive: Option<ConflictPolicy>,
+}
+
 /// Git pull the changes and dump the changed files.
-pub async fn sync_pull() -> Result<()> {
+#[cfg_attr(feature = "profiling", tracing::instrument(skip_all))]
+pub async fn sync_pull(options: SyncOptions) -> Result<()> {
+    let started = Instant::now();
+    let result = async {
+        pull(options).await?;
+        restore(options).await
+    }
+    .await;
+    metrics::record_pull(started.elapsed());
+    result
+}
+
+/// Fetch the remote and move the repository to it, without touching the
+/// device. The commit it moved from is remembered until [`restore`] dumps the
+/// changed files. Commits collected but not pushed yet are merged with the
+/// pulled ones, see [`merge_keeping_local`].
+pub async fn pull(options: SyncOptions) -> Result<()> {
+    if CONFIG.read().unwrap().device_branches {
+        return pull_device_branches(options).await;
+    }
     git(["branch", SYNC_BRANCH])?;
     let prev_commit = git(["rev-parse", "HEAD"])?;
-    git(["fetch", REMOTE_NAME, SYNC_BRANCH])?;
-    let files_changed = git(["diff", "--name-only", prev_commit.trim(), "FETCH_HEAD"])?;
+    let prev_commit = prev_commit.trim();
+    let fetching = phase("fetch");
+    fetch(REMOTE_NAME, SYNC_BRANCH).await?;
+    drop(fetching);
+    // Only the changes of the remote since both sides split.
+    let files_changed = git(["diff", "--name-only", "HEAD...FETCH_HEAD"])?;
     if files_changed.trim().is_empty() {
         return Ok(());
     }
-    git(["reset", "--hard", "FETCH_HEAD"])?;
-    let result = async_scoped::TokioScope::scope_and_block(|scope| {
-        for path in files_changed.trim().lines() {
-            scope.spawn(dump_changed_file(path.trim()));
+    let config_changed = files_changed.lines().any(|path| path.trim() == CONFIG_NAME);
+    if config_changed && !options.accept_config {
+        bail!(
+            "the remote changed {0}, review it with `git diff HEAD...FETCH_HEAD -- {0}` in {1:?} \
+             and run `gsb sync --accept-config` to adopt it",
+            CONFIG_NAME,
+            REPO_PATH.as_path()
+        );
+    }
+    if git_checked(["merge-base", "--is-ancestor", "HEAD", "FETCH_HEAD"]).is_ok() {
+        git(["reset", "--hard", "FETCH_HEAD"])?;
+        if let Err(e) = verify_manifest(prev_commit) {
+            git(["reset", "--hard", prev_commit])?;
+            return Err(e.context("refused to restore the pulled changes"));
         }
-    });
-    result.1.into_iter().flatten().collect::<Result<()>>()
+    } else {
+        // A reset would throw away the commits collected but not pushed yet.
+        info!("merging the pulled changes with the collected ones not pushed yet");
+        let message = format!("merge the remote {} into the unpushed collects", SYNC_BRANCH);
+        let result = verify_manifest_at("FETCH_HEAD", prev_commit)
+            .and_then(|_| merge_keeping_local(prev_commit, "FETCH_HEAD", &message, options))
+            .and_then(|_| verify_merged(prev_commit, &["FETCH_HEAD"]));
+        if let Err(e) = result {
+            abort_merge(prev_commit)?;
+            return Err(e.context("refused to merge the pulled changes"));
+        }
+    }
+    pulled(prev_commit, config_changed)
 }
 
-/// Deal a changed file after pull. If it's a hardlink, do nothing; otherwise
-/// copy it to the device.
-async fn dump_changed_file(path: &str) -> Result<()> {
-    let path = Path::new(path);
-    let info = CONFIG
-        .read()
+/// Merge `rev` into `HEAD` with `message`, keeping the changes collected in
+/// `prev_commit` that were not pushed yet. The merged side wins the clashing
+/// lines, then the conflict policies decide which of the files changed on
+/// both sides keep the version of `prev_commit`. The caller aborts the merge
+/// on failure.
+fn merge_keeping_local(
+    prev_commit: &str,
+    rev: &str,
+    message: &str,
+    options: SyncOptions,
+) -> Result<()> {
+    let local = git(["diff", "--name-only", &format!("{}...{}", rev, prev_commit)])?;
+    let incoming = git(["diff", "--name-only", &format!("{}...{}", prev_commit, rev)])?;
+    let base = git_checked(["merge-base", prev_commit, rev])?;
+    git_checked(["merge", "--no-edit", "-X", "theirs", "-m", message, rev])?;
+
+    let config = CONFIG.read().unwrap().clone();
+    let local = local.lines().map(str::trim).collect::<HashSet<_>>();
+    let clashing = incoming
+        .lines()
+        .map(str::trim)
+        .filter(|path| local.contains(path))
+        .filter(|path| {
+            locate(&config, Path::new(path))
+                .and_then(|(item, info)| on_device(&info, &item, Path::new(path)))
+                .is_some_and(|on_device| on_device.is_file())
+        })
+        .collect::<Vec<_>>();
+    let keep_local = resolve_conflicts(&clashing, base.trim(), options)?;
+    for path in keep_local.iter().copied() {
+        git_checked(["checkout", prev_commit, "--", path])?;
+    }
+    if !keep_local.is_empty() {
+        git_checked(["commit", "--amend", "--no-edit"])?;
+    }
+    Ok(())
+}
+
+/// After the pull moved `HEAD` from `prev_commit`, remember it for
+/// [`restore`].
+fn pulled(prev_commit: &str, config_changed: bool) -> Result<()> {
+    if config_changed {
+        reload_config();
+    }
+    sparse::apply()?;
+    STATE
+        .lock()
         .unwrap()
-        .sync_group
-        .0
-        .get(path)
-        .die(format!("`{:?}` not found in config", path).as_str())
-        .clone();
-    assert!(path.exists(), "`{:?}` does not exist", path);
-    if info.is_hardlink {
+        .restore_base
+        .get_or_insert_with(|| prev_commit.to_string());
+    save_state()
+}
+
+/// Pull with `device_branches`: merge the branches of the other devices and
+/// the shared one into this device's, see [`merge_keeping_local`]. Every
+/// branch is verified before it is merged, and the merged tree after.
+async fn pull_device_branches(options: SyncOptions) -> Result<()> {
+    git(["branch", SYNC_BRANCH])?;
+    let prev_commit = git(["rev-parse", "HEAD"])?;
+    let prev_commit = prev_commit.trim();
+    let fetching = phase("fetch");
+    let refspec = format!("+refs/heads/{0}*:refs/remotes/{1}/{0}*", SYNC_BRANCH, REMOTE_NAME);
+    git_remote(["fetch", REMOTE_NAME, &refspec]).await?;
+    drop(fetching);
+
+    let own_branch = device_branch(&devicename());
+    let own = format!("refs/remotes/{}/{}", REMOTE_NAME, own_branch);
+    let pattern = format!("refs/remotes/{}/{}*", REMOTE_NAME, SYNC_BRANCH);
+    let branches = git(["for-each-ref", "--format=%(refname)", &pattern])?;
+    let branches = branches.lines().map(str::trim).filter(|branch| !branch.is_empty());
+    let mut config_changed = false;
+    let mut merged = Vec::new();
+    for branch in branches.filter(|branch| *branch != own) {
+        if git_checked(["merge-base", "--is-ancestor", branch, "HEAD"]).is_ok() {
+            continue;
+        }
+        let since_base = format!("HEAD...{}", branch);
+        let changed = git(["diff", "--name-only", &since_base, "--", CONFIG_NAME])?;
+        if !changed.trim().is_empty() {
+            if !options.accept_config {
+                git(["reset", "--hard", prev_commit])?;
+                bail!(
+                    "`{0}` changed {1}, review it with `git diff HEAD...{0} -- {1}` in {2:?} and \
+                     run `gsb sync --accept-config` to adopt it",
+                    branch,
+                    CONFIG_NAME,
+                    REPO_PATH.as_path()
+                );
+            }
+            config_changed = true;
+        }
+        let name = branch.rsplit('/').next().unwrap_or(branch);
+        let message = format!("merge {} into {}", name, own_branch);
+        let result = verify_manifest_at(branch, prev_commit)
+            .and_then(|_| merge_keeping_local(prev_commit, branch, &message, options));
+        if let Err(e) = result {
+            abort_merge(prev_commit)?;
+            return Err(e.context(format!("refused to merge `{}`", branch)));
+        }
+        merged.push(branch);
+    }
+    if git(["rev-parse", "HEAD"])?.trim() == prev_commit {
+        return Ok(());
+    }
+    if let Err(e) = verify_merged(prev_commit, &merged) {
+        abort_merge(prev_commit)?;
+        return Err(e.context("refused the merged branches"));
+    }
+    pulled(prev_commit, config_changed)
+}
+
+/// Abort the merge in progress, if any, and go back to `prev_commit`.
+fn abort_merge(prev_commit: &str) -> Result<()> {
+    if !git(["rev-parse", "--quiet", "--verify", "MERGE_HEAD"])?.trim().is_empty() {
+        git_checked(["merge", "--abort"])?;
+    }
+    git_checked(["reset", "--hard", prev_commit])?;
+    Ok(())
+}
+
+/// Dump the files changed by the pulls since the last restore to the device.
+pub async fn restore(options: SyncOptions) -> Result<()> {
+    let Some(base) = STATE.lock().unwrap().restore_base.clone() else {
         return Ok(());
+    };
+    let files_changed = git(["diff", "--name-only", &base, "HEAD"])?;
+    let conflicts = conflicts(files_changed.lines().map(str::trim), &base);
+    let keep_local = resolve_conflicts(&conflicts, &base, options)?;
+    let files_changed = files_changed
+        .trim()
+        .lines()
+        .map(str::trim)
+        .filter(|path| !is_manifest_file(Path::new(path)) && *path != CONFIG_NAME)
+        .filter(|path| !is_receipt_file(Path::new(path)))
+        .filter(|path| !keep_local.contains(path))
+        .filter(|path| selected(Path::new(path)) && !in_archived_item(Path::new(path)))
+        .collect::<Vec<_>>();
+    let shadow = options.shadow.then(ShadowReport::new).transpose()?;
+    if shadow.is_none() {
+        run_hooks(HookPhase::PreRestore, &files_changed)?;
+        undo::start()?;
+        receipt::begin();
     }
-    let to = info.get_on_device();
-    if let Some(to) = to {
-        tokio::fs::copy(REPO_PATH.join(path), to).await?;
+    let restore = phase("restore");
+    let bar = overall_bar("restore", files_changed.len() as u64);
+    let jobs = files_changed.iter().map(|path| {
+        let (bar, shadow) = (&bar, shadow.as_ref());
+        async move {
+            let started = Instant::now();
+            let result = dump_changed_file(path, shadow).await;
+            bar.inc(1);
+            (PathBuf::from(path), result, started.elapsed())
+        }
+    });
+    let result = run_items(jobs.collect());
+    bar.finish_and_clear();
+    let mut report = Report::new("restore");
+    report.add_all(result);
+    save_state()?;
+    save_quarantine()?;
+    if shadow.is_none() {
+        undo::finish()?;
+        receipt::write(git(["rev-parse", "HEAD"])?.trim())?;
+    }
+    drop(restore);
+    if options.restart && shadow.is_none() {
+        let changed = files_changed.iter().map(Path::new).collect::<Vec<_>>();
+        restart_services(&changed);
     }
+    let restored = report.stats().files_copied;
+    if restored > 0 && shadow.is_none() {
+        notification::notify(
+            Category::Success,
+            &format!("gsb restored {} files", restored),
+            &files_changed.join("\n"),
+        );
+    }
+    report.finish()?;
+    if shadow.is_some() {
+        // The pull is still to be restored for real.
+        return Ok(());
+    }
+    STATE.lock().unwrap().restore_base = None;
+    save_state()?;
+    run_hooks(HookPhase::PostRestore, &files_changed)?;
     Ok(())
 }
 
-pub async fn sync_push() -> Result<()> {
-    let filemap = &CONFIG.read().unwrap().sync_group.0;
-    let result = async_scoped::TokioScope::scope_and_block(move |scope| {
-        for path in filemap.keys() {
-            scope.spawn(sync_load(path));
+/// Restore the selected items of the sync group as they were at `rev`,
+/// leaving the repository and its branches alone. Like any restore, it can be
+/// reverted with `gsb undo`.
+pub async fn restore_at(rev: &str, options: SyncOptions) -> Result<()> {
+    let commit = git_checked(["rev-parse", "--verify", &format!("{}^{{commit}}", rev)])?;
+    let commit = commit.trim();
+    let config = CONFIG.read().unwrap().clone();
+    let mut items = Vec::new();
+    for (item, info) in config.sync_group.0.iter() {
+        if !config.selects(&info.profiles, &info.tags) || info.get_on_device().is_none() {
+            continue;
+        }
+        if info.is_hardlink || is_archived(item) {
+            warn!("{:?} is a hardlink or archived item, check it out with git instead", item);
+            continue;
+        }
+        if git(["ls-tree", "--name-only", commit, "--", &item.to_string_lossy()])?.is_empty() {
+            warn!("{:?} did not exist at `{}`", item, rev);
+            continue;
+        }
+        items.push(item.clone());
+    }
+    if items.is_empty() {
+        return Ok(());
+    }
+    // The items as they were at `rev`, without touching the working tree.
+    let dir = Extracted(state_dir()?.join("at").join(commit));
+    if dir.0.exists() {
+        std::fs::remove_dir_all(&dir.0)?;
+    }
+    std::fs::create_dir_all(&dir.0)?;
+    let mut archive = git_command();
+    archive.args(["archive", "--format=tar", commit, "--"]);
+    archive.args(items.iter());
+    let output = git_output(&mut archive)?;
+    ensure!(
+        output.status.success(),
+        "failed to read {:?} at `{}`: {}",
+        items,
+        rev,
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    tar::Archive::new(output.stdout.as_slice()).unpack(&dir.0)?;
+
+    let shadow = options.shadow.then(ShadowReport::new).transpose()?;
+    if shadow.is_none() {
+        undo::start()?;
+        receipt::begin();
+    }
+    let mut report = Report::new("restore");
+    for item in items.iter() {
+        let started = Instant::now();
+        let result = restore_item_at(&config, item, &dir.0.join(item), shadow.as_ref()).await;
+        report.add(item.clone(), result, started.elapsed());
+    }
+    save_state()?;
+    if shadow.is_none() {
+        undo::finish()?;
+        receipt::write(commit)?;
+        if options.restart {
+            restart_services(&items.iter().map(PathBuf::as_path).collect::<Vec<_>>());
+        }
+    }
+    report.finish()
+}
+
+async fn restore_item_at(
+    config: &Config,
+    item: &Path,
+    from: &Path,
+    shadow: Option<&ShadowReport>,
+) -> Result<CopyStats> {
+    let info = &config.sync_group.0[item];
+    let to = info.get_on_device().context("not on this device")?;
+    ensure!(from.exists(), "{:?} did not exist yet", item);
+    if let Some(shadow) = shadow {
+        shadow.record(from, to)?;
+        return Ok(CopyStats::default());
+    }
+    undo::record(from, to).context("failed to save the files for `gsb undo`")?;
+    keep_overwritten(from, to).context("failed to keep the overwritten files")?;
+    copy_item(from, to, info.placeholder).await
+}
+
+/// The files that the next [`restore`] would find changed both here and by
+/// the pulls, with where they are on this device. Nothing is modified.
+pub fn pending_conflicts() -> Result<Vec<(String, PathBuf)>> {
+    let Some(base) = STATE.lock().unwrap().restore_base.clone() else {
+        return Ok(Vec::new());
+    };
+    let files_changed = git(["diff", "--name-only", &base, "HEAD"])?;
+    let config = CONFIG.read().unwrap().clone();
+    Ok(conflicts(files_changed.lines().map(str::trim), &base)
+        .into_iter()
+        .filter_map(|path| {
+            let (item, info) = locate(&config, Path::new(path))?;
+            let on_device = on_device(&info, &item, Path::new(path))?;
+            Some((path.to_string(), on_device))
+        })
+        .collect())
+}
+
+/// The pulled paths whose files on this device have changed since they were
+/// last collected: their size or mtime changed, and their content is neither
+/// the one at `base` nor the pulled one.
+fn conflicts<'a>(paths: impl Iterator<Item = &'a str>, base: &str) -> Vec<&'a str> {
+    let config = CONFIG.read().unwrap();
+    let state = STATE.lock().unwrap();
+    paths
+        .filter(|path| {
+            let Some(on_device) = locate(&config, Path::new(path))
+                .and_then(|(item, info)| on_device(&info, &item, Path::new(path)))
+            else {
+                return false;
+            };
+            let (Some(recorded), Ok(meta)) = (
+                state.files.get(&REPO_PATH.join(path)),
+                on_device.metadata(),
+            ) else {
+                return false;
+            };
+            FileState::from_metadata(&meta).is_ok_and(|current| !recorded.same_stat(&current))
+                && !same_content(&on_device, path, base)
+        })
+        .collect()
+}
+
+/// Whether the file at `on_device` has the content of `path` at `base` or at
+/// `HEAD`.
+fn same_content(on_device: &Path, path: &str, base: &str) -> bool {
+    let Ok(hash) = git(["hash-object", &on_device.to_string_lossy()]) else {
+        return false;
+    };
+    [base, "HEAD"].iter().any(|rev| {
+        git(["rev-parse", &format!("{}:{}", rev, path)])
+            .is_ok_and(|blob| !hash.trim().is_empty() && blob.trim() == hash.trim())
+    })
+}
+
+/// Apply the conflict policies to the `conflicts` before the restore
+/// overwrites them, returning the paths whose local files are kept. Both
+/// versions go to the conflict inbox, see [`crate::conflicts`]. With `force`,
+/// the pulled files win.
+fn resolve_conflicts<'a>(
+    conflicts: &[&'a str],
+    base: &str,
+    options: SyncOptions,
+) -> Result<Vec<&'a str>> {
+    if conflicts.is_empty() {
+        return Ok(Vec::new());
+    }
+    let config = CONFIG.read().unwrap().clone();
+    let mut decisions = Vec::new();
+    let mut refused = Vec::new();
+    for path in conflicts.iter().copied() {
+        let (item, info) = locate(&config, Path::new(path))
+            .with_context(|| format!("`{}` not found in config", path))?;
+        let on_device = on_device(&info, &item, Path::new(path))
+            .with_context(|| format!("`{}` is not on this device", path))?;
+        let policy = match info.conflict.unwrap_or(config.conflict) {
+            _ if options.force => ConflictPolicy::Remote,
+            ConflictPolicy::Ask => match options.non_interactive {
+                Some(ConflictPolicy::Ask) => ConflictPolicy::Stop,
+                Some(policy) => policy,
+                None if !std::io::stdin().is_terminal() => ConflictPolicy::Stop,
+                None => ConflictPolicy::Ask,
+            },
+            policy => policy,
+        };
+        let keep_local = match policy {
+            ConflictPolicy::Ask => ask(path, &on_device)?,
+            ConflictPolicy::Remote => false,
+            ConflictPolicy::Local => true,
+            ConflictPolicy::Newest => local_is_newer(path, &on_device)?,
+            ConflictPolicy::Stop => {
+                refused.push((path, on_device));
+                continue;
+            }
+        };
+        decisions.push((path, on_device, keep_local, info.conflict_copies));
+    }
+    notification::notify(
+        Category::Conflict,
+        &format!("gsb: {} files changed both here and on the remote", conflicts.len()),
+        &conflicts.join("\n"),
+    );
+    let mut inbox = Inbox::load();
+    if !refused.is_empty() {
+        for (path, on_device) in refused.iter() {
+            for id in inbox.record(path, on_device, base, Side::Local)? {
+                warn!("{} changed since the last collect, see `gsb conflicts show {}`", path, id);
+            }
+        }
+        inbox.save()?;
+        bail!(
+            "refused to overwrite {} files changed both here and on the remote, resolve them \
+             with `gsb conflicts resolve` or restore with `--force`",
+            refused.len()
+        );
+    }
+    let mut keep = Vec::new();
+    for (path, on_device, keep_local, copies) in decisions {
+        let kept = if keep_local { Side::Local } else { Side::Repo };
+        for id in inbox.record(path, &on_device, base, kept)? {
+            warn!("conflict {} recorded, see `gsb conflicts show {}`", id, id);
+        }
+        if keep_local {
+            warn!("keeping the local changes of {}", path);
+            keep.push(path);
+        } else if copies {
+            let copy = conflict_copy(&on_device)?;
+            warn!("restoring the remote {}, the local one is kept as {:?}", path, copy);
+        } else {
+            warn!("discarding the local changes of {}", path);
+        }
+    }
+    inbox.save()?;
+    Ok(keep)
+}
+
+/// Ask on the terminal whether to keep the local `on_device` instead of the
+/// pulled `path`. A skipped file is kept, and stays in the conflict inbox.
+fn ask(path: &str, on_device: &Path) -> Result<bool> {
+    loop {
+        let question = format!(
+            "{} changed both here and on the remote: keep [l]ocal, take [r]epo, show [d]iff \
+             or [s]kip?",
+            path
+        );
+        match prompt(&question, "s")?.as_str() {
+            "l" | "local" => return Ok(true),
+            "r" | "repo" => return Ok(false),
+            "s" | "skip" => {
+                warn!("skipped {}, resolve it later with `gsb conflicts`", path);
+                return Ok(true);
+            }
+            "d" | "diff" => {
+                let output = git_output(
+                    git_command()
+                        .args(["diff", "--no-index", "--"])
+                        .arg(REPO_PATH.join(path))
+                        .arg(on_device),
+                )?;
+                eprint!("{}", String::from_utf8_lossy(&output.stdout));
+            }
+            answer => eprintln!("unknown answer `{}`", answer),
+        }
+    }
+}
+
+/// Whether the file at `on_device` was modified after the pulled `path` was
+/// committed.
+fn local_is_newer(path: &str, on_device: &Path) -> Result<bool> {
+    let committed = git(["log", "-1", "--format=%ct", "HEAD", "--", path])?;
+    let committed = UNIX_EPOCH + Duration::from_secs(committed.trim().parse().unwrap_or(0));
+    Ok(on_device.metadata()?.modified()? > committed)
+}
+
+/// Copy `on_device`, a file or a directory, to `<name>.conflict-<device>-<time>`
+/// next to it.
+fn conflict_copy(on_device: &Path) -> Result<PathBuf> {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let mut name = on_device.file_name().unwrap_or_default().to_owned();
+    let device = CONFIG.read().unwrap().this_device();
+    name.push(format!(".conflict-{}-{}", device, secs));
+    let copy = on_device.with_file_name(name);
+    for entry in walkdir::WalkDir::new(on_device) {
+        let entry = entry?;
+        let relative = entry.path().strip_prefix(on_device)?;
+        let to = if relative.as_os_str().is_empty() {
+            copy.clone()
+        } else {
+            copy.join(relative)
+        };
+        if entry.file_type().is_dir() {
+            std::fs::create_dir_all(&to)?;
+        } else if entry.file_type().is_file() {
+            std::fs::copy(entry.path(), &to)?;
+        }
+    }
+    Ok(copy)
+}
+
+/// The item containing `path`, which is relative to the repository.
+fn locate(config: &Config, path: &Path) -> Option<(PathBuf, SyncFile)> {
+    config
+        .sync_group
+        .0
+        .iter()
+        .find(|(item, _)| path.starts_with(item))
+        .map(|(item, info)| (item.clone(), info.clone()))
+}
+
+/// Whether the item containing `path` is selected by the active profile. Paths
+/// outside any item are left to fail in [`dump_changed_file`].
+fn selected(path: &Path) -> bool {
+    let config = CONFIG.read().unwrap();
+    locate(&config, path).is_none_or(|(_, info)| config.selects(&info.profiles, &info.tags))
+}
+
+/// Whether `path` is in an item that was archived, see [`crate::archive`]. The
+/// archive itself is restored instead.
+fn in_archived_item(path: &Path) -> bool {
+    locate(&CONFIG.read().unwrap(), path).is_some_and(|(item, _)| is_archived(&item))
+}
+
+/// Where `path` in `item` is on this device.
+fn on_device(info: &SyncFile, item: &Path, path: &Path) -> Option<PathBuf> {
+    let on_device = info.get_on_device()?;
+    match path.strip_prefix(item) {
+        Ok(relative) if !relative.as_os_str().is_empty() => Some(on_device.join(relative)),
+        _ => Some(on_device.clone()),
+    }
+}
+
+/// Whether a hardlink item can be left alone, see
+/// [`crate::mounts::hardlink_usable`].
+fn hardlinked(info: &SyncFile, path: &Path) -> bool {
+    info.get_on_device()
+        .is_none_or(|on_device| hardlink_usable(on_device, &REPO_PATH.join(path)))
+}
+
+/// Where the file changed at `path` is restored from: the path in the item it
+/// stands for, the file or directory to copy and the guard of a temporary one.
+/// `None` if there is nothing to restore for it.
+fn restore_source(path: &Path) -> Result<Option<(PathBuf, PathBuf, Option<Extracted>)>> {
+    // An archived item is restored from its extracted archive.
+    if let Some(item) = item_of_archive(path) {
+        // Rehydrated on another device, its files are restored on their own.
+        if !is_archived(&item) {
+            return Ok(None);
+        }
+        let (from, extracted) = extract(&item)?;
+        return Ok(Some((item, from, Some(extracted))));
+    }
+    // A packed item is restored from its unpacked pack.
+    let packed = packed_item(&CONFIG.read().unwrap(), path);
+    if let Some((item, format)) = packed {
+        let (from, extracted) = unpack(&item, format)?;
+        return Ok(Some((item, from, Some(extracted))));
+    }
+    let encrypted = encrypted_file(&CONFIG.read().unwrap(), path);
+    if let Some(original) = encrypted {
+        let (from, extracted) = decrypt(&original)?;
+        return Ok(Some((original, from, Some(extracted))));
+    }
+    let encrypted = encrypted_item(&CONFIG.read().unwrap(), path);
+    if let Some(item) = encrypted {
+        // Every change of the item changes its index, which restores the whole
+        // item.
+        if !is_index(path) {
+            return Ok(None);
+        }
+        let (from, extracted) = decrypt_item(&item)?;
+        return Ok(Some((item, from, Some(extracted))));
+    }
+    let compressed = compressed_file(&CONFIG.read().unwrap(), path);
+    if let Some(original) = compressed {
+        let (from, extracted) = decompress(&original)?;
+        return Ok(Some((original, from, Some(extracted))));
+    }
+    let chunked = chunked_file(&CONFIG.read().unwrap(), path);
+    if let Some(original) = chunked {
+        // New chunks always come with a new chunk list, which restores the
+        // whole file.
+        if !is_chunk_list(path) {
+            return Ok(None);
+        }
+        let (from, extracted) = reassemble(&original)?;
+        return Ok(Some((original, from, Some(extracted))));
+    }
+    Ok(Some((path.to_path_buf(), REPO_PATH.join(path), None)))
+}
+
+/// Deal a file changed by the pull, which may be a whole item or a file in a
+/// directory item. If it's a hardlink, do nothing; otherwise copy only this
+/// file to the device, or only record it in the `shadow` report. Suspicious
+/// changes are quarantined instead of copied.
+#[cfg_attr(feature = "profiling", tracing::instrument(skip_all))]
+async fn dump_changed_file(path: &str, shadow: Option<&ShadowReport>) -> Result<CopyStats> {
+    let Some((path, from, _extracted)) = restore_source(Path::new(path))? else {
+        return Ok(CopyStats::default());
+    };
+    let path = path.as_path();
+    let (item, info) = locate(&CONFIG.read().unwrap(), path)
+        .with_context(|| format!("`{:?}` not found in config", path))?;
+    // Not on this device, and maybe not checked out with `sparse_checkout`.
+    if info.get_on_device().is_none() {
+        return Ok(CopyStats::default());
+    }
+    ensure!(from.exists(), "`{:?}` does not exist", path);
+    if info.is_hardlink && hardlinked(&info, &item) {
+        return Ok(CopyStats::default());
+    }
+    let to = on_device(&info, &item, path);
+    let to = to.as_deref();
+    match (to, shadow) {
+        (Some(to), Some(shadow)) => shadow.record(&from, to)?,
+        (Some(to), None) => match suspicion(&from, to)? {
+            Some(reason) => quarantine(path, &from, to, reason).await?,
+            None => {
+                undo::record(&from, to).context("failed to save the files for `gsb undo`")?;
+                keep_overwritten(&from, to).context("failed to keep the overwritten files")?;
+                match copy_item(&from, to, info.placeholder).await {
+                    Err(e) if is_access_denied(&e) => {
+                        let reason = format!("needs elevation: {:#}", e);
+                        quarantine(path, &from, to, reason).await?
+                    }
+                    result => return result,
+                }
+            }
+        },
+        (None, _) => {}
+    }
+    Ok(CopyStats::default())
+}
+
+/// Collect the files into the repository and commit them, then push if `push`.
+#[cfg_attr(feature = "profiling", tracing::instrument(skip_all))]
+pub async fn sync_push(push: bool) -> Result<()> {
+    ensure!(
+        STATE.lock().unwrap().restore_base.is_none(),
+        "the pulled changes are not restored yet, run `gsb restore` first"
+    );
+    run_hooks(HookPhase::PreCollect, &[])?;
+    let items = {
+        let config = CONFIG.read().unwrap();
+        let selected = config.sync_group.0.iter();
+        let selected = selected.filter(|(_, info)| config.selects(&info.profiles, &info.tags));
+        selected.map(|(item, _)| item.clone()).collect::<Vec<_>>()
+    };
+    let archive_after = CONFIG
+        .read()
+        .unwrap()
+        .archive_after
+        .as_deref()
+        .map(humantime::parse_duration)
+        .transpose()
+        .context("invalid `archive_after`")?;
+    let items = unarchive_touched(items, archive_after)?;
+    let collect = phase("collect");
+    let bar = overall_bar("collect", items.len() as u64);
+    let jobs = items.iter().map(|path| {
+        let bar = &bar;
+        async move {
+            let started = Instant::now();
+            let result = sync_load(path).await;
+            bar.inc(1);
+            (path.clone(), result, started.elapsed())
         }
     });
-    result.1.into_iter().flatten().collect::<Result<()>>()?;
+    let result = run_items(jobs.collect());
+    bar.finish_and_clear();
+    let mut report = Report::new("collect");
+    report.add_all(result);
+    save_state()?;
+    if let Some(age) = archive_after {
+        archive_untouched(&items, age)?;
+    }
+    write_manifest()?;
+    drop(collect);
 
+    let commit_phase = phase("commit");
+    let head = git(["rev-parse", "HEAD"])?;
+    sparse::apply()?;
+    lfs::ensure_attributes()?;
     git(["add", "."])?;
-    git(["push", REMOTE_NAME, SYNC_BRANCH])?;
+    commit("collect", items.iter().map(PathBuf::as_path))?;
+    drop(commit_phase);
+    if push {
+        let _push = phase("push");
+        push_sync_branch().await?;
+    }
+    let committed = git(["diff", "--name-only", head.trim(), "HEAD"])?;
+    run_hooks(HookPhase::PostCollect, &committed.lines().collect::<Vec<_>>())?;
+    report.finish()
+}
+
+/// The `items` to collect. Archived items are left out while untouched for
+/// `archive_after`, and rehydrated once touched again.
+fn unarchive_touched(items: Vec<PathBuf>, archive_after: Option<Duration>) -> Result<Vec<PathBuf>> {
+    let config = CONFIG.read().unwrap();
+    let mut active = Vec::new();
+    for item in items {
+        if is_archived(&item) {
+            let on_device = config.sync_group.0.get(&item).and_then(|info| info.get_on_device());
+            let Some(on_device) = on_device else {
+                continue;
+            };
+            if archive_after.is_some_and(|age| untouched_for(on_device, age)) {
+                continue;
+            }
+            unarchive_item(&item)?;
+        }
+        active.push(item);
+    }
+    Ok(active)
+}
+
+/// Archive the collected `items` untouched for `age`. Hardlink items are never
+/// archived, their files in the repository are the ones on the device, and
+/// packed items are compressed already.
+fn archive_untouched(items: &[PathBuf], age: Duration) -> Result<()> {
+    let config = CONFIG.read().unwrap();
+    for item in items {
+        let Some(info) = config.sync_group.0.get(item) else {
+            continue;
+        };
+        let Some(on_device) = info.get_on_device() else {
+            continue;
+        };
+        if info.is_hardlink || info.archive.is_some() {
+            continue;
+        }
+        if REPO_PATH.join(item).exists() && untouched_for(on_device, age) {
+            archive_item(item)?;
+        }
+    }
     Ok(())
 }
 
-async fn sync_load(path: &Path) -> Result<()> {
+#[cfg_attr(feature = "profiling", tracing::instrument(skip_all))]
+async fn sync_load(path: &Path) -> Result<CopyStats> {
     let info = CONFIG
         .read()
         .unwrap()
         .sync_group
         .0
         .get(path)
-        .die(format!("`{:?}` not found in config", path).as_str())
+        .with_context(|| format!("`{:?}` not found in config", path))?
         .clone();
 
-    assert!(path.exists(), "`{:?}` does not exist", path);
-    if info.is_hardlink {
-        return Ok(());
+    if info.is_hardlink && hardlinked(&info, path) {
+        return Ok(CopyStats::default());
     }
 
     let from = info.get_on_device();
     if let Some(from) = from {
-        tokio::fs::copy(from, REPO_PATH.join(path)).await?;
+        ensure!(
+            from.exists(),
+            "`{:?}` does not exist on {}",
+            from,
+            CONFIG.read().unwrap().this_device()
+        );
+        limits::check(from, path, info.limits)?;
+        if let Some(format) = info.archive {
+            return pack(from, path, format);
+        }
+        if let Some(encryption) = info.encrypt {
+            return encrypt_item(from, path, encryption);
+        }
+        if info.compress.is_some() {
+            return compress_item(from, path);
+        }
+        if info.chunk {
+            return chunk_item(from, path);
+        }
+        return copy_item(from, &REPO_PATH.join(path), info.placeholder).await;
     }
 
+    Ok(CopyStats::default())
+}
+
+/// Push the collected commits.
+pub async fn push() -> Result<()> {
+    let _push = phase("push");
+    push_sync_branch().await?;
+    info!("pushed {}", SYNC_BRANCH);
     Ok(())
 }
+
+/// Push the sync branch, or with `device_branches`, this device's branch. The
+/// shared branch then follows when it can be fast-forwarded, so a new device
+/// still clones everything.
+async fn push_sync_branch() -> Result<()> {
+    if !CONFIG.read().unwrap().device_branches {
+        return push_branch(REMOTE_NAME, SYNC_BRANCH).await;
+    }
+    let own = format!("{}:{}", SYNC_BRANCH, device_branch(&devicename()));
+    push_branch(REMOTE_NAME, &own).await?;
+    if let Err(e) = git_remote(["push", REMOTE_NAME, SYNC_BRANCH]).await {
+        info!("left the shared `{}` behind until the next pull: {}", SYNC_BRANCH, e);
+    }
+    Ok(())
+}
+
+/// Pull the changes from the remote, then push the local ones.
+pub async fn sync(options: SyncOptions) -> Result<()> {
+    sync_pull(options).await?;
+    if options.shadow {
+        info!("shadow mode, nothing is collected, committed or pushed");
+        return Ok(());
+    }
+    sync_push(true).await
+}
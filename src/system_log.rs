@@ -0,0 +1,169 @@
+//! Logging to journald or syslog, for daemons managed by systemd or another
+//! service manager. The key-values of a record (e.g. `item`, `action`) become
+//! structured journald fields like `GSB_ITEM`.
+
+use anyhow::Result;
+use env_logger::filter::Filter;
+
+use crate::config::LogTarget;
+
+#[cfg(unix)]
+pub fn init(target: LogTarget, filter: Filter) -> Result<()> {
+    use std::os::unix::net::UnixDatagram;
+
+    use crate::config::CONFIG;
+
+    let socket = UnixDatagram::unbound()?;
+    socket.connect(match target {
+        LogTarget::Journald => "/run/systemd/journal/socket",
+        _ => "/dev/log",
+    })?;
+    log::set_max_level(filter.filter());
+    log::set_boxed_logger(Box::new(unix::SystemLogger {
+        socket,
+        target,
+        filter,
+        device: CONFIG.read().unwrap().this_device(),
+    }))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn init(target: LogTarget, _filter: Filter) -> Result<()> {
+    anyhow::bail!("logging to {:?} is only available on unix", target)
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::os::unix::net::UnixDatagram;
+
+    use env_logger::filter::Filter;
+    use log::{
+        kv::{self, Key, VisitSource},
+        Level, Log, Metadata, Record,
+    };
+
+    use crate::config::LogTarget;
+
+    const IDENTIFIER: &str = "gsb";
+
+    pub struct SystemLogger {
+        pub socket: UnixDatagram,
+        pub target: LogTarget,
+        pub filter: Filter,
+        pub device: String,
+    }
+
+    /// The key-values of a record.
+    #[derive(Default)]
+    struct Fields(Vec<(String, String)>);
+
+    impl<'kvs> VisitSource<'kvs> for Fields {
+        fn visit_pair(&mut self, key: Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+            self.0.push((key.to_string(), value.to_string()));
+            Ok(())
+        }
+    }
+
+    /// The syslog severity of a level, also used by journald.
+    fn severity(level: Level) -> u8 {
+        match level {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug | Level::Trace => 7,
+        }
+    }
+
+    /// Append a field in the journald native protocol. Values with newlines
+    /// need the binary form with an explicit length.
+    fn journald_field(buf: &mut Vec<u8>, key: &str, value: &str) {
+        if value.contains('\n') {
+            buf.extend_from_slice(key.as_bytes());
+            buf.push(b'\n');
+            buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            buf.extend_from_slice(value.as_bytes());
+            buf.push(b'\n');
+        } else {
+            buf.extend_from_slice(format!("{}={}\n", key, value).as_bytes());
+        }
+    }
+
+    impl SystemLogger {
+        fn journald_message(&self, record: &Record, fields: &Fields) -> Vec<u8> {
+            let mut buf = Vec::new();
+            journald_field(&mut buf, "PRIORITY", &severity(record.level()).to_string());
+            journald_field(&mut buf, "MESSAGE", &record.args().to_string());
+            journald_field(&mut buf, "SYSLOG_IDENTIFIER", IDENTIFIER);
+            journald_field(&mut buf, "GSB_DEVICE", &self.device);
+            journald_field(&mut buf, "GSB_TARGET", record.target());
+            for (key, value) in fields.0.iter() {
+                let key = key
+                    .chars()
+                    .map(|c| match c.is_ascii_alphanumeric() {
+                        true => c.to_ascii_uppercase(),
+                        false => '_',
+                    })
+                    .collect::<String>();
+                journald_field(&mut buf, &format!("GSB_{}", key), value);
+            }
+            buf
+        }
+
+        /// A RFC 3164 message with the `user` facility, leaving the timestamp
+        /// and host to the syslog daemon.
+        fn syslog_message(&self, record: &Record, fields: &Fields) -> Vec<u8> {
+            let mut message = format!(
+                "<{}>{}[{}]: {}",
+                8 + severity(record.level()),
+                IDENTIFIER,
+                std::process::id(),
+                record.args()
+            );
+            for (key, value) in fields.0.iter() {
+                message.push_str(&format!(" {}={}", key, value));
+            }
+            message.into_bytes()
+        }
+    }
+
+    impl Log for SystemLogger {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            self.filter.enabled(metadata)
+        }
+
+        fn log(&self, record: &Record) {
+            if !self.filter.matches(record) {
+                return;
+            }
+            let mut fields = Fields::default();
+            let _ = record.key_values().visit(&mut fields);
+            let message = match self.target {
+                LogTarget::Journald => self.journald_message(record, &fields),
+                _ => self.syslog_message(record, &fields),
+            };
+            let _ = self.socket.send(&message);
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_journald_field() {
+            let mut buf = Vec::new();
+            journald_field(&mut buf, "GSB_ITEM", "notes");
+            assert_eq!(buf, b"GSB_ITEM=notes\n");
+
+            let mut buf = Vec::new();
+            journald_field(&mut buf, "MESSAGE", "a\nb");
+            let mut expected = b"MESSAGE\n".to_vec();
+            expected.extend_from_slice(&3u64.to_le_bytes());
+            expected.extend_from_slice(b"a\nb\n");
+            assert_eq!(buf, expected);
+        }
+    }
+}
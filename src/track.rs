@@ -0,0 +1,143 @@
+//! `gsb track`, made to be called from a context menu of a file manager or a
+//! shell alias with any path.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    backup::backup,
+    cli::{print_text, Group},
+    config::{save_config, BackupFile, Config, Getable, SyncFile, CONFIG},
+    device::devicename,
+    sync::sync_push,
+    utils::{confirm, prompt},
+};
+
+/// Track `path` in a new item of `group`, unless an item covers it already,
+/// then collect it at once. Asks for the path in the repository unless `yes`.
+pub async fn track(path: &Path, group: Group, yes: bool) -> Result<()> {
+    if !add_item(path, group, yes)? {
+        return Ok(());
+    }
+    save_config()?;
+    collect(group).await
+}
+
+/// `gsb add`: track all `paths` in `group` without asking, then collect them
+/// once.
+pub async fn add(paths: &[String], group: Group) -> Result<()> {
+    let mut added = false;
+    for path in paths {
+        added |= add_item(Path::new(path), group, true)?;
+    }
+    if !added {
+        return Ok(());
+    }
+    save_config()?;
+    collect(group).await
+}
+
+/// Add the item of `path` to the config, returning whether it was added.
+fn add_item(path: &Path, group: Group, yes: bool) -> Result<bool> {
+    let path = path
+        .canonicalize()
+        .with_context(|| format!("`{:?}` does not exist", path))?;
+    if let Some((group, item)) = covering_item(&CONFIG.read().unwrap(), &path) {
+        print_text(&format!(
+            "`{}` is already tracked by `{}` in the {} group",
+            path.display(),
+            item.display(),
+            group
+        ));
+        return Ok(false);
+    }
+
+    let mut item = default_item(&path);
+    if !yes {
+        let answer = prompt("Path in the repository", &item.to_string_lossy())?;
+        item = PathBuf::from(answer);
+        if !confirm(&format!("Track `{}` as `{}`?", path.display(), item.display()))? {
+            return Ok(false);
+        }
+    }
+    {
+        let mut config = CONFIG.write().unwrap();
+        if config.sync_group.0.contains_key(&item) || config.backup_group.0.contains_key(&item) {
+            bail!("`{}` is already an item", item.display());
+        }
+        match group {
+            Group::Sync => {
+                let info = SyncFile {
+                    path_on_devices: [(devicename(), path.clone())].into(),
+                    is_hardlink: false,
+                    placeholder: Default::default(),
+                    conflict: None,
+                    conflict_copies: false,
+                    profiles: Vec::new(),
+                    tags: Vec::new(),
+                    archive: None,
+                    compress: None,
+                    chunk: false,
+                    encrypt: None,
+                    limits: None,
+                    lfs: false,
+                };
+                config.sync_group.0.insert(item, info);
+            }
+            Group::Backup => {
+                let info = BackupFile {
+                    path_on_device: path.clone(),
+                    is_hardlink: false,
+                    placeholder: Default::default(),
+                    profiles: Vec::new(),
+                    tags: Vec::new(),
+                    workspace: None,
+                    limits: None,
+                    lfs: false,
+                };
+                config.backup_group.0.insert(item, info);
+            }
+        }
+    }
+    Ok(true)
+}
+
+async fn collect(group: Group) -> Result<()> {
+    match group {
+        Group::Sync => sync_push(false).await,
+        Group::Backup => backup().await,
+    }
+}
+
+/// The group and item whose path on this device contains `path`.
+fn covering_item(config: &Config, path: &Path) -> Option<(&'static str, PathBuf)> {
+    let synced = config.sync_group.0.iter().find_map(|(item, info)| {
+        info.get_on_device()
+            .filter(|on_device| path.starts_with(on_device))
+            .map(|_| ("sync", item.clone()))
+    });
+    synced.or_else(|| {
+        config
+            .backup_group
+            .0
+            .iter()
+            .find(|(_, info)| path.starts_with(&info.path_on_device))
+            .map(|(item, _)| ("backup", item.clone()))
+    })
+}
+
+/// The path in the repository for `path`: relative to the home directory if
+/// inside it, with the leading dot of dotfiles dropped so they are visible in
+/// the repository, or its file name otherwise.
+fn default_item(path: &Path) -> PathBuf {
+    let home = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from);
+    let relative = home
+        .and_then(|home| path.strip_prefix(home).ok().map(Path::to_path_buf))
+        .filter(|relative| !relative.as_os_str().is_empty())
+        .unwrap_or_else(|| path.file_name().map(PathBuf::from).unwrap_or_default());
+    let relative = relative.to_string_lossy();
+    PathBuf::from(relative.strip_prefix('.').unwrap_or(&relative))
+}
@@ -0,0 +1,78 @@
+//! Keeping the local files a restore overwrites, see `restore.backup`.
+
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::LazyLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use log::info;
+
+use crate::{
+    config::{RestoreBackup, CONFIG},
+    utils::{hash_file, item_files},
+    workspace::expand_home,
+};
+
+/// The trash directory of this run, so the files of one restore stay together.
+static RUN_TRASH: LazyLock<PathBuf> = LazyLock::new(|| {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    data_dir().join("gsb").join("trash").join(secs.to_string())
+});
+
+fn data_dir() -> PathBuf {
+    ["XDG_DATA_HOME", "LOCALAPPDATA"]
+        .iter()
+        .find_map(std::env::var_os)
+        .map_or_else(|| expand_home(Path::new("~/.local/share")), PathBuf::from)
+}
+
+/// Move away the files under `to` that restoring `from` would overwrite with a
+/// different content, as chosen by `restore.backup`.
+pub fn keep_overwritten(from: &Path, to: &Path) -> Result<()> {
+    let backup = CONFIG.read().unwrap().restore.backup;
+    if backup == RestoreBackup::None {
+        return Ok(());
+    }
+    for (source, target) in item_files(from, to)? {
+        if !target.is_file() || hash_file(&target)? == hash_file(&source)? {
+            continue;
+        }
+        let kept = match backup {
+            RestoreBackup::None => continue,
+            RestoreBackup::Trash => trash_path(&target),
+            RestoreBackup::Bak => {
+                let mut name = target.file_name().unwrap_or_default().to_owned();
+                name.push(".gsb.bak");
+                target.with_file_name(name)
+            }
+        };
+        if let Some(parent) = kept.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // A rename does not work across filesystems, e.g. into the trash.
+        if std::fs::rename(&target, &kept).is_err() {
+            std::fs::copy(&target, &kept)?;
+        }
+        info!("kept the overwritten {:?} as {:?}", target, kept);
+    }
+    Ok(())
+}
+
+/// Where `path` goes in the trash of this run, keeping its absolute path.
+fn trash_path(path: &Path) -> PathBuf {
+    let mut trashed = RUN_TRASH.clone();
+    for component in path.components() {
+        match component {
+            Component::Prefix(prefix) => {
+                trashed.push(prefix.as_os_str().to_string_lossy().replace(':', ""))
+            }
+            Component::Normal(name) => trashed.push(name),
+            _ => {}
+        }
+    }
+    trashed
+}
@@ -0,0 +1,128 @@
+//! `gsb undo`: put back the files of the last restore as they were before it.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{bail, Result};
+use config_file::*;
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cli::json_output,
+    git_command::REPO_PATH,
+    state::{save_state, state_dir, STATE, STATE_DIR},
+    utils::{hash_file, item_files},
+};
+
+const UNDO_DIR: &str = "undo";
+const JOURNAL_NAME: &str = "journal.toml";
+
+static JOURNAL: Mutex<Journal> = Mutex::new(Journal {
+    entries: Vec::new(),
+});
+
+/// The files written by the last restore, kept in `.gsb/undo/`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Journal {
+    #[serde(default)]
+    entries: Vec<JournalEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct JournalEntry {
+    /// A file written by the restore.
+    target: PathBuf,
+    /// The name of its former content in `.gsb/undo/`, `None` if the restore
+    /// created it.
+    saved: Option<String>,
+}
+
+fn undo_dir() -> Result<PathBuf> {
+    Ok(state_dir()?.join(UNDO_DIR))
+}
+
+/// Start the journal of a restore, dropping the one of the restore before.
+pub fn start() -> Result<()> {
+    let dir = undo_dir()?;
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir)?;
+    }
+    std::fs::create_dir_all(dir)?;
+    JOURNAL.lock().unwrap().entries.clear();
+    Ok(())
+}
+
+/// Save the files under `to` that restoring `from` is about to change.
+pub fn record(from: &Path, to: &Path) -> Result<()> {
+    let dir = undo_dir()?;
+    for (source, target) in item_files(from, to)? {
+        if target.is_file() && hash_file(&target)? == hash_file(&source)? {
+            continue;
+        }
+        let created = !target.exists();
+        if !created && !target.is_file() {
+            continue;
+        }
+        let saved = {
+            let mut journal = JOURNAL.lock().unwrap();
+            let saved = (!created).then(|| journal.entries.len().to_string());
+            journal.entries.push(JournalEntry {
+                target: target.clone(),
+                saved: saved.clone(),
+            });
+            saved
+        };
+        if let Some(saved) = saved {
+            std::fs::copy(&target, dir.join(saved))?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the journal of the restore, for `gsb undo`.
+pub fn finish() -> Result<()> {
+    JOURNAL
+        .lock()
+        .unwrap()
+        .to_config_file(undo_dir()?.join(JOURNAL_NAME))?;
+    Ok(())
+}
+
+/// Put back the files written by the last restore, and forget that they were
+/// restored, so the next restore writes them again.
+pub fn undo() -> Result<()> {
+    let dir = REPO_PATH.join(STATE_DIR).join(UNDO_DIR);
+    let Ok(journal) = Journal::from_config_file(dir.join(JOURNAL_NAME)) else {
+        bail!("nothing to undo, no restore was recorded");
+    };
+    let (mut put_back, mut removed) = (Vec::new(), Vec::new());
+    for entry in journal.entries.iter().rev() {
+        match entry.saved.as_ref() {
+            Some(saved) => {
+                std::fs::copy(dir.join(saved), &entry.target)?;
+                info!("put back {:?}", entry.target);
+                put_back.push(&entry.target);
+            }
+            None if entry.target.exists() => {
+                std::fs::remove_file(&entry.target)?;
+                info!("removed {:?}", entry.target);
+                removed.push(&entry.target);
+            }
+            None => {}
+        }
+        STATE.lock().unwrap().files.remove(&entry.target);
+    }
+    save_state()?;
+    std::fs::remove_dir_all(dir)?;
+    info!(
+        "undid the last restore of {} files, the next collect commits them as they are now",
+        journal.entries.len()
+    );
+    if json_output() {
+        println!("{}", serde_json::json!({ "put_back": put_back, "removed": removed }));
+    }
+    Ok(())
+}
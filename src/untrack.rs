@@ -0,0 +1,104 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use log::info;
+
+use crate::{
+    attic,
+    cli::json_output,
+    commit::commit,
+    config::{save_config, Getable, CONFIG, CONFIG_NAME},
+    git_command::{git, REPO_PATH},
+    utils::confirm,
+};
+
+/// What `gsb untrack` cleans up besides the config. Asked for interactively if
+/// none is given.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UntrackOptions {
+    /// Remove the files of the item from the repository.
+    pub remove_files: bool,
+    /// Turn the hardlinks on this device into independent files.
+    pub unlink: bool,
+    /// Only remove the item from the config, leaving everything in place.
+    pub keep: bool,
+}
+
+/// Remove `item` from the config and clean up after it, then commit.
+pub fn untrack(item: &Path, mut options: UntrackOptions) -> Result<()> {
+    let (is_hardlink, on_device) = {
+        let config = CONFIG.read().unwrap();
+        let found = if let Some(info) = config.sync_group.0.get(item) {
+            (info.is_hardlink, info.get_on_device().cloned())
+        } else if let Some(info) = config.backup_group.0.get(item) {
+            (info.is_hardlink, Some(info.path_on_device.clone()))
+        } else {
+            bail!("`{}` is not an item", item.display());
+        };
+        if let Some(file) = config.included_item(item) {
+            bail!(
+                "`{}` comes from the included `{}`, remove it there",
+                item.display(),
+                file.display()
+            );
+        }
+        found
+    };
+    if !options.keep && !options.remove_files && !options.unlink {
+        options.remove_files = confirm(&format!(
+            "Remove the files of `{}` from the repository?",
+            item.display()
+        ))?;
+        options.unlink = is_hardlink
+            && on_device.is_some()
+            && confirm("Turn the hardlinks on this device into independent files?")?;
+    }
+
+    if options.unlink && is_hardlink {
+        if let Some(on_device) = on_device.as_ref() {
+            unlink(on_device)?;
+        }
+    }
+    {
+        let mut config = CONFIG.write().unwrap();
+        config.sync_group.0.remove(item);
+        config.backup_group.0.remove(item);
+        config.services.remove(item);
+    }
+    save_config()?;
+    git(["add", CONFIG_NAME])?;
+    if options.remove_files && REPO_PATH.join(item).exists() {
+        attic::stash(item)?;
+        let item = item.to_string_lossy();
+        git(["rm", "-r", "--quiet", "--", &item])?;
+    }
+    commit("untrack", [item])?;
+    info!("untracked `{}`", item.display());
+    if json_output() {
+        let output = serde_json::json!({
+            "untracked": item,
+            "removed_files": options.remove_files,
+            "unlinked": options.unlink && is_hardlink,
+        });
+        println!("{}", output);
+    }
+    Ok(())
+}
+
+/// Replace every file under `path` by a copy of itself, so it no longer shares
+/// its content with the repository. The copy is renamed over the link, so the
+/// file is never missing.
+fn unlink(path: &Path) -> Result<()> {
+    for entry in walkdir::WalkDir::new(path) {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let mut copy = entry.path().as_os_str().to_owned();
+        copy.push(".gsb-unlink");
+        let copy = PathBuf::from(copy);
+        std::fs::copy(entry.path(), &copy)?;
+        std::fs::rename(&copy, entry.path())?;
+    }
+    Ok(())
+}
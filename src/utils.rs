@@ -0,0 +1,716 @@
+use std::{
+    collections::HashSet,
+    fs::{File, Metadata, OpenOptions},
+    future::Future,
+    io::{self, Read, Write},
+    ops::AddAssign,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, LazyLock,
+    },
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Context, Result};
+use log::{debug, warn};
+use serde::Serialize;
+use tokio::sync::{oneshot, Semaphore};
+
+use crate::{
+    capabilities::common_capabilities,
+    cli::CLI,
+    config::{CompareConfig, CompareMode, LogTarget, PlaceholderPolicy, CONFIG},
+    events::{emit, Event, SkipReason},
+    mounts::is_network_fs,
+    progress::{item_bar, set_bytes},
+    state::{FileState, STATE},
+    system_log,
+};
+
+/// The log filter directives: from `-q`/`-v` if given, `RUST_LOG` otherwise,
+/// and info by default.
+fn log_filters() -> String {
+    match CLI.get().and_then(|cli| cli.log_level()) {
+        Some(level) => level.to_string(),
+        None => std::env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string()),
+    }
+}
+
+/// Init the logger, see [`log_filters`] for the level. The logs go to stderr
+/// and/or a rotated file, or to journald or syslog, see
+/// [`crate::config::LoggingConfig`].
+pub fn log_init() {
+    let logging = CONFIG.read().unwrap().logging.clone();
+    if logging.target != LogTarget::Stderr {
+        let filter = env_logger::filter::Builder::new()
+            .parse(&log_filters())
+            .build();
+        match system_log::init(logging.target, filter) {
+            Ok(()) => return,
+            Err(e) => eprintln!("failed to log to {:?}, using stderr: {}", logging.target, e),
+        }
+    }
+
+    let mut builder = env_logger::Builder::new();
+    builder.parse_filters(&log_filters());
+    if let Some(path) = logging.file.as_ref() {
+        let max_age = match logging.rotate_every.as_deref().map(humantime::parse_duration) {
+            Some(Err(e)) => {
+                eprintln!("invalid `logging.rotate_every`: {}", e);
+                None
+            }
+            max_age => max_age.and_then(Result::ok),
+        };
+        match RotatingFile::open(path, logging.max_size, max_age, logging.keep) {
+            Ok(file) => {
+                let mut writers: Vec<Box<dyn Write + Send>> = vec![Box::new(file)];
+                if logging.stderr {
+                    writers.push(Box::new(io::stderr()));
+                }
+                builder.target(env_logger::Target::Pipe(Box::new(Tee(writers))));
+            }
+            Err(e) => eprintln!("failed to open the log file `{:?}`: {}", path, e),
+        }
+    }
+    builder.init();
+}
+
+/// A log file rotated by size or age: `gsb.log` is renamed to `gsb.log.1`, the
+/// old `gsb.log.1` to `gsb.log.2` and so on, keeping `keep` old files.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    opened: SystemTime,
+    max_size: u64,
+    max_age: Option<Duration>,
+    keep: u32,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_size: u64, max_age: Option<Duration>, keep: u32) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let meta = file.metadata()?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            size: meta.len(),
+            opened: meta.created().unwrap_or_else(|_| SystemTime::now()),
+            file,
+            max_size,
+            max_age,
+            keep,
+        })
+    }
+
+    fn numbered(&self, n: u32) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for n in (1..self.keep).rev() {
+            let from = self.numbered(n);
+            if from.exists() {
+                std::fs::rename(from, self.numbered(n + 1))?;
+            }
+        }
+        if self.keep > 0 {
+            std::fs::rename(&self.path, self.numbered(1))?;
+        } else {
+            std::fs::remove_file(&self.path)?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        self.opened = SystemTime::now();
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let too_old = self
+            .max_age
+            .is_some_and(|max_age| self.opened.elapsed().unwrap_or_default() > max_age);
+        if self.size > 0 && (self.size + buf.len() as u64 > self.max_size || too_old) {
+            self.rotate()?;
+        }
+        let len = self.file.write(buf)?;
+        self.size += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Write to all writers.
+struct Tee(Vec<Box<dyn Write + Send>>);
+
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for writer in self.0.iter_mut() {
+            writer.write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.iter_mut().try_for_each(|writer| writer.flush())
+    }
+}
+
+/// Ask a yes/no question on the terminal, defaulting to no.
+pub fn confirm(question: &str) -> Result<bool> {
+    eprint!("{} [y/N] ", question);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Ask for a line on the terminal, `default` if nothing is entered.
+pub fn prompt(question: &str, default: &str) -> Result<String> {
+    eprint!("{} [{}] ", question, default);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    match answer.trim() {
+        "" => Ok(default.to_string()),
+        answer => Ok(answer.to_string()),
+    }
+}
+
+/// Whether the items and their files are processed one after the other in a
+/// stable order, see [`crate::config::Config::deterministic`].
+pub fn ordered() -> bool {
+    CLI.get().is_some_and(|cli| cli.ordered) || CONFIG.read().unwrap().deterministic
+}
+
+/// Walk `path`, sorted by file name if [`ordered`].
+pub fn walk(path: &Path) -> walkdir::WalkDir {
+    let walker = walkdir::WalkDir::new(path);
+    if ordered() {
+        walker.sort_by_file_name()
+    } else {
+        walker
+    }
+}
+
+/// The result of a job of [`run_items`]: the item, its result and how long it
+/// took.
+pub type ItemOutcome = (PathBuf, Result<CopyStats>, Duration);
+
+/// Run the `jobs` of the items all at once, or one after the other in their
+/// order if [`ordered`].
+pub fn run_items<'a, F>(jobs: Vec<F>) -> Vec<Result<ItemOutcome>>
+where
+    F: Future<Output = ItemOutcome> + Send + 'a,
+{
+    if ordered() {
+        return tokio::task::block_in_place(|| {
+            tokio::runtime::Handle::current().block_on(async {
+                let mut outcomes = Vec::new();
+                for job in jobs {
+                    outcomes.push(Ok(job.await));
+                }
+                outcomes
+            })
+        });
+    }
+    let (_, results) = async_scoped::TokioScope::scope_and_block(|scope| {
+        for job in jobs {
+            scope.spawn(job);
+        }
+    });
+    results.into_iter().map(|result| result.map_err(Into::into)).collect()
+}
+
+/// The files of the item at `from` with where [`copy_item`] puts them under
+/// `to`.
+pub fn item_files(from: &Path, to: &Path) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let mut files = Vec::new();
+    for entry in walk(from) {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(from)?;
+        let target = if relative.as_os_str().is_empty() {
+            to.to_path_buf()
+        } else {
+            to.join(relative)
+        };
+        files.push((entry.path().to_path_buf(), target));
+    }
+    Ok(files)
+}
+
+/// What [`copy_item`] has done.
+#[derive(Serialize, Debug, Clone, Copy, Default)]
+pub struct CopyStats {
+    pub files_copied: u64,
+    pub files_skipped: u64,
+    pub bytes_copied: u64,
+}
+
+impl AddAssign for CopyStats {
+    fn add_assign(&mut self, rhs: Self) {
+        self.files_copied += rhs.files_copied;
+        self.files_skipped += rhs.files_skipped;
+        self.bytes_copied += rhs.bytes_copied;
+    }
+}
+
+/// Copy an item (a file or a whole directory) from `from` to `to`, skipping
+/// the files that have not changed according to the configured
+/// [`CompareMode`].
+///
+/// Reflinks are tried first, so on copy-on-write filesystems (Btrfs, XFS,
+/// APFS, ReFS) the copy is nearly instant and shares the data blocks. Other
+/// filesystems fall back to a normal copy.
+///
+/// Cloud-only placeholders in `from` are dealt with according to `placeholder`.
+pub async fn copy_item(
+    from: &Path,
+    to: &Path,
+    placeholder: PlaceholderPolicy,
+) -> Result<CopyStats> {
+    let timeout = CONFIG
+        .read()
+        .unwrap()
+        .item_timeout
+        .as_deref()
+        .map(humantime::parse_duration)
+        .transpose()
+        .context("invalid `item_timeout`")?;
+    let network = is_network_fs(from) || is_network_fs(to);
+    // Network filesystems are slower and stall now and then.
+    let timeout = timeout.map(|timeout| match network {
+        true => timeout * NETWORK_TIMEOUT_FACTOR,
+        false => timeout,
+    });
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let copy = async {
+        // Held by the thread until the copy really ends, even after a timeout.
+        let memory = MEMORY.acquire_many(HASH_BUFFER_SIZE as u32).await?;
+        let thread = THREADS.acquire().await?;
+        let (tx, rx) = oneshot::channel();
+        let (from, to, cancelled) = (from.to_path_buf(), to.to_path_buf(), cancelled.clone());
+        // Not `spawn_blocking`, the runtime would wait for a hung copy on exit.
+        std::thread::spawn(move || {
+            let _permits = (memory, thread);
+            let _ = tx.send(copy_item_blocking(&from, &to, placeholder, network, &cancelled));
+        });
+        rx.await?
+    };
+    let Some(timeout) = timeout else {
+        return copy.await;
+    };
+    match tokio::time::timeout(timeout, copy).await {
+        Ok(result) => result,
+        Err(_) => {
+            cancelled.store(true, Ordering::Relaxed);
+            bail!(
+                "`{:?}` cancelled after {}",
+                from,
+                humantime::format_duration(timeout)
+            )
+        }
+    }
+}
+
+#[cfg_attr(feature = "profiling", tracing::instrument(skip_all))]
+fn copy_item_blocking(
+    from: &Path,
+    to: &Path,
+    placeholder: PlaceholderPolicy,
+    network: bool,
+    cancelled: &AtomicBool,
+) -> Result<CopyStats> {
+    let bar = item_bar(from);
+    let mut compare = CONFIG.read().unwrap().compare;
+    if network {
+        // Network filesystems round or fake mtimes, so they cannot be trusted.
+        warn!(
+            "`{:?}` is copied over a network filesystem, comparing by hash with a longer timeout",
+            from
+        );
+        compare.method = CompareMode::Hash;
+    }
+    let capabilities = common_capabilities(from, to);
+    let resolution_ms = capabilities.mtime_resolution_ns.div_ceil(1_000_000);
+    compare.mtime_tolerance_ms = compare.mtime_tolerance_ms.max(resolution_ms);
+    let mut stats = CopyStats::default();
+    // The lowercased paths of the files, if `to` does not tell them apart.
+    let mut folded = HashSet::new();
+    emit(Event::ItemStarted {
+        from: from.to_path_buf(),
+        to: to.to_path_buf(),
+    });
+    for entry in walk(from) {
+        if cancelled.load(Ordering::Relaxed) {
+            bail!("cancelled");
+        }
+        let entry = entry?;
+        // A single file item has its own path as the only entry.
+        let relative = entry.path().strip_prefix(from)?;
+        let target = if relative.as_os_str().is_empty() {
+            to.to_path_buf()
+        } else {
+            to.join(relative)
+        };
+        if !capabilities.case_sensitive && !folded.insert(relative.to_string_lossy().to_lowercase())
+        {
+            warn!(
+                "`{:?}` differs from another path only in case, `{:?}` keeps only one of them",
+                entry.path(),
+                to
+            );
+        }
+        if entry.file_type().is_dir() {
+            std::fs::create_dir_all(&target)?;
+        } else {
+            stats += copy_file(
+                entry.path(),
+                &target,
+                placeholder,
+                compare,
+                capabilities.reflink,
+                !network,
+            )?;
+            bar.inc(1);
+            set_bytes(&bar, stats.bytes_copied);
+        }
+    }
+    bar.finish_and_clear();
+    Ok(stats)
+}
+
+/// Copy the file `from` to `to` unless unchanged. The recorded size and mtime
+/// are only enough to skip the file if `trust_stat`.
+fn copy_file(
+    from: &Path,
+    to: &Path,
+    placeholder: PlaceholderPolicy,
+    compare: CompareConfig,
+    reflink: bool,
+    trust_stat: bool,
+) -> Result<CopyStats> {
+    let meta = from.metadata()?;
+    let mut current = FileState::from_metadata(&meta)?;
+    let recorded = STATE.lock().unwrap().files.get(to).cloned();
+    let same_stat = recorded.as_ref().is_some_and(|state| state.same_stat(&current));
+    if trust_stat && to.exists() && same_stat {
+        return Ok(skipped(from, SkipReason::Unchanged));
+    }
+    if is_placeholder(&meta) {
+        match placeholder {
+            PlaceholderPolicy::Skip => {
+                let name = from.to_string_lossy();
+                warn!(
+                    item = name.as_ref(), action = "skip";
+                    "`{:?}` is a cloud-only placeholder, skipped", from
+                );
+                return Ok(skipped(from, SkipReason::Placeholder));
+            }
+            PlaceholderPolicy::Hydrate => debug!("downloading the placeholder `{:?}`", from),
+            PlaceholderPolicy::Error => bail!("`{:?}` is a cloud-only placeholder", from),
+        }
+    }
+
+    let unchanged = match compare.method {
+        CompareMode::Metadata => metadata_matches(&meta, to, compare.mtime_tolerance_ms),
+        CompareMode::Hash => {
+            let hash = hash_file(from)?;
+            let unchanged =
+                to.exists() && recorded.and_then(|state| state.hash).as_ref() == Some(&hash);
+            current.hash = Some(hash);
+            unchanged
+        }
+    };
+    if unchanged {
+        record(to, current);
+        return Ok(skipped(from, SkipReason::Unchanged));
+    }
+
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    // A reflink can only create a new file, which would cut the other links
+    // of the target off it or replace a symlink, so such a target is written
+    // in place.
+    let existing = to.metadata().ok();
+    let copied = if reflink && can_replace(to) {
+        reflink_over(from, to, existing.as_ref())?
+    } else {
+        Some(std::fs::copy(from, to)?)
+    };
+    let name = from.to_string_lossy();
+    match copied {
+        None => debug!(
+            item = name.as_ref(), action = "reflink";
+            "reflinked `{:?}` to `{:?}`", from, to
+        ),
+        Some(bytes) => debug!(
+            item = name.as_ref(), action = "copy";
+            "copied {} bytes from `{:?}` to `{:?}`", bytes, from, to
+        ),
+    }
+    // Keep the mtime, otherwise the metadata comparison never matches.
+    File::options()
+        .write(true)
+        .open(to)?
+        .set_modified(meta.modified()?)?;
+    record(to, current);
+    emit(Event::FileCopied {
+        from: from.to_path_buf(),
+        to: to.to_path_buf(),
+        bytes: meta.len(),
+    });
+
+    Ok(CopyStats {
+        files_copied: 1,
+        bytes_copied: meta.len(),
+        ..Default::default()
+    })
+}
+
+/// Reflink, or copy, `from` to a new file next to `to`, then rename it over
+/// `to` with the permissions of the `existing` target. `to` stays as it was if
+/// the copy fails.
+fn reflink_over(from: &Path, to: &Path, existing: Option<&Metadata>) -> Result<Option<u64>> {
+    let mut temporary = to.as_os_str().to_owned();
+    temporary.push(".gsb-tmp");
+    let temporary = PathBuf::from(temporary);
+    if temporary.exists() {
+        std::fs::remove_file(&temporary)?;
+    }
+    let copied = reflink_copy::reflink_or_copy(from, &temporary).and_then(|copied| {
+        if let Some(existing) = existing {
+            std::fs::set_permissions(&temporary, existing.permissions())?;
+        }
+        std::fs::rename(&temporary, to)?;
+        Ok(copied)
+    });
+    if copied.is_err() {
+        let _ = std::fs::remove_file(&temporary);
+    }
+    Ok(copied?)
+}
+
+/// Whether `to` can be replaced by a new file without changing anything else:
+/// it does not exist, or it is a regular file known to have no other hardlink.
+fn can_replace(to: &Path) -> bool {
+    match to.symlink_metadata() {
+        Err(e) => e.kind() == io::ErrorKind::NotFound,
+        Ok(meta) if meta.file_type().is_symlink() => false,
+        Ok(meta) => link_count(&meta) == Some(1),
+    }
+}
+
+/// The number of hardlinks of the file of `meta`, if the platform tells it.
+fn link_count(meta: &Metadata) -> Option<u64> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Some(meta.nlink())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = meta;
+        None
+    }
+}
+
+fn record(to: &Path, state: FileState) {
+    STATE.lock().unwrap().files.insert(to.to_path_buf(), state);
+}
+
+fn skipped(path: &Path, reason: SkipReason) -> CopyStats {
+    if reason == SkipReason::Unchanged {
+        debug!("`{:?}` is unchanged, skipped", path);
+    }
+    emit(Event::Skipped {
+        path: path.to_path_buf(),
+        reason,
+    });
+    CopyStats {
+        files_skipped: 1,
+        ..Default::default()
+    }
+}
+
+/// Whether the file is a cloud-only placeholder, which is downloaded when read:
+/// recalled on access or offline on Windows (OneDrive, Dropbox), dataless on
+/// macOS (iCloud, Dropbox). Linux has no such files.
+#[cfg(windows)]
+fn is_placeholder(meta: &Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+
+    const FILE_ATTRIBUTE_OFFLINE: u32 = 0x1000;
+    const FILE_ATTRIBUTE_RECALL_ON_OPEN: u32 = 0x40000;
+    const FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS: u32 = 0x400000;
+    meta.file_attributes()
+        & (FILE_ATTRIBUTE_OFFLINE
+            | FILE_ATTRIBUTE_RECALL_ON_OPEN
+            | FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS)
+        != 0
+}
+
+#[cfg(target_os = "macos")]
+fn is_placeholder(meta: &Metadata) -> bool {
+    use std::os::macos::fs::MetadataExt;
+
+    const SF_DATALESS: u32 = 0x40000000;
+    meta.st_flags() & SF_DATALESS != 0
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+fn is_placeholder(_meta: &Metadata) -> bool {
+    false
+}
+
+/// Whether `to` has the same size as `from`, and an mtime at most
+/// `tolerance_ms` apart.
+fn metadata_matches(from: &Metadata, to: &Path, tolerance_ms: u64) -> bool {
+    let Ok(to) = to.metadata() else {
+        return false;
+    };
+    let (Ok(from_time), Ok(to_time)) = (from.modified(), to.modified()) else {
+        return false;
+    };
+    let diff = from_time
+        .duration_since(to_time)
+        .unwrap_or_else(|e| e.duration());
+    from.len() == to.len() && diff <= Duration::from_millis(tolerance_ms)
+}
+
+/// The size of the buffer of [`hash_file`].
+const HASH_BUFFER_SIZE: usize = 64 * 1024;
+/// How many times `item_timeout` an item on a network filesystem gets.
+const NETWORK_TIMEOUT_FACTOR: u32 = 4;
+
+/// The memory budget of the parallel jobs in bytes, see
+/// [`crate::config::Config::memory_budget`]. Each job of [`copy_item`] holds
+/// the size of its buffer, which is never larger than the hash buffer, while
+/// running. At least one job can always run.
+static MEMORY: LazyLock<Semaphore> = LazyLock::new(|| {
+    let budget = CONFIG.read().unwrap().memory_budget;
+    Semaphore::new(budget.map_or(Semaphore::MAX_PERMITS, |budget| {
+        (budget as usize).clamp(HASH_BUFFER_SIZE, Semaphore::MAX_PERMITS)
+    }))
+});
+
+/// The threads of [`copy_item`] running at once, including the ones still
+/// finishing a copy that timed out.
+static THREADS: LazyLock<Semaphore> = LazyLock::new(|| {
+    let cores = std::thread::available_parallelism().map_or(4, |cores| cores.get());
+    Semaphore::new(cores * 2)
+});
+
+/// The hex encoded BLAKE3 hash of the file content.
+#[cfg_attr(feature = "profiling", tracing::instrument(skip_all))]
+pub fn hash_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buf = vec![0; HASH_BUFFER_SIZE];
+    loop {
+        let len = file.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        hasher.update(&buf[..len]);
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// A new empty directory for the test `name`.
+#[cfg(test)]
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gsb-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether `to`, modified `offset_ms` after `from` (before if negative),
+    /// matches `from` within `tolerance_ms`.
+    fn matches_with(name: &str, offset_ms: i64, tolerance_ms: u64) -> bool {
+        let dir = test_dir(name);
+        let (from, to) = (dir.join("from"), dir.join("to"));
+        std::fs::write(&from, "save").unwrap();
+        std::fs::write(&to, "save").unwrap();
+        let time = SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_001_500);
+        let offset = Duration::from_millis(offset_ms.unsigned_abs());
+        let to_time = if offset_ms < 0 { time - offset } else { time + offset };
+        File::options().write(true).open(&from).unwrap().set_modified(time).unwrap();
+        File::options().write(true).open(&to).unwrap().set_modified(to_time).unwrap();
+        let matches = metadata_matches(&from.metadata().unwrap(), &to, tolerance_ms);
+        std::fs::remove_dir_all(dir).unwrap();
+        matches
+    }
+
+    #[test]
+    fn test_metadata_matches_tolerance() {
+        assert!(matches_with("mtime_same", 0, 0));
+        assert!(!matches_with("mtime_off", 1, 0));
+        assert!(matches_with("mtime_at", 2000, 2000));
+        assert!(matches_with("mtime_inside", 1999, 2000));
+        assert!(!matches_with("mtime_outside", 2001, 2000));
+        assert!(matches_with("mtime_at_before", -2000, 2000));
+        assert!(!matches_with("mtime_outside_before", -2001, 2000));
+    }
+
+    #[test]
+    fn test_metadata_matches_fat() {
+        // FAT stores mtimes in steps of 2 seconds, rounding a time up to 1.999s
+        // past an even second down to it. Its resolution becomes the tolerance.
+        let fat_ms = 2000;
+        assert!(matches_with("mtime_fat", -1500, fat_ms));
+        assert!(matches_with("mtime_fat_worst", -1999, fat_ms));
+        assert!(!matches_with("mtime_fat_default", -1500, 0));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_can_replace() {
+        let dir = test_dir("can_replace");
+        let file = dir.join("file");
+        assert!(can_replace(&file));
+        std::fs::write(&file, "save").unwrap();
+        assert!(can_replace(&file));
+        let link = dir.join("link");
+        std::os::unix::fs::symlink(&file, &link).unwrap();
+        assert!(!can_replace(&link));
+        std::fs::hard_link(&file, dir.join("hardlink")).unwrap();
+        assert!(!can_replace(&file));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_metadata_matches_size() {
+        let dir = test_dir("mtime_size");
+        let (from, to) = (dir.join("from"), dir.join("to"));
+        std::fs::write(&from, "save").unwrap();
+        assert!(!metadata_matches(&from.metadata().unwrap(), &to, 1000));
+        std::fs::write(&to, "saved").unwrap();
+        let time = SystemTime::now();
+        File::options().write(true).open(&from).unwrap().set_modified(time).unwrap();
+        File::options().write(true).open(&to).unwrap().set_modified(time).unwrap();
+        assert!(!metadata_matches(&from.metadata().unwrap(), &to, 1000));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
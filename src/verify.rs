@@ -0,0 +1,122 @@
+//! Deep verification in the background of `gsb daemon`: each cycle hashes a
+//! few more files of the sync group on both sides, so bit rot and silent drift
+//! are found over time without an expensive full run.
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use log::{debug, warn};
+
+use crate::{
+    config::{Getable, CONFIG},
+    git_command::REPO_PATH,
+    metrics,
+    notification::{self, Category},
+    state::{save_state, FileState, STATE},
+    utils::hash_file,
+};
+
+/// A file in the repository and where it is on this device.
+#[derive(Clone)]
+struct Pair {
+    in_repo: PathBuf,
+    on_device: PathBuf,
+}
+
+/// Verify the next `count` files after the ones verified last time, starting
+/// over once all were. A file differing from its copy although it has not
+/// changed since it was collected is reported as drifted.
+pub async fn verify_some(count: usize) -> Result<()> {
+    let pairs = pairs();
+    if pairs.is_empty() {
+        return Ok(());
+    }
+    let cursor = STATE.lock().unwrap().verify_cursor.clone();
+    let start = cursor.map_or(0, |cursor| pairs.partition_point(|pair| pair.in_repo <= cursor));
+    let count = count.min(pairs.len());
+    let batch = pairs
+        .into_iter()
+        .cycle()
+        .skip(start)
+        .take(count)
+        .collect::<Vec<_>>();
+    let last = batch.last().map(|pair| pair.in_repo.clone());
+    let drifted = tokio::task::spawn_blocking(move || {
+        batch
+            .into_iter()
+            .filter(|pair| match drifted(pair) {
+                Ok(drifted) => drifted,
+                Err(e) => {
+                    debug!("failed to verify `{}`: {:#}", pair.in_repo.display(), e);
+                    false
+                }
+            })
+            .map(|pair| pair.on_device)
+            .collect::<Vec<_>>()
+    })
+    .await?;
+    STATE.lock().unwrap().verify_cursor = last;
+    save_state()?;
+    metrics::record_drift(drifted.len() as u64);
+    if drifted.is_empty() {
+        return Ok(());
+    }
+    let list = drifted
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>();
+    for path in list.iter() {
+        warn!("`{}` differs from its copy in the repository, but did not change", path);
+    }
+    notification::notify(
+        Category::Error,
+        &format!("gsb: {} files drifted from the repository", list.len()),
+        &list.join("\n"),
+    );
+    Ok(())
+}
+
+/// The files of the copied items of the sync group, sorted by their path in the
+/// repository.
+fn pairs() -> Vec<Pair> {
+    let config = CONFIG.read().unwrap();
+    let mut pairs = Vec::new();
+    for (item, info) in config.sync_group.0.iter() {
+        if info.is_hardlink || !config.selects(&info.profiles, &info.tags) {
+            continue;
+        }
+        let Some(on_device) = info.get_on_device() else {
+            continue;
+        };
+        let root = REPO_PATH.join(item);
+        for entry in walkdir::WalkDir::new(&root).into_iter().flatten() {
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let on_device = match entry.path().strip_prefix(&root) {
+                Ok(relative) if !relative.as_os_str().is_empty() => on_device.join(relative),
+                _ => on_device.clone(),
+            };
+            pairs.push(Pair {
+                in_repo: entry.into_path(),
+                on_device,
+            });
+        }
+    }
+    pairs.sort_by(|a, b| a.in_repo.cmp(&b.in_repo));
+    pairs
+}
+
+/// Whether the file on the device still has the size and mtime it was
+/// collected with, but not the content of its copy. A changed file is just
+/// waiting for the next collect.
+fn drifted(pair: &Pair) -> Result<bool> {
+    let Some(recorded) = STATE.lock().unwrap().files.get(&pair.in_repo).cloned() else {
+        return Ok(false);
+    };
+    let current = FileState::from_metadata(&pair.on_device.metadata()?)?;
+    if !recorded.same_stat(&current) {
+        return Ok(false);
+    }
+    Ok(hash_file(&pair.on_device)? != hash_file(&pair.in_repo)?)
+}
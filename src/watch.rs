@@ -0,0 +1,121 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{ensure, Context, Result};
+use log::{error, info, warn};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use tokio::{
+    sync::mpsc,
+    time::{sleep_until, timeout, Instant},
+};
+
+use crate::{
+    config::{Getable, CONFIG},
+    lock::lock_repo,
+    notification::{self, Category},
+    shutdown::Shutdown,
+    sync::sync_push,
+    webhook,
+};
+
+/// The paths of the sync group on this device.
+fn watched_paths() -> Vec<PathBuf> {
+    CONFIG
+        .read()
+        .unwrap()
+        .sync_group
+        .0
+        .values()
+        .filter(|info| !info.is_hardlink)
+        .filter_map(|info| info.get_on_device().cloned())
+        .collect()
+}
+
+/// Whether `path` looks like a temporary file of an editor, e.g. `.foo.swp`
+/// of vim or `.#foo` of emacs.
+fn is_temp_file(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+    name == "4913"
+        || name.starts_with(".#")
+        || name.ends_with('~')
+        || [".swp", ".swx", ".tmp"]
+            .iter()
+            .any(|suffix| name.ends_with(suffix))
+}
+
+/// Whether the event is a change worth a collect.
+fn is_change(event: &Event) -> bool {
+    !matches!(event.kind, EventKind::Access(_))
+        && !event.paths.iter().all(|path| is_temp_file(path))
+}
+
+/// Watch the files of the sync group and collect them (and push if `push`)
+/// whenever they change. Runs until a shutdown is requested, finishing a
+/// running collect first. See [`crate::config::WatchConfig`] for how the
+/// changes are batched.
+pub async fn watch(push: bool) -> Result<()> {
+    let config = CONFIG.read().unwrap().watch.clone();
+    let debounce = Duration::from_millis(config.debounce_ms);
+    let min_interval = config
+        .min_commit_interval
+        .as_deref()
+        .map(humantime::parse_duration)
+        .transpose()
+        .context("invalid `watch.min_commit_interval`")?
+        .unwrap_or_default();
+
+    let mut shutdown = Shutdown::new()?;
+    notification::enable();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        let _ = tx.send(event);
+    })?;
+    let paths = watched_paths();
+    ensure!(!paths.is_empty(), "no file of the sync group on this device");
+    for path in paths.iter() {
+        watcher.watch(path, RecursiveMode::Recursive)?;
+    }
+    info!("watching {} items", paths.len());
+
+    let mut last_collect: Option<Instant> = None;
+    loop {
+        let event = tokio::select! {
+            event = rx.recv() => event,
+            _ = shutdown.requested() => break,
+        };
+        let Some(event) = event else {
+            break;
+        };
+        match event {
+            Ok(event) if is_change(&event) => {}
+            Ok(_) => continue,
+            Err(e) => {
+                warn!("{:?}", e);
+                continue;
+            }
+        }
+        while let Ok(Some(_)) = timeout(debounce, rx.recv()).await {}
+        if let Some(last) = last_collect {
+            tokio::select! {
+                _ = sleep_until(last + min_interval) => {}
+                _ = shutdown.requested() => break,
+            }
+            // The changes made while waiting are collected now as well.
+            while rx.try_recv().is_ok() {}
+        }
+        let _lock = lock_repo(true)?;
+        let result = sync_push(push).await;
+        webhook::ping(&result);
+        if let Err(e) = result {
+            error!("{:?}", e);
+            notification::notify(Category::Error, "gsb collect failed", &format!("{:#}", e));
+        }
+        last_collect = Some(Instant::now());
+    }
+    info!("shutting down");
+    Ok(())
+}
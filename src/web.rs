@@ -0,0 +1,368 @@
+//! A minimal local web page of `gsb daemon --web`, for machines where nobody
+//! opens a terminal: the items of the last run, the last cycles, the
+//! quarantined restores, the conflicts, and buttons to run a sync, collect or restore now.
+//!
+//! There is no authentication, so it only listens on a loopback address. The
+//! requests must name that address as their `Host`, against DNS rebinding, and
+//! the buttons carry a token of the process, so other pages open in the
+//! browser cannot press them.
+
+use std::{
+    collections::{hash_map::RandomState, VecDeque},
+    hash::BuildHasher,
+    io::{Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::{LazyLock, Mutex, OnceLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{ensure, Context, Result};
+use log::{debug, info};
+use tokio::sync::Notify;
+
+use crate::{
+    conflicts::{Inbox, Side},
+    git_command::REPO_PATH,
+    quarantine::Quarantine,
+    report::LAST_RUN_NAME,
+    state::STATE_DIR,
+};
+
+/// How many cycles are shown.
+const CYCLES: usize = 10;
+/// The largest request read, the page only posts a token.
+const MAX_REQUEST: usize = 8192;
+
+static REQUESTED: Mutex<Option<Action>> = Mutex::new(None);
+static WAKE: LazyLock<Notify> = LazyLock::new(Notify::new);
+static LAST_CYCLES: Mutex<VecDeque<Cycle>> = Mutex::new(VecDeque::new());
+/// The `Host`s the requests may name: the address given and the bound one.
+static HOSTS: OnceLock<Vec<String>> = OnceLock::new();
+/// The token of the forms, new for every process.
+static TOKEN: LazyLock<String> = LazyLock::new(|| {
+    let mut hasher = blake3::Hasher::new();
+    for _ in 0..4 {
+        // Seeded by the OS for every process.
+        hasher.update(
+            &RandomState::new()
+                .hash_one(std::process::id())
+                .to_le_bytes(),
+        );
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    hasher.update(&now.as_nanos().to_le_bytes());
+    hasher.finalize().to_hex().to_string()
+});
+
+/// What a cycle of the daemon does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Sync,
+    Collect,
+    Restore,
+}
+
+impl Action {
+    fn name(self) -> &'static str {
+        match self {
+            Action::Sync => "sync",
+            Action::Collect => "collect",
+            Action::Restore => "restore",
+        }
+    }
+}
+
+struct Cycle {
+    action: Action,
+    finished: SystemTime,
+    error: Option<String>,
+}
+
+/// Remember the result of a cycle for the page.
+pub fn record_cycle<T>(action: Action, result: &Result<T>) {
+    let mut cycles = LAST_CYCLES.lock().unwrap();
+    if cycles.len() == CYCLES {
+        cycles.pop_back();
+    }
+    cycles.push_front(Cycle {
+        action,
+        finished: SystemTime::now(),
+        error: result.as_ref().err().map(|e| format!("{:#}", e)),
+    });
+}
+
+/// Wait until a button of the page is pressed, returning its action.
+pub async fn requested() -> Action {
+    loop {
+        if let Some(action) = REQUESTED.lock().unwrap().take() {
+            return action;
+        }
+        WAKE.notified().await;
+    }
+}
+
+/// Serve the page on `addr`, e.g. `127.0.0.1:7070`, in the background. Only
+/// loopback addresses are allowed.
+pub fn serve(addr: &str) -> Result<()> {
+    let resolved = addr
+        .to_socket_addrs()
+        .with_context(|| format!("invalid address `{}`", addr))?
+        .collect::<Vec<_>>();
+    ensure!(
+        !resolved.is_empty() && resolved.iter().all(|addr| addr.ip().is_loopback()),
+        "the web page has no authentication, it only listens on a loopback address like \
+         `127.0.0.1:7070`, not `{}`",
+        addr
+    );
+    let listener =
+        TcpListener::bind(addr).with_context(|| format!("failed to listen on `{}`", addr))?;
+    let bound = listener.local_addr()?;
+    let _ = HOSTS.set(vec![
+        addr.to_string(),
+        bound.to_string(),
+        format!("localhost:{}", bound.port()),
+    ]);
+    info!("serving the web page on http://{}", addr);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            if let Err(e) = handle(stream) {
+                debug!("failed to answer a web request: {:#}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Read the request up to the end of its body, or [`MAX_REQUEST`] bytes.
+fn read_request(stream: &mut TcpStream) -> Result<String> {
+    let mut request = Vec::new();
+    let mut buf = [0; 4096];
+    while request.len() < MAX_REQUEST {
+        let len = stream.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..len]);
+        let text = String::from_utf8_lossy(&request);
+        if let Some((head, body)) = text.split_once("\r\n\r\n") {
+            let length = header(head, "content-length").and_then(|len| len.parse().ok());
+            if body.len() >= length.unwrap_or(0) {
+                break;
+            }
+        }
+    }
+    Ok(String::from_utf8_lossy(&request).into_owned())
+}
+
+/// The value of the header `name` in the `head` of a request.
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+/// Whether the request comes from the page: it names the bound address as
+/// `Host` and, for a form, as `Origin`, and a form carries the [`TOKEN`].
+fn trusted(head: &str, body: Option<&str>) -> bool {
+    let hosts = HOSTS.get().map_or(&[][..], Vec::as_slice);
+    let Some(host) = header(head, "host") else {
+        return false;
+    };
+    if !hosts.iter().any(|allowed| allowed == host) {
+        return false;
+    }
+    let Some(body) = body else {
+        return true;
+    };
+    let origin_ok = header(head, "origin").is_none_or(|origin| {
+        let origin = origin.strip_prefix("http://").unwrap_or_default();
+        hosts.iter().any(|allowed| allowed == origin)
+    });
+    let token = body
+        .split('&')
+        .find_map(|field| field.trim().strip_prefix("token="));
+    origin_ok && token == Some(TOKEN.as_str())
+}
+
+fn handle(mut stream: TcpStream) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let request = read_request(&mut stream)?;
+    let (head, body) = request.split_once("\r\n\r\n").unwrap_or((&request, ""));
+    let mut line = head.lines().next().unwrap_or_default().split_whitespace();
+    let (method, path) = (line.next(), line.next());
+    let body = (method == Some("POST")).then_some(body);
+    if !trusted(head, body) {
+        stream.write_all(forbidden().as_bytes())?;
+        return Ok(());
+    }
+    let response = match (method, path) {
+        (Some("POST"), Some(path)) => {
+            let action = match path {
+                "/sync" => Some(Action::Sync),
+                "/collect" => Some(Action::Collect),
+                "/restore" => Some(Action::Restore),
+                _ => None,
+            };
+            match action {
+                Some(action) => {
+                    *REQUESTED.lock().unwrap() = Some(action);
+                    WAKE.notify_one();
+                    "HTTP/1.1 303 See Other\r\nLocation: /\r\nContent-Length: 0\r\n\
+                     Connection: close\r\n\r\n"
+                        .to_string()
+                }
+                None => not_found(),
+            }
+        }
+        (Some("GET"), Some("/")) => {
+            let body = page();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+        _ => not_found(),
+    };
+    stream.write_all(response.as_bytes())?;
+    Ok(())
+}
+
+fn forbidden() -> String {
+    "HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+}
+
+fn not_found() -> String {
+    "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn ago(time: SystemTime) -> String {
+    let secs = SystemTime::now()
+        .duration_since(time)
+        .map_or(0, |ago| ago.as_secs());
+    format!(
+        "{} ago",
+        humantime::format_duration(Duration::from_secs(secs))
+    )
+}
+
+fn page() -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>gsb</title>\
+         <style>body{font-family:sans-serif;margin:2em}td{padding:0 1em}\
+         .error{color:#b00}</style></head><body><h1>gsb</h1>",
+    );
+    for (action, label) in [
+        ("sync", "Sync"),
+        ("collect", "Collect"),
+        ("restore", "Restore"),
+    ] {
+        html.push_str(&format!(
+            "<form method=\"post\" action=\"/{}\" style=\"display:inline\">\
+             <input type=\"hidden\" name=\"token\" value=\"{}\">\
+             <button>{} now</button></form> ",
+            action,
+            TOKEN.as_str(),
+            label
+        ));
+    }
+
+    html.push_str("<h2>Last cycles</h2><table>");
+    for cycle in LAST_CYCLES.lock().unwrap().iter() {
+        let result = match cycle.error.as_deref() {
+            Some(error) => format!("<span class=\"error\">{}</span>", escape(error)),
+            None => "OK".to_string(),
+        };
+        html.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            cycle.action.name(),
+            ago(cycle.finished),
+            result
+        ));
+    }
+    html.push_str("</table>");
+
+    html.push_str("<h2>Items of the last run</h2><table>");
+    let last_run = std::fs::read_to_string(REPO_PATH.join(STATE_DIR).join(LAST_RUN_NAME))
+        .ok()
+        .and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok());
+    let items = last_run
+        .as_ref()
+        .and_then(|run| run["items"].as_array().cloned())
+        .unwrap_or_default();
+    for item in items {
+        let result = match item["error"].as_str() {
+            Some(error) => format!("<span class=\"error\">{}</span>", escape(error)),
+            None => format!(
+                "{} files copied",
+                item["files_copied"].as_u64().unwrap_or(0)
+            ),
+        };
+        html.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td></tr>",
+            escape(item["item"].as_str().unwrap_or_default()),
+            result
+        ));
+    }
+    html.push_str("</table>");
+
+    // Both are read on every render, `gsb approve` and `gsb conflicts resolve`
+    // run in other processes.
+    html.push_str("<h2>Held back restores</h2><table>");
+    for (id, entry) in Quarantine::load_or_default().entries.iter() {
+        html.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(id),
+            escape(&entry.item.to_string_lossy()),
+            escape(&entry.reason)
+        ));
+    }
+    html.push_str("</table><p>Approve them with <code>gsb approve</code>.</p>");
+
+    html.push_str("<h2>Conflicts</h2><table>");
+    for (id, entry) in Inbox::load().entries.iter() {
+        let kept = match entry.kept {
+            Side::Local => "kept the local version",
+            Side::Repo => "took the repository version",
+        };
+        html.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(id),
+            escape(&entry.path.to_string_lossy()),
+            escape(&entry.time),
+            kept
+        ));
+    }
+    html.push_str(
+        "</table><p>Resolve them with <code>gsb conflicts resolve</code>.</p></body></html>",
+    );
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("notes/a.md"), "notes/a.md");
+        assert_eq!(
+            escape("<a href=\"x\">&amp;</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;amp;&lt;/a&gt;"
+        );
+    }
+}
@@ -0,0 +1,52 @@
+//! Posting the results of the runs to a webhook and pinging a healthcheck, so
+//! headless servers can report into an existing alerting.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use log::{debug, warn};
+use serde_json::Value;
+
+use crate::config::{WebhookKind, CONFIG};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Post `event` to the configured webhook, if any, described by `summary` for
+/// the formats taking plain text. A failure is only warned about.
+pub fn send(event: &Value, summary: &str) {
+    let config = CONFIG.read().unwrap().notify.clone();
+    let Some(url) = config.url else {
+        return;
+    };
+    match post(&url, config.kind, event, summary) {
+        Ok(()) => debug!("posted the event to `{}`", url),
+        Err(e) => warn!("failed to post the event to `{}`: {}", url, e),
+    }
+}
+
+fn post(url: &str, kind: WebhookKind, event: &Value, summary: &str) -> Result<()> {
+    let request = ureq::post(url).timeout(TIMEOUT);
+    match kind {
+        WebhookKind::Json => request.send_json(event)?,
+        WebhookKind::Ntfy => request
+            .set("Title", "gsb")
+            .send_string(&format!("{}\n{}", summary, event))?,
+        WebhookKind::Slack => request.send_json(serde_json::json!({ "text": summary }))?,
+    };
+    Ok(())
+}
+
+/// Ping the configured healthcheck, if any, with the result of a run.
+pub fn ping<T>(result: &Result<T>) {
+    let Some(url) = CONFIG.read().unwrap().notify.healthcheck_url.clone() else {
+        return;
+    };
+    let url = match result {
+        Ok(_) => url,
+        Err(_) => format!("{}/fail", url.trim_end_matches('/')),
+    };
+    match ureq::get(&url).timeout(TIMEOUT).call() {
+        Ok(_) => debug!("pinged `{}`", url),
+        Err(e) => warn!("failed to ping `{}`: {}", url, e),
+    }
+}
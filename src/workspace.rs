@@ -0,0 +1,43 @@
+//! Workspace roots like `projects = "~/code"`, so one backup item can cover a
+//! file in many projects, e.g. `*/.env` in every repository under `~/code`.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::config::{BackupFile, CONFIG};
+
+/// `path` with a leading `~` replaced by the home directory.
+pub fn expand_home(path: &Path) -> PathBuf {
+    let Ok(rest) = path.strip_prefix("~") else {
+        return path.to_path_buf();
+    };
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map_or_else(|| path.to_path_buf(), |home| PathBuf::from(home).join(rest))
+}
+
+/// The root directory of the workspace `name` on this device.
+pub fn workspace_root(name: &str) -> Result<PathBuf> {
+    let config = CONFIG.read().unwrap();
+    let root = config
+        .workspaces
+        .get(name)
+        .with_context(|| format!("no workspace `{}` in the `[workspaces]` section", name))?;
+    Ok(expand_home(root))
+}
+
+/// The files matched by the glob of a workspace item, with their paths
+/// relative to the workspace root, which are kept in the repository.
+pub fn resolve(workspace: &str, info: &BackupFile) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let root = workspace_root(workspace)?;
+    let pattern = Path::new(&glob::Pattern::escape(&root.to_string_lossy()))
+        .join(&info.path_on_device);
+    let mut matches = Vec::new();
+    for path in glob::glob(&pattern.to_string_lossy())? {
+        let path = path?;
+        let relative = path.strip_prefix(&root)?.to_path_buf();
+        matches.push((path, relative));
+    }
+    Ok(matches)
+}